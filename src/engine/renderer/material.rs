@@ -33,6 +33,9 @@ mod vertex_shader {
 
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
+layout(location = 2) in vec3 color;
+layout(location = 3) in vec2 light;
+layout(location = 4) in float ao;
 
 layout(location = 0) out vec3 fragColor;
 layout(push_constant) uniform constants
@@ -44,18 +47,22 @@ layout(push_constant) uniform constants
 
 const vec3 DIRECTION_TO_LIGHT = normalize(vec3(1.0, 3.0, -1.0));
 const float AMBIENT = 0.06;
+const float LIGHT_FALLOFF = 0.8;
+// brightness of fully occluded corners
+const float AO_MIN = 0.4;
 
 void main() {
   gl_Position = PushConstants.matrix * vec4(position, 1.0);
 
   vec3 normalWorldSpace = normalize(mat3(PushConstants.matrix) * normal);
 
-  float lightIntensity = AMBIENT + max(dot(normalWorldSpace, DIRECTION_TO_LIGHT), 0);
+  // every light level is a bit darker than the one above it
+  float level = max(light.x, light.y);
+  float brightness = pow(LIGHT_FALLOFF, 15.0 * (1.0 - level));
 
-  vec3 color = vec3(0.8, 0.8, 0.8);
-  if (PushConstants.id == 1) {
-    color = vec3(0.3, 1.0, 0.3);
-  }
+  float occlusion = mix(AO_MIN, 1.0, ao);
+
+  float lightIntensity = (AMBIENT + max(dot(normalWorldSpace, DIRECTION_TO_LIGHT), 0)) * brightness * occlusion;
 
   fragColor = lightIntensity * color;
 }
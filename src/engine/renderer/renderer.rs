@@ -49,6 +49,11 @@ use crate::engine::renderer::options::Multisampling::Disable;
 pub struct Vertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
+    pub color: [f32; 3],
+    // sky and block light in 0..1
+    pub light: [f32; 2],
+    // ambient occlusion, 0 is fully occluded and 1 not occluded at all
+    pub ao: f32,
 }
 
 pub type VertexIndex = u16;
@@ -61,7 +66,7 @@ pub struct ShaderObjectData {
     pub id: u32
 }
 
-vulkano::impl_vertex!(Vertex, position, normal);
+vulkano::impl_vertex!(Vertex, position, normal, color, light, ao);
 
 pub(crate) trait Renderer {
     fn init(options: GraphicOptions, window: Arc<Window>) -> Self;
@@ -78,6 +83,8 @@ pub(crate) trait Renderer {
 
     fn create_graphic_object(&mut self, desc: GraphicObjectDesc) -> u32;
 
+    fn remove_graphic_object(&mut self, index: u32);
+
     fn move_object(&mut self, index: u32, pos: Vec3);
 
     fn translate_position(&self, position: Vec2) -> Vec2;
@@ -100,7 +107,8 @@ pub struct GraphicEngine {
     pub(crate) surface: Arc<Surface<Arc<Window>>>,
     pub(crate) materials: Arc<RefCell<dyn Materials>>,
     // because who needs more than one? TODO: or something
-    objects: Vec<RenderMesh>,
+    // removed objects leave empty slots, so ids of other objects don't change
+    objects: Vec<Option<RenderMesh>>,
     window_resized: bool,
     recreate_swapchain: bool,
     old_size: PhysicalSize<u32>,
@@ -125,10 +133,21 @@ impl Renderer for GraphicEngine {
     // TODO: reduce copies?
     fn create_graphic_object(&mut self, desc: GraphicObjectDesc) -> u32 {
         let object = GraphicObject::create(desc, self);
-        self.objects.push(object);
+        if let Some(free) = self.objects.iter().position(|object| object.is_none()) {
+            self.objects[free] = Some(object);
+            return free as u32;
+        }
+        self.objects.push(Some(object));
         return self.objects.len() as u32 - 1;
     }
 
+    fn remove_graphic_object(&mut self, index: u32) {
+        let removed = self.objects.get_mut(index as usize).and_then(|object| object.take());
+        if let Some(object) = removed {
+            self.mesh_cache.borrow_mut().remove(&object.data.id);
+        }
+    }
+
     // fn init(options: GraphicOptions, event_loop: &mut EventLoop<()>) -> GraphicEngine {
     fn init(options: GraphicOptions, window: Arc<Window>) -> Self {
         // can this even work?
@@ -352,7 +371,9 @@ impl Renderer for GraphicEngine {
     }
 
     fn move_object(&mut self, index: u32, pos: Vec3) {
-        self.objects[index as usize].set_position(pos);
+        if let Some(object) = &mut self.objects[index as usize] {
+            object.set_position(pos);
+        }
     }
 
     fn get_sync(&self) -> &GameSync {
@@ -370,13 +391,7 @@ impl GraphicEngine {
     }
 
     fn generate_command_buffers(graphic_engine: &mut GraphicEngine) {
-        if graphic_engine.objects.is_empty() {
-            println!("Nothing to render so i will spam instead");
-            if !graphic_engine.command_buffers.is_empty() {
-                graphic_engine.command_buffers = Vec::new()
-            }
-            return;
-        }
+        // command buffers are needed even without objects, streamed chunks can show up any time
         Self::create_command_buffers(graphic_engine);
     }
 
@@ -427,7 +442,7 @@ impl GraphicEngine {
         let materials = graphic_engine.materials.borrow();
         let material = materials.get(0);
         let whatever = material.borrow();
-        for object in &graphic_engine.objects {
+        for object in graphic_engine.objects.iter().flatten() {
             let material = object.material;
             commands =  whatever.draw(object, projection_view, commands)
         }
@@ -1,4 +1,28 @@
 pub mod chunk;
 pub mod block;
 pub mod world;
-pub mod chunk_generator;
\ No newline at end of file
+pub mod chunk_generator;
+pub mod mesher;
+pub mod neighbourhood;
+pub mod noise;
+pub mod terrain_generator;
+pub mod carver;
+pub mod biome;
+pub mod features;
+pub mod registry;
+pub mod block_state;
+pub mod palette;
+pub mod region;
+pub mod streaming;
+pub mod workers;
+pub mod raycast;
+pub mod light;
+pub mod fluids;
+pub mod ticks;
+pub mod lod;
+pub mod block_region;
+pub mod edit;
+pub mod schematic;
+pub mod vox;
+pub mod heightmap;
+pub mod map;
\ No newline at end of file
@@ -0,0 +1,156 @@
+use crate::engine::terrarin::noise::{FractalNoise, hash_seed, NoiseSettings};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Biome {
+    Plains,
+    Desert,
+    Mountains,
+    Ocean,
+}
+
+pub const BIOMES: [Biome; 4] = [Biome::Plains, Biome::Desert, Biome::Mountains, Biome::Ocean];
+
+#[derive(Debug, Copy, Clone)]
+pub struct BiomeProperties {
+    // point in climate space where this biome is the strongest, both in -1.0..1.0
+    pub temperature: f64,
+    pub humidity: f64,
+    pub base_height: f64,
+    pub amplitude: f64,
+    // block names from the block registry
+    pub surface_block: &'static str,
+    pub filler_block: &'static str,
+    // chance of vegetation feature per surface block
+    pub vegetation_density: f64,
+    // colour of grass tinted faces, see FaceAppearance::grass_tint
+    pub grass_color: [f32; 3],
+}
+
+impl Biome {
+    pub fn properties(self) -> BiomeProperties {
+        return match self {
+            Biome::Plains => BiomeProperties {
+                temperature: 0.0,
+                humidity: 0.0,
+                base_height: 6.0,
+                amplitude: 6.0,
+                surface_block: "grass",
+                filler_block: "dirt",
+                vegetation_density: 0.01,
+                grass_color: [0.3, 1.0, 0.3],
+            },
+            Biome::Desert => BiomeProperties {
+                temperature: 0.8,
+                humidity: -0.6,
+                base_height: 5.0,
+                amplitude: 4.0,
+                surface_block: "sand",
+                filler_block: "sand",
+                vegetation_density: 0.001,
+                grass_color: [0.75, 0.7, 0.35],
+            },
+            Biome::Mountains => BiomeProperties {
+                temperature: -0.7,
+                humidity: 0.0,
+                base_height: 24.0,
+                amplitude: 32.0,
+                surface_block: "stone",
+                filler_block: "stone",
+                vegetation_density: 0.004,
+                grass_color: [0.35, 0.7, 0.45],
+            },
+            Biome::Ocean => BiomeProperties {
+                temperature: 0.0,
+                humidity: 0.8,
+                base_height: -14.0,
+                amplitude: 5.0,
+                surface_block: "sand",
+                filler_block: "dirt",
+                vegetation_density: 0.0,
+                grass_color: [0.3, 0.8, 0.5],
+            },
+        };
+    }
+}
+
+// Height profile of a single column after blending all biomes nearby in climate space.
+#[derive(Debug, Copy, Clone)]
+pub struct BiomeSample {
+    pub biome: Biome,
+    pub base_height: f64,
+    pub amplitude: f64,
+    pub grass_color: [f32; 3],
+}
+
+// Picks biomes from temperature and humidity noise. Climate changes smoothly, and height of a column
+// is weighted by how close its climate is to each biome, so there are no cliffs at biome borders.
+pub struct BiomeSource {
+    temperature: FractalNoise,
+    humidity: FractalNoise,
+    // smaller values give sharper transitions between biomes
+    pub blend_width: f64,
+}
+
+impl BiomeSource {
+    pub fn new(seed: u64) -> BiomeSource {
+        let climate = NoiseSettings {
+            octaves: 3,
+            frequency: 1.0 / 512.0,
+            persistence: 0.5,
+            lacunarity: 2.0,
+        };
+        return BiomeSource {
+            temperature: FractalNoise::new(hash_seed(seed, &[1]), climate),
+            humidity: FractalNoise::new(hash_seed(seed, &[2]), climate),
+            blend_width: 0.06,
+        };
+    }
+
+    // temperature and humidity, both roughly in -1.0..1.0
+    pub fn climate_at(&self, x: i32, z: i32) -> (f64, f64) {
+        // raw fractal noise rarely leaves -0.4..0.4
+        let temperature = (self.temperature.get2(x as f64, z as f64) * 2.5).clamp(-1.0, 1.0);
+        let humidity = (self.humidity.get2(x as f64, z as f64) * 2.5).clamp(-1.0, 1.0);
+        return (temperature, humidity);
+    }
+
+    pub fn biome_at(&self, x: i32, z: i32) -> Biome {
+        return self.sample(x, z).biome;
+    }
+
+    pub fn sample(&self, x: i32, z: i32) -> BiomeSample {
+        let (temperature, humidity) = self.climate_at(x, z);
+        let mut total_weight = 0.0;
+        let mut base_height = 0.0;
+        let mut amplitude = 0.0;
+        let mut grass_color = [0.0; 3];
+        let mut biome = Biome::Plains;
+        let mut strongest = -1.0;
+        for candidate in BIOMES {
+            let properties = candidate.properties();
+            let dt = temperature - properties.temperature;
+            let dh = humidity - properties.humidity;
+            let weight = (-(dt * dt + dh * dh) / self.blend_width).exp();
+            if weight > strongest {
+                strongest = weight;
+                biome = candidate;
+            }
+            total_weight += weight;
+            base_height += properties.base_height * weight;
+            amplitude += properties.amplitude * weight;
+            for (channel, value) in grass_color.iter_mut().zip(properties.grass_color) {
+                *channel += value * weight as f32;
+            }
+        }
+        if total_weight <= 0.0 {
+            let properties = biome.properties();
+            return BiomeSample { biome, base_height: properties.base_height, amplitude: properties.amplitude, grass_color: properties.grass_color };
+        }
+        return BiomeSample {
+            biome,
+            base_height: base_height / total_weight,
+            amplitude: amplitude / total_weight,
+            grass_color: grass_color.map(|channel| channel / total_weight as f32),
+        };
+    }
+}
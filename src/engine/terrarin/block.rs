@@ -1,15 +1,36 @@
 use std::ops::{Add, Deref};
-use glam::{IVec3, Vec3};
+use glam::{const_ivec3, IVec3, Vec3};
 use num_traits::real::Real;
 use crate::engine::object::transform::Pos;
 use crate::engine::terrarin::chunk::{CHUNK_SIZE_EXP, CHUNK_SIZE_I, ChunkPos};
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Block {
     pub id: u16,
+    // values of state properties packed as described by the block definition, 0 is the default state
+    pub state: u16,
 }
 
-#[derive(Copy, Clone, PartialEq)]
+impl Block {
+    pub const AIR: Block = Block { id: 0, state: 0 };
+
+    pub fn new(id: u16) -> Block { Block { id, state: 0 } }
+    pub fn with_state(self, state: u16) -> Block { Block { id: self.id, state } }
+
+    pub fn is_air(self) -> bool { self.id == 0 }
+}
+
+// directions of the six block faces, also used to find face neighbours of blocks and chunks
+pub const FACE_NORMALS: [IVec3; 6] = [
+    const_ivec3!([1, 0, 0]),
+    const_ivec3!([-1, 0, 0]),
+    const_ivec3!([0, 1, 0]),
+    const_ivec3!([0, -1, 0]),
+    const_ivec3!([0, 0, 1]),
+    const_ivec3!([0, 0, -1]),
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct BlockPos(pub IVec3);
 
 impl From<IVec3> for BlockPos {
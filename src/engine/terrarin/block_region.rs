@@ -0,0 +1,331 @@
+use std::cell::Ref;
+use glam::IVec3;
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::chunk::{Chunk, ChunkPos};
+use crate::engine::terrarin::world::GameWorld;
+
+// Box of blocks between two corners, both corners are inside of it.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BlockAabb {
+    pub min: BlockPos,
+    pub max: BlockPos,
+}
+
+impl BlockAabb {
+    // corners can be given in any order
+    pub fn new(a: BlockPos, b: BlockPos) -> BlockAabb {
+        return BlockAabb {
+            min: BlockPos(a.0.min(b.0)),
+            max: BlockPos(a.0.max(b.0)),
+        };
+    }
+
+    pub fn of_chunk(pos: ChunkPos) -> BlockAabb {
+        return BlockAabb { min: pos.block_min(), max: pos.block_max() };
+    }
+
+    // blocks along each axis
+    pub fn size(&self) -> IVec3 {
+        return self.max.0 - self.min.0 + 1;
+    }
+
+    pub fn volume(&self) -> u64 {
+        let size = self.size();
+        return size.x as u64 * size.y as u64 * size.z as u64;
+    }
+
+    pub fn contains(&self, pos: BlockPos) -> bool {
+        return pos.0.cmpge(self.min.0).all() && pos.0.cmple(self.max.0).all();
+    }
+
+    pub fn intersection(&self, other: &BlockAabb) -> Option<BlockAabb> {
+        let min = self.min.0.max(other.min.0);
+        let max = self.max.0.min(other.max.0);
+        if min.cmpgt(max).any() {
+            return None;
+        }
+        return Some(BlockAabb { min: BlockPos(min), max: BlockPos(max) });
+    }
+
+    // chunks the box touches, in the same order as blocks in iter
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkPos> {
+        let chunks = BlockAabb {
+            min: BlockPos(ChunkPos::from_block(self.min).0),
+            max: BlockPos(ChunkPos::from_block(self.max).0),
+        };
+        return chunks.iter().map(|pos| ChunkPos(pos.0));
+    }
+
+    // part of the box inside of given chunk
+    pub fn chunk_slice(&self, chunk: ChunkPos) -> Option<BlockAabb> {
+        return self.intersection(&BlockAabb::of_chunk(chunk));
+    }
+
+    // x changes slowest and z fastest, the same order blocks are stored in chunks
+    pub fn iter(&self) -> AabbIter {
+        return AabbIter { min: self.min.0, max: self.max.0, next: Some(self.min.0) };
+    }
+}
+
+impl IntoIterator for BlockAabb {
+    type Item = BlockPos;
+    type IntoIter = AabbIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        return self.iter();
+    }
+}
+
+pub struct AabbIter {
+    min: IVec3,
+    max: IVec3,
+    next: Option<IVec3>,
+}
+
+impl AabbIter {
+    pub fn empty() -> AabbIter {
+        return AabbIter { min: IVec3::ZERO, max: IVec3::ZERO, next: None };
+    }
+}
+
+impl Iterator for AabbIter {
+    type Item = BlockPos;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pos = self.next?;
+        let mut next = pos;
+        next.z += 1;
+        if next.z > self.max.z {
+            next.z = self.min.z;
+            next.y += 1;
+            if next.y > self.max.y {
+                next.y = self.min.y;
+                next.x += 1;
+            }
+        }
+        self.next = if next.x > self.max.x { None } else { Some(next) };
+        return Some(BlockPos(pos));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let pos = match self.next {
+            Some(pos) => pos,
+            None => return (0, Some(0)),
+        };
+        let size = self.max - self.min + 1;
+        let offset = pos - self.min;
+        let done = (offset.x as usize * size.y as usize + offset.y as usize) * size.z as usize + offset.z as usize;
+        let left = size.x as usize * size.y as usize * size.z as usize - done;
+        return (left, Some(left));
+    }
+}
+
+impl ExactSizeIterator for AabbIter {}
+
+// Box of blocks in a world, spanning any number of chunks. Blocks of chunks that are not
+// loaded are left out.
+pub struct BlockRegion<'a> {
+    world: &'a GameWorld,
+    aabb: BlockAabb,
+}
+
+impl<'a> BlockRegion<'a> {
+    pub fn new(world: &'a GameWorld, aabb: BlockAabb) -> BlockRegion<'a> {
+        return BlockRegion { world, aabb };
+    }
+
+    pub fn aabb(&self) -> BlockAabb {
+        return self.aabb;
+    }
+
+    // Loaded chunks with the part of the region inside of them. Each chunk is borrowed only once,
+    // which is much faster than looking blocks up in the world one by one.
+    pub fn chunk_slices(&self) -> impl Iterator<Item = (Ref<'a, Chunk>, BlockAabb)> + '_ {
+        let world = self.world;
+        return self.aabb.chunks().filter_map(move |pos| {
+            let chunk = world.loaded_chunk(pos)?;
+            return Some((chunk, self.aabb.chunk_slice(pos)?));
+        });
+    }
+
+    // blocks chunk by chunk, in order of BlockAabb::iter inside of every chunk
+    pub fn iter(&self) -> RegionIter<'a> {
+        return RegionIter {
+            world: self.world,
+            aabb: self.aabb,
+            chunks: Box::new(self.aabb.chunks()),
+            current: None,
+        };
+    }
+}
+
+pub struct RegionIter<'a> {
+    world: &'a GameWorld,
+    aabb: BlockAabb,
+    chunks: Box<dyn Iterator<Item = ChunkPos>>,
+    current: Option<(Ref<'a, Chunk>, AabbIter)>,
+}
+
+impl<'a> Iterator for RegionIter<'a> {
+    type Item = (BlockPos, Block);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((chunk, positions)) = &mut self.current {
+                if let Some(pos) = positions.next() {
+                    return Some((pos, chunk.get_block(pos)));
+                }
+            }
+            let pos = self.chunks.next()?;
+            self.current = match (self.world.loaded_chunk(pos), self.aabb.chunk_slice(pos)) {
+                (Some(chunk), Some(slice)) => Some((chunk, slice.iter())),
+                _ => None,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use crate::engine::terrarin::registry::BlockRegistry;
+    use super::*;
+
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self, min: i32, max: i32) -> i32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            return min + (self.0 % (max - min + 1) as u64) as i32;
+        }
+
+        // boxes of up to 20 blocks along each axis around the origin, most of them cross chunk borders
+        fn aabb(&mut self) -> BlockAabb {
+            let a = IVec3::new(self.next(-40, 25), self.next(-40, 25), self.next(-40, 25));
+            let b = a + IVec3::new(self.next(-19, 19), self.next(-19, 19), self.next(-19, 19));
+            return BlockAabb::new(BlockPos(a), BlockPos(b));
+        }
+    }
+
+    // every block of the box with the plain triple loop
+    fn naive(aabb: &BlockAabb) -> Vec<BlockPos> {
+        let mut positions = Vec::new();
+        for x in aabb.min.x..=aabb.max.x {
+            for y in aabb.min.y..=aabb.max.y {
+                for z in aabb.min.z..=aabb.max.z {
+                    positions.push(BlockPos::new(x, y, z));
+                }
+            }
+        }
+        return positions;
+    }
+
+    #[test]
+    fn iter_matches_triple_loop() {
+        let mut random = Random(0x1234_5678_9abc_def1);
+        for _ in 0..200 {
+            let aabb = random.aabb();
+            let expected = naive(&aabb);
+            let mut iter = aabb.iter();
+            assert_eq!(iter.len(), expected.len());
+            assert_eq!(aabb.volume(), expected.len() as u64);
+            for (i, pos) in expected.iter().enumerate() {
+                assert_eq!(iter.next(), Some(*pos));
+                assert_eq!(iter.len(), expected.len() - i - 1);
+            }
+            assert_eq!(iter.next(), None);
+            assert!(expected.iter().all(|pos| aabb.contains(*pos)));
+            assert!(!aabb.contains(BlockPos(aabb.min.0 - IVec3::X)) && !aabb.contains(BlockPos(aabb.max.0 + IVec3::Z)));
+        }
+    }
+
+    #[test]
+    fn chunk_slices_cover_the_box_once() {
+        let mut random = Random(0x0fed_cba9_8765_4321);
+        for _ in 0..200 {
+            let aabb = random.aabb();
+            let expected = naive(&aabb);
+            let mut touched: Vec<ChunkPos> = Vec::new();
+            for pos in &expected {
+                if !touched.contains(&pos.chunk()) {
+                    touched.push(pos.chunk());
+                }
+            }
+            touched.sort_by_key(|pos| (pos.x, pos.y, pos.z));
+            let chunks: Vec<ChunkPos> = aabb.chunks().collect();
+            assert_eq!(chunks, touched);
+
+            let mut volume = 0;
+            for chunk in &chunks {
+                let slice = aabb.chunk_slice(*chunk).unwrap();
+                volume += slice.volume();
+                let inside: Vec<BlockPos> = expected.iter().copied().filter(|pos| pos.chunk() == *chunk).collect();
+                assert_eq!(slice.iter().collect::<Vec<_>>(), inside);
+            }
+            assert_eq!(volume, aabb.volume());
+            let outside = ChunkPos(chunks.last().unwrap().0 + IVec3::ONE);
+            assert_eq!(aabb.chunk_slice(outside), None);
+        }
+    }
+
+    #[test]
+    fn intersection_matches_triple_loop() {
+        let mut random = Random(0x5555_aaaa_3333_cccc);
+        for _ in 0..200 {
+            let a = random.aabb();
+            let b = random.aabb();
+            let expected: Vec<BlockPos> = naive(&a).into_iter().filter(|pos| b.contains(*pos)).collect();
+            match a.intersection(&b) {
+                Some(intersection) => {
+                    assert_eq!(naive(&intersection), expected);
+                    assert_eq!(b.intersection(&a), Some(intersection));
+                }
+                None => assert!(expected.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn region_iter_matches_triple_loop() {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        let blocks = ["stone", "dirt", "sand", "log", "water"].map(|name| registry.block(name));
+        let mut world = GameWorld::new(Box::new(FlatEarthGenerator::new(7, 5, &registry)), registry.clone());
+        let mut random = Random(0x7777_1111_2222_9999);
+        // some chunks the boxes touch are left unloaded
+        for x in -3..=2 {
+            for y in -3..=2 {
+                for z in -3..=2 {
+                    if random.next(0, 5) != 0 {
+                        world.chunk_at(ChunkPos::new(x, y, z));
+                    }
+                }
+            }
+        }
+        for _ in 0..2000 {
+            let pos = BlockPos::new(random.next(-48, 47), random.next(-48, 47), random.next(-48, 47));
+            world.set_block(pos, blocks[random.next(0, 4) as usize]);
+        }
+
+        for _ in 0..100 {
+            let aabb = random.aabb();
+            let mut expected = Vec::new();
+            for chunk in aabb.chunks() {
+                if !world.is_loaded(chunk) {
+                    continue;
+                }
+                for pos in naive(&aabb).into_iter().filter(|pos| pos.chunk() == chunk) {
+                    expected.push((pos, world.get_block(pos).unwrap()));
+                }
+            }
+            let region = world.region(aabb);
+            assert_eq!(region.iter().collect::<Vec<_>>(), expected);
+            let from_slices: Vec<(BlockPos, Block)> = region.chunk_slices()
+                .flat_map(|(chunk, slice)| slice.iter().map(|pos| (pos, chunk.get_block(pos))).collect::<Vec<_>>())
+                .collect();
+            assert_eq!(from_slices, expected);
+        }
+    }
+}
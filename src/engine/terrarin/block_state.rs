@@ -0,0 +1,96 @@
+use serde::Deserialize;
+
+// Property declared by a block definition, for example "axis" of a log or "level" of water.
+// In data files enum properties list their values, int properties give the highest value.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StateProperty {
+    Enum { name: String, values: Vec<String> },
+    Int { name: String, max: u16 },
+}
+
+impl StateProperty {
+    pub fn name(&self) -> &str {
+        return match self {
+            StateProperty::Enum { name, .. } => name,
+            StateProperty::Int { name, .. } => name,
+        };
+    }
+
+    pub fn value_count(&self) -> u32 {
+        return match self {
+            StateProperty::Enum { values, .. } => values.len() as u32,
+            StateProperty::Int { max, .. } => *max as u32 + 1,
+        };
+    }
+
+    // index of named value, only enum properties have names
+    pub fn value_index(&self, value_name: &str) -> Option<u16> {
+        return match self {
+            StateProperty::Enum { values, .. } => values.iter().position(|value| value == value_name).map(|i| i as u16),
+            StateProperty::Int { .. } => None,
+        };
+    }
+
+    pub fn value_name(&self, value: u16) -> Option<&str> {
+        return match self {
+            StateProperty::Enum { values, .. } => values.get(value as usize).map(|name| name.as_str()),
+            StateProperty::Int { .. } => None,
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct StateField {
+    shift: u32,
+    bits: u32,
+}
+
+// Where each property of a block lives inside the 16 bits of Block::state, every property
+// only takes as many bits as its values need.
+#[derive(Debug, Clone, Default)]
+pub struct StateLayout {
+    fields: Vec<StateField>,
+}
+
+impl StateLayout {
+    // None if properties don't fit in 16 bits or some property has no values
+    pub fn new(properties: &[StateProperty]) -> Option<StateLayout> {
+        let mut fields = Vec::with_capacity(properties.len());
+        let mut shift = 0;
+        for property in properties {
+            let count = property.value_count();
+            if count == 0 {
+                return None;
+            }
+            let bits = 32 - (count - 1).leading_zeros();
+            fields.push(StateField { shift, bits });
+            shift += bits;
+        }
+        if shift > 16 {
+            return None;
+        }
+        return Some(StateLayout { fields });
+    }
+
+    pub fn get(&self, state: u16, property: usize) -> u16 {
+        let field = self.fields[property];
+        if field.bits == 0 {
+            return 0;
+        }
+        return (state >> field.shift) & Self::mask(field.bits);
+    }
+
+    pub fn set(&self, state: u16, property: usize, value: u16) -> u16 {
+        let field = self.fields[property];
+        if field.bits == 0 {
+            return state;
+        }
+        let mask = Self::mask(field.bits) << field.shift;
+        return (state & !mask) | ((value << field.shift) & mask);
+    }
+
+    fn mask(bits: u32) -> u16 {
+        return ((1u32 << bits) - 1) as u16;
+    }
+}
@@ -0,0 +1,335 @@
+use std::f64::consts::PI;
+use glam::{DVec3, IVec3};
+use crate::engine::terrarin::biome::Biome;
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::chunk::{Chunk, CHUNK_SIZE, CHUNK_SIZE_I, ChunkPos};
+use crate::engine::terrarin::chunk_generator::ChunkGenerator;
+use crate::engine::terrarin::noise::{FractalNoise, NoiseSettings, Random};
+use crate::engine::terrarin::registry::BlockRegistry;
+
+// Removes blocks from already generated terrain. Result must only depend on chunk position
+// and carver settings, so chunks can be generated in any order.
+pub trait Carver: Send + Sync {
+    fn carve(&self, chunk: &mut Chunk);
+}
+
+// Runs base generator and then every carver on its output.
+pub struct CarvingGenerator {
+    base: Box<dyn ChunkGenerator>,
+    carvers: Vec<Box<dyn Carver>>,
+}
+
+impl CarvingGenerator {
+    pub fn new(base: Box<dyn ChunkGenerator>, carvers: Vec<Box<dyn Carver>>) -> CarvingGenerator {
+        return CarvingGenerator { base, carvers };
+    }
+}
+
+impl ChunkGenerator for CarvingGenerator {
+    fn generate_chunk(&self, pos: ChunkPos) -> Chunk {
+        let mut chunk = self.base.generate_chunk(pos);
+        for carver in &self.carvers {
+            carver.carve(&mut chunk);
+        }
+        return chunk;
+    }
+
+    fn biome_at(&self, pos: BlockPos) -> Biome {
+        return self.base.biome_at(pos);
+    }
+
+    fn grass_color(&self, pos: BlockPos) -> [f32; 3] {
+        return self.base.grass_color(pos);
+    }
+}
+
+// protected blocks can't be removed by carvers, by default these are liquids so caves don't drain seas
+fn is_carvable(block: Block, protected: &[Block]) -> bool {
+    return !block.is_air() && !protected.contains(&block);
+}
+
+#[derive(Debug, Clone)]
+pub struct NoiseCaveSettings {
+    pub seed: u64,
+    pub noise: NoiseSettings,
+    // blocks where noise is above this value are removed, higher means less caves
+    pub threshold: f64,
+    // stretches caves horizontally when above 1.0
+    pub vertical_squash: f64,
+    pub max_y: i32,
+    pub protected: Vec<Block>,
+}
+
+impl NoiseCaveSettings {
+    pub fn new(seed: u64, registry: &BlockRegistry) -> Self {
+        NoiseCaveSettings {
+            seed,
+            noise: NoiseSettings {
+                octaves: 3,
+                frequency: 1.0 / 32.0,
+                persistence: 0.5,
+                lacunarity: 2.0,
+            },
+            threshold: 0.3,
+            vertical_squash: 1.5,
+            max_y: 64,
+            protected: registry.blocks_where(|block| block.liquid),
+        }
+    }
+}
+
+// Big open caves and overhangs from 3D noise.
+pub struct NoiseCaveCarver {
+    settings: NoiseCaveSettings,
+    noise: FractalNoise,
+}
+
+impl NoiseCaveCarver {
+    pub fn new(settings: NoiseCaveSettings) -> NoiseCaveCarver {
+        return NoiseCaveCarver {
+            noise: FractalNoise::new(settings.seed, settings.noise),
+            settings,
+        };
+    }
+}
+
+impl Carver for NoiseCaveCarver {
+    fn carve(&self, chunk: &mut Chunk) {
+        let min = chunk.get_position().block_min();
+        if min.y > self.settings.max_y {
+            return;
+        }
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                let real_y = min.y + y as i32;
+                if real_y > self.settings.max_y {
+                    break;
+                }
+                for z in 0..CHUNK_SIZE {
+                    if !is_carvable(chunk.get(x, y, z), &self.settings.protected) {
+                        continue;
+                    }
+                    let value = self.noise.get3(
+                        (min.x + x as i32) as f64,
+                        real_y as f64 * self.settings.vertical_squash,
+                        (min.z + z as i32) as f64,
+                    );
+                    if value > self.settings.threshold {
+                        chunk.set(x, y, z, Block::AIR);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TunnelSettings {
+    pub seed: u64,
+    // chance for a tunnel to start in a chunk
+    pub chance: f64,
+    pub length: u32,
+    pub min_radius: f64,
+    pub max_radius: f64,
+    // values above 1.0 make tall and narrow ravines
+    pub vertical_scale: f64,
+    // how much tunnel can turn each step
+    pub curviness: f64,
+    // tunnels only start between these heights but can wander outside of them
+    pub min_y: i32,
+    pub max_y: i32,
+    pub protected: Vec<Block>,
+}
+
+impl TunnelSettings {
+    pub fn worms(seed: u64, registry: &BlockRegistry) -> Self {
+        TunnelSettings {
+            seed,
+            chance: 0.15,
+            length: 80,
+            min_radius: 1.5,
+            max_radius: 3.0,
+            vertical_scale: 1.0,
+            curviness: 0.25,
+            min_y: -64,
+            max_y: 16,
+            protected: registry.blocks_where(|block| block.liquid),
+        }
+    }
+
+    pub fn ravines(seed: u64, registry: &BlockRegistry) -> Self {
+        TunnelSettings {
+            seed,
+            chance: 0.01,
+            length: 112,
+            min_radius: 1.5,
+            max_radius: 4.0,
+            vertical_scale: 4.0,
+            curviness: 0.08,
+            min_y: -32,
+            max_y: 0,
+            protected: registry.blocks_where(|block| block.liquid),
+        }
+    }
+}
+
+// "Worm" tunnels. Every tunnel belongs to the chunk it starts in and its whole path is calculated
+// from that chunk position, so any chunk it passes through carves exactly the same shape.
+pub struct TunnelCarver {
+    settings: TunnelSettings,
+    // how far from its start a tunnel can carve blocks
+    max_distance: f64,
+    // how many chunks away a tunnel can start and still reach into the carved chunk
+    reach: i32,
+}
+
+struct TunnelStep {
+    center: DVec3,
+    radius: f64,
+}
+
+impl TunnelCarver {
+    pub fn new(settings: TunnelSettings) -> TunnelCarver {
+        let max_distance = settings.length as f64 + settings.max_radius * settings.vertical_scale.max(1.0);
+        let reach = (max_distance / CHUNK_SIZE as f64).ceil() as i32;
+        return TunnelCarver { settings, max_distance, reach };
+    }
+
+    // Start of the tunnel of given chunk and the random generator to walk its path with,
+    // None if chunk has no tunnel. Finding the start is cheap, the path is only walked when
+    // the start is close enough to the carved chunk.
+    fn tunnel_start(&self, origin: ChunkPos) -> Option<(DVec3, Random)> {
+        let settings = &self.settings;
+        let min = origin.block_min();
+        if min.y + CHUNK_SIZE_I <= settings.min_y || min.y > settings.max_y {
+            return None;
+        }
+        let mut random = Random::at(settings.seed, &[origin.x as i64, origin.y as i64, origin.z as i64]);
+        if random.next_f64() >= settings.chance {
+            return None;
+        }
+        let start = IVec3::new(
+            min.x + random.next_i32(0, CHUNK_SIZE_I),
+            min.y + random.next_i32(0, CHUNK_SIZE_I),
+            min.z + random.next_i32(0, CHUNK_SIZE_I),
+        );
+        if start.y < settings.min_y || start.y > settings.max_y {
+            return None;
+        }
+        return Some((start.as_dvec3(), random));
+    }
+
+    // whole tunnel path, every step moves one block from the previous one
+    fn tunnel_path(&self, start: DVec3, mut random: Random) -> Vec<TunnelStep> {
+        let settings = &self.settings;
+        let mut position = start;
+        let mut yaw = random.next_f64() * PI * 2.0;
+        let mut pitch = (random.next_f64() - 0.5) * 0.5;
+        let mut steps = Vec::with_capacity(settings.length as usize);
+        for step in 0..settings.length {
+            let progress = step as f64 / settings.length as f64;
+            let radius = settings.min_radius + (settings.max_radius - settings.min_radius) * (progress * PI).sin();
+            steps.push(TunnelStep { center: position, radius });
+
+            let direction = DVec3::new(yaw.cos() * pitch.cos(), pitch.sin(), yaw.sin() * pitch.cos());
+            position += direction;
+            yaw += (random.next_f64() - 0.5) * settings.curviness * 2.0;
+            pitch = pitch * 0.9 + (random.next_f64() - 0.5) * settings.curviness;
+        }
+        return steps;
+    }
+
+    fn carve_step(&self, chunk: &mut Chunk, step: &TunnelStep) {
+        let min = chunk.get_position().block_min().0;
+        let vertical_radius = step.radius * self.settings.vertical_scale;
+        let extent = DVec3::new(step.radius, vertical_radius, step.radius);
+        let from = ((step.center - extent).floor().as_ivec3() - min).max(IVec3::ZERO);
+        let to = ((step.center + extent).ceil().as_ivec3() - min).min(IVec3::splat(CHUNK_SIZE_I - 1));
+        if from.x > to.x || from.y > to.y || from.z > to.z {
+            return;
+        }
+        for x in from.x..=to.x {
+            for y in from.y..=to.y {
+                for z in from.z..=to.z {
+                    let block_center = (min + IVec3::new(x, y, z)).as_dvec3() + 0.5;
+                    let offset = (block_center - step.center) / extent;
+                    if offset.length_squared() > 1.0 {
+                        continue;
+                    }
+                    let (x, y, z) = (x as usize, y as usize, z as usize);
+                    if is_carvable(chunk.get(x, y, z), &self.settings.protected) {
+                        chunk.set(x, y, z, Block::AIR);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Carver for TunnelCarver {
+    fn carve(&self, chunk: &mut Chunk) {
+        let pos = chunk.get_position();
+        let chunk_min = pos.block_min().0.as_dvec3();
+        let chunk_max = chunk_min + CHUNK_SIZE as f64;
+        // tunnels only start in chunk layers between min_y and max_y
+        let min_y = (pos.y - self.reach).max(self.settings.min_y.div_euclid(CHUNK_SIZE_I));
+        let max_y = (pos.y + self.reach).min(self.settings.max_y.div_euclid(CHUNK_SIZE_I));
+        for x in -self.reach..=self.reach {
+            for y in min_y..=max_y {
+                for z in -self.reach..=self.reach {
+                    let origin = ChunkPos::new(pos.x + x, y, pos.z + z);
+                    // gap between the two chunks along each axis
+                    let gap = (IVec3::new(x, y - pos.y, z).abs() - 1).max(IVec3::ZERO) * CHUNK_SIZE_I;
+                    if gap.as_dvec3().length() > self.max_distance {
+                        continue;
+                    }
+                    let (start, random) = match self.tunnel_start(origin) {
+                        Some(start) => start,
+                        None => continue,
+                    };
+                    if (start - start.clamp(chunk_min, chunk_max)).length() > self.max_distance {
+                        continue;
+                    }
+                    for step in self.tunnel_path(start, random) {
+                        self.carve_step(chunk, &step);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tunnels_that_can_reach_the_chunk_are_carved() {
+        let registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        let stone = registry.block("stone");
+        for settings in [TunnelSettings::worms(7, &registry), TunnelSettings::ravines(7, &registry)] {
+            let carver = TunnelCarver::new(TunnelSettings { chance: 0.5, ..settings });
+            let mut carved = 0;
+            for pos in [ChunkPos::new(0, -1, 0), ChunkPos::new(-3, -2, 5), ChunkPos::new(4, 0, -7), ChunkPos::new(-1, -4, -1)] {
+                let mut chunk = Chunk::filled(pos, stone);
+                carver.carve(&mut chunk);
+                // every tunnel of the whole neighbourhood
+                let mut expected = Chunk::filled(pos, stone);
+                for x in -carver.reach..=carver.reach {
+                    for y in -carver.reach..=carver.reach {
+                        for z in -carver.reach..=carver.reach {
+                            if let Some((start, random)) = carver.tunnel_start(ChunkPos::new(pos.x + x, pos.y + y, pos.z + z)) {
+                                for step in carver.tunnel_path(start, random) {
+                                    carver.carve_step(&mut expected, &step);
+                                }
+                            }
+                        }
+                    }
+                }
+                assert!(chunk.iter().eq(expected.iter()));
+                carved += chunk.iter().filter(|(_, block)| block.is_air()).count();
+            }
+            assert!(carved > 0);
+        }
+    }
+}
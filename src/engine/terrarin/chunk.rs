@@ -1,40 +1,197 @@
 use std::cmp::min;
 use std::hash::{Hash, Hasher};
-use std::ops::{Add, Deref, Index, IndexMut};
+use std::ops::{Add, Deref};
 use std::process::Output;
 use glam::{IVec2, IVec3, Vec2, Vec3};
 use crate::engine::object::transform::Pos;
-use crate::engine::terrarin::block::{AddXYZi32, Block, BlockPos};
+use crate::engine::terrarin::block::{Block, BlockPos, FACE_NORMALS};
+use crate::engine::terrarin::block_region::{AabbIter, BlockAabb};
+use crate::engine::terrarin::light::{Light, LightKind, NibbleArray};
+use crate::engine::terrarin::palette::PalettedStorage;
 
 pub const CHUNK_SIZE_EXP: u32 = 4;
 pub const CHUNK_SIZE: usize = (2 as u32).pow(CHUNK_SIZE_EXP) as usize;
 pub const CHUNK_SIZE_I: i32 = CHUNK_SIZE as i32;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
 
 
 pub struct Chunk {
     position: ChunkPos,
-    blocks: [[[Block; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+    blocks: PalettedStorage,
+    // light is not saved, it's computed again when the chunk is loaded
+    sky_light: NibbleArray,
+    block_light: NibbleArray,
+    // counts block changes, so users can tell if chunk changed since they last looked at it
+    revision: u64,
+    // changed since it was loaded from storage or saved
+    dirty: bool,
 }
 
 impl Chunk {
     pub fn empty(pos: ChunkPos) -> Chunk {
-        let blocks = [[[Block { id: 0 }; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        return Self::filled(pos, Block::AIR);
+    }
+
+    pub fn filled(pos: ChunkPos, block: Block) -> Chunk {
         return Chunk {
             position: pos,
-            blocks,
+            blocks: PalettedStorage::filled(CHUNK_VOLUME, block),
+            sky_light: NibbleArray::filled(CHUNK_VOLUME, 0),
+            block_light: NibbleArray::filled(CHUNK_VOLUME, 0),
+            revision: 0,
+            dirty: false,
         };
     }
+
+    // None if storage doesn't hold exactly one chunk of blocks
+    pub fn from_storage(pos: ChunkPos, blocks: PalettedStorage) -> Option<Chunk> {
+        if blocks.len() != CHUNK_VOLUME {
+            return None;
+        }
+        return Some(Chunk {
+            position: pos,
+            blocks,
+            sky_light: NibbleArray::filled(CHUNK_VOLUME, 0),
+            block_light: NibbleArray::filled(CHUNK_VOLUME, 0),
+            revision: 0,
+            dirty: false,
+        });
+    }
+
     pub fn get_position(&self) -> ChunkPos {
         return self.position;
     }
+
+    // x, y, z are relative to the chunk, each in 0..CHUNK_SIZE
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Block {
+        return self.blocks.get(Self::index(x, y, z));
+    }
+
+    // returns block that was there before
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: Block) -> Block {
+        let index = Self::index(x, y, z);
+        let old = self.blocks.get(index);
+        if old != block {
+            self.blocks.set(index, block);
+            self.revision += 1;
+            self.dirty = true;
+        }
+        return old;
+    }
+
+    // position must be inside of this chunk
+    pub fn get_block(&self, pos: BlockPos) -> Block {
+        let local = pos.chunk_relative();
+        return self.get(local.x as usize, local.y as usize, local.z as usize);
+    }
+
+    pub fn set_block(&mut self, pos: BlockPos, block: Block) -> Block {
+        let local = pos.chunk_relative();
+        return self.set(local.x as usize, local.y as usize, local.z as usize, block);
+    }
+
+    // light changes don't count as block changes, so they don't make the chunk dirty
+    pub fn light(&self, kind: LightKind, x: usize, y: usize, z: usize) -> u8 {
+        return self.light_storage(kind).get(Self::index(x, y, z));
+    }
+
+    pub fn set_light(&mut self, kind: LightKind, x: usize, y: usize, z: usize, level: u8) {
+        let index = Self::index(x, y, z);
+        match kind {
+            LightKind::Sky => self.sky_light.set(index, level),
+            LightKind::Block => self.block_light.set(index, level),
+        }
+    }
+
+    // position must be inside of this chunk
+    pub fn get_light(&self, pos: BlockPos, kind: LightKind) -> u8 {
+        let local = pos.chunk_relative();
+        return self.light(kind, local.x as usize, local.y as usize, local.z as usize);
+    }
+
+    pub fn set_light_at(&mut self, pos: BlockPos, kind: LightKind, level: u8) {
+        let local = pos.chunk_relative();
+        self.set_light(kind, local.x as usize, local.y as usize, local.z as usize, level);
+    }
+
+    // both light levels of a block, x, y, z are relative to the chunk
+    pub fn light_levels(&self, x: usize, y: usize, z: usize) -> Light {
+        let index = Self::index(x, y, z);
+        return Light {
+            sky: self.sky_light.get(index),
+            block: self.block_light.get(index),
+        };
+    }
+
+    pub fn light_storage(&self, kind: LightKind) -> &NibbleArray {
+        return match kind {
+            LightKind::Sky => &self.sky_light,
+            LightKind::Block => &self.block_light,
+        };
+    }
+
+    pub fn revision(&self) -> u64 {
+        return self.revision;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        return self.dirty;
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    // Some if the whole chunk is made of one block, lets callers skip chunks of air or stone
+    pub fn single_block(&self) -> Option<Block> {
+        return self.blocks.single();
+    }
+
+    pub fn storage(&self) -> &PalettedStorage {
+        return &self.blocks;
+    }
+
+    // blocks with their world positions, see BlockAabb::iter for the order
+    pub fn iter(&self) -> ChunkIter<'_> {
+        return ChunkIter { chunk: self, positions: BlockAabb::of_chunk(self.position).iter() };
+    }
+
+    // only blocks of the chunk inside of the box
+    pub fn iter_in(&self, aabb: &BlockAabb) -> ChunkIter<'_> {
+        let positions = match aabb.chunk_slice(self.position) {
+            Some(slice) => slice.iter(),
+            None => AabbIter::empty(),
+        };
+        return ChunkIter { chunk: self, positions };
+    }
+
+    // Calls f with every block and its world position, in the order of iter. Blocks are bit-packed,
+    // so there is no &mut Block into the chunk, f gets a copy that is stored back if f changed it.
+    pub fn for_each_mut<F: FnMut(BlockPos, &mut Block)>(&mut self, mut f: F) {
+        for pos in BlockAabb::of_chunk(self.position).iter() {
+            let old = self.get_block(pos);
+            let mut block = old;
+            f(pos, &mut block);
+            if block != old {
+                self.set_block(pos, block);
+            }
+        }
+    }
+
+    // forgets blocks that were replaced everywhere in the chunk, so indices can use less bits
+    pub fn compact(&mut self) {
+        self.blocks.compact();
+    }
+
+    fn index(x: usize, y: usize, z: usize) -> usize {
+        debug_assert!(x < CHUNK_SIZE && y < CHUNK_SIZE && z < CHUNK_SIZE);
+        return (x * CHUNK_SIZE + y) * CHUNK_SIZE + z;
+    }
 }
 
 pub struct ChunkIntoIterator {
     chunk: Chunk,
-    min: BlockPos,
-    x: i32,
-    y: i32,
-    z: i32,
+    positions: AabbIter,
 }
 
 impl IntoIterator for Chunk {
@@ -43,11 +200,8 @@ impl IntoIterator for Chunk {
 
     fn into_iter(self) -> Self::IntoIter {
         return ChunkIntoIterator {
-            min: self.position.block_min(),
+            positions: BlockAabb::of_chunk(self.position).iter(),
             chunk: self,
-            x: 0,
-            y: 0,
-            z: 0,
         };
     }
 }
@@ -56,45 +210,58 @@ impl Iterator for ChunkIntoIterator {
     type Item = (BlockPos, Block);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.x == CHUNK_SIZE_I {
-            return None;
-        }
-        let curr = self.chunk[self.x as usize][self.y as usize][self.z as usize];
-        let pos = self.min.add(self.x, self.y, self.z);
-        if self.z < CHUNK_SIZE_I {
-            self.z += 1;
-        } else {
-            self.z = 0;
-            if self.y < CHUNK_SIZE_I {
-                self.y += 1;
-            } else {
-                self.y = 0;
-                if self.x < CHUNK_SIZE_I {
-                    self.x += 1;
-                } else {
-                    self.x = CHUNK_SIZE_I;
-                    return None;
-                }
-            }
-        }
-        Some((pos, curr))
+        let pos = self.positions.next()?;
+        return Some((pos, self.chunk.get_block(pos)));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return self.positions.size_hint();
+    }
+}
+
+impl ExactSizeIterator for ChunkIntoIterator {}
+
+pub struct ChunkIter<'a> {
+    chunk: &'a Chunk,
+    positions: AabbIter,
+}
+
+impl<'a> IntoIterator for &'a Chunk {
+    type Item = (BlockPos, Block);
+    type IntoIter = ChunkIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        return self.iter();
     }
 }
 
-impl Index<usize> for Chunk {
-    type Output = [[Block; CHUNK_SIZE]; CHUNK_SIZE];
-    fn index(&self, i: usize) -> &Self::Output {
-        &self.blocks[i]
+impl Iterator for ChunkIter<'_> {
+    type Item = (BlockPos, Block);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pos = self.positions.next()?;
+        return Some((pos, self.chunk.get_block(pos)));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return self.positions.size_hint();
     }
 }
 
-impl IndexMut<usize> for Chunk {
-    fn index_mut(&mut self, i: usize) -> &mut Self::Output {
-        &mut self.blocks[i]
+impl ExactSizeIterator for ChunkIter<'_> {}
+
+// lets regression tests compare generated chunks by their hash
+impl Hash for Chunk {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // hashes blocks and not the storage, palette order depends on the order blocks were set in
+        self.position.hash(state);
+        for i in 0..CHUNK_VOLUME {
+            self.blocks.get(i).hash(state);
+        }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ChunkPos(pub IVec3);
 
 impl From<IVec3> for ChunkPos {
@@ -130,4 +297,52 @@ impl ChunkPos {
     }
 
     pub fn from_block(pos: BlockPos) -> ChunkPos { ChunkPos(pos.0 >> CHUNK_SIZE_EXP) }
+
+    pub fn neighbours(self) -> [ChunkPos; 6] { FACE_NORMALS.map(|dir| ChunkPos(self.0 + dir)) }
+
+    // chunks touching this one by a face, an edge or a corner
+    pub fn surrounding(self) -> [ChunkPos; 26] {
+        let mut surrounding = [self; 26];
+        let mut i = 0;
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    if x != 0 || y != 0 || z != 0 {
+                        surrounding[i] = ChunkPos(self.0 + IVec3::new(x, y, z));
+                        i += 1;
+                    }
+                }
+            }
+        }
+        return surrounding;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn for_each_mut_visits_every_block_in_order() {
+        let pos = ChunkPos::new(-1, 0, -2);
+        let mut chunk = Chunk::empty(pos);
+        let mut visited = Vec::new();
+        chunk.for_each_mut(|block_pos, block| {
+            visited.push(block_pos);
+            if (block_pos.x + block_pos.y + block_pos.z) % 3 == 0 {
+                *block = Block::new(2).with_state(block_pos.y as u16);
+            }
+        });
+        assert_eq!(visited, BlockAabb::of_chunk(pos).iter().collect::<Vec<_>>());
+        assert_eq!(visited[0], BlockPos::new(-16, 0, -32));
+        for (block_pos, block) in chunk.iter() {
+            let expected = if (block_pos.x + block_pos.y + block_pos.z) % 3 == 0 { Block::new(2).with_state(block_pos.y as u16) } else { Block::AIR };
+            assert_eq!(block, expected);
+        }
+
+        // blocks that were not changed are not written
+        let revision = chunk.revision();
+        chunk.for_each_mut(|_, _| {});
+        assert_eq!(chunk.revision(), revision);
+    }
 }
@@ -1,33 +1,59 @@
+use crate::engine::terrarin::biome::Biome;
 use crate::engine::terrarin::block::{Block, BlockPos};
 use crate::engine::terrarin::chunk::{Chunk, CHUNK_SIZE, CHUNK_SIZE_I, ChunkPos};
-use crate::engine::terrarin::world::GameWorld;
+use crate::engine::terrarin::registry::BlockRegistry;
 
-pub trait ChunkGenerator {
-    fn generate_chunk(&self, world: &GameWorld, pos: ChunkPos) -> Chunk;
+// Generators run on worker threads, so chunks must only depend on their position and the
+// generator itself.
+pub trait ChunkGenerator: Send + Sync {
+    fn generate_chunk(&self, pos: ChunkPos) -> Chunk;
+
+    // generators without biomes are just plains everywhere
+    fn biome_at(&self, _pos: BlockPos) -> Biome {
+        return Biome::Plains;
+    }
+
+    // grass colour of the block column, blended between biomes where the generator blends them
+    fn grass_color(&self, pos: BlockPos) -> [f32; 3] {
+        return self.biome_at(pos).properties().grass_color;
+    }
 }
 
 pub struct FlatEarthGenerator {
     pub grass_level: i32,
     pub stone_level: i32,
+    pub grass_block: Block,
+    pub stone_block: Block,
+}
+
+impl FlatEarthGenerator {
+    pub fn new(grass_level: i32, stone_level: i32, registry: &BlockRegistry) -> FlatEarthGenerator {
+        return FlatEarthGenerator {
+            grass_level,
+            stone_level,
+            grass_block: registry.block("grass"),
+            stone_block: registry.block("stone"),
+        };
+    }
 }
 
 impl ChunkGenerator for FlatEarthGenerator {
-    fn generate_chunk(&self, _: &GameWorld, pos: ChunkPos) -> Chunk {
+    fn generate_chunk(&self, pos: ChunkPos) -> Chunk {
         let mut chunk = Chunk::empty(pos);
         let chunk_block_y = pos.block_min().y;
         for y in 0..CHUNK_SIZE {
             let real_y = chunk_block_y + y as i32;
             let material = if real_y <= self.stone_level {
-                Block { id: 2 }
+                self.stone_block
             } else if real_y <= self.grass_level {
-                Block { id: 1 }
+                self.grass_block
             } else {
-                Block { id: 0 }
+                Block::AIR
             };
-            if material.id != 0 {
+            if !material.is_air() {
                 for x in 0..CHUNK_SIZE {
                     for z in 0..CHUNK_SIZE {
-                        chunk[x][y][z] = material
+                        chunk.set(x, y, z, material);
                     }
                 }
             }
@@ -0,0 +1,489 @@
+use std::collections::VecDeque;
+use glam::IVec3;
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::block_region::BlockAabb;
+use crate::engine::terrarin::registry::BlockRegistry;
+use crate::engine::terrarin::schematic::Schematic;
+use crate::engine::terrarin::world::{BlockChanged, GameWorld};
+
+// "facing" values in the order a clockwise quarter turn around the y axis moves them
+const HORIZONTAL_FACINGS: [&str; 4] = ["north", "east", "south", "west"];
+
+// Blocks copied out of a world, positions are relative to the lowest corner of the copied box.
+#[derive(Clone)]
+pub struct Clipboard {
+    size: IVec3,
+    // x changes slowest and z fastest, like in chunks
+    blocks: Vec<Block>,
+}
+
+impl Clipboard {
+    // blocks of chunks that are not loaded are copied as air
+    pub fn copy(world: &GameWorld, aabb: BlockAabb) -> Clipboard {
+        let mut clipboard = Clipboard {
+            size: aabb.size(),
+            blocks: vec![Block::AIR; aabb.volume() as usize],
+        };
+        for (pos, block) in world.region(aabb).iter() {
+            let i = clipboard.index(pos.0 - aabb.min.0);
+            clipboard.blocks[i] = block;
+        }
+        return clipboard;
+    }
+
+    // None if there isn't a block for every position of the box
+    pub fn from_blocks(size: IVec3, blocks: Vec<Block>) -> Option<Clipboard> {
+        if size.cmplt(IVec3::ONE).any() || blocks.len() as i64 != size.x as i64 * size.y as i64 * size.z as i64 {
+            return None;
+        }
+        return Some(Clipboard { size, blocks });
+    }
+
+    pub fn size(&self) -> IVec3 {
+        return self.size;
+    }
+
+    // in the order of BlockAabb::iter
+    pub fn blocks(&self) -> &[Block] {
+        return &self.blocks;
+    }
+
+    // air outside of the clipboard
+    pub fn get(&self, pos: IVec3) -> Block {
+        if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(self.size).any() {
+            return Block::AIR;
+        }
+        return self.blocks[self.index(pos)];
+    }
+
+    // Turned clockwise around the y axis as seen from above, by given number of quarter turns.
+    // Blocks with an "axis" or "facing" state are turned with it.
+    pub fn rotated(&self, registry: &BlockRegistry, quarter_turns: i32) -> Clipboard {
+        let turns = quarter_turns.rem_euclid(4);
+        let size = if turns % 2 == 0 { self.size } else { IVec3::new(self.size.z, self.size.y, self.size.x) };
+        let mut rotated = Clipboard { size, blocks: vec![Block::AIR; self.blocks.len()] };
+        for x in 0..self.size.x {
+            for y in 0..self.size.y {
+                for z in 0..self.size.z {
+                    let pos = match turns {
+                        0 => IVec3::new(x, y, z),
+                        1 => IVec3::new(self.size.z - 1 - z, y, x),
+                        2 => IVec3::new(self.size.x - 1 - x, y, self.size.z - 1 - z),
+                        _ => IVec3::new(z, y, self.size.x - 1 - x),
+                    };
+                    let i = rotated.index(pos);
+                    rotated.blocks[i] = Self::rotate_block(registry, self.get(IVec3::new(x, y, z)), turns);
+                }
+            }
+        }
+        return rotated;
+    }
+
+    // Flipped along given axis, 0 for x, 1 for y and 2 for z. Blocks with a "facing" state along
+    // that axis are turned around, an "axis" state stays the same.
+    pub fn mirrored(&self, registry: &BlockRegistry, axis: usize) -> Clipboard {
+        let mut mirrored = self.clone();
+        for x in 0..self.size.x {
+            for y in 0..self.size.y {
+                for z in 0..self.size.z {
+                    let pos = IVec3::new(x, y, z);
+                    let mut flipped = pos;
+                    flipped[axis] = self.size[axis] - 1 - pos[axis];
+                    let i = mirrored.index(flipped);
+                    mirrored.blocks[i] = Self::mirror_block(registry, self.get(pos), axis);
+                }
+            }
+        }
+        return mirrored;
+    }
+
+    fn rotate_block(registry: &BlockRegistry, block: Block, turns: i32) -> Block {
+        let definition = registry.get(block);
+        if let Some(axis) = definition.get_state_name(block.state, "axis") {
+            let swapped = match axis {
+                "x" if turns % 2 == 1 => "z",
+                "z" if turns % 2 == 1 => "x",
+                _ => return block,
+            };
+            return registry.with_state_name(block, "axis", swapped).unwrap_or(block);
+        }
+        if let Some(facing) = definition.get_state_name(block.state, "facing") {
+            // clockwise order, up and down don't turn
+            let i = match HORIZONTAL_FACINGS.iter().position(|name| *name == facing) {
+                Some(i) => i,
+                None => return block,
+            };
+            let turned = HORIZONTAL_FACINGS[(i + turns as usize) % 4];
+            return registry.with_state_name(block, "facing", turned).unwrap_or(block);
+        }
+        return block;
+    }
+
+    fn mirror_block(registry: &BlockRegistry, block: Block, axis: usize) -> Block {
+        let flipped = match (registry.get(block).get_state_name(block.state, "facing"), axis) {
+            (Some("east"), 0) => "west",
+            (Some("west"), 0) => "east",
+            (Some("up"), 1) => "down",
+            (Some("down"), 1) => "up",
+            (Some("south"), 2) => "north",
+            (Some("north"), 2) => "south",
+            _ => return block,
+        };
+        return registry.with_state_name(block, "facing", flipped).unwrap_or(block);
+    }
+
+    fn index(&self, pos: IVec3) -> usize {
+        return ((pos.x * self.size.y + pos.y) * self.size.z + pos.z) as usize;
+    }
+}
+
+// WorldEdit style editing for building test maps. Every edit is applied with one set_blocks,
+// so light is updated and chunks are meshed once per edit and not once per block. Edits only
+// change loaded chunks and are remembered, so they can be undone and redone.
+pub struct WorldEditor {
+    undo: VecDeque<Vec<BlockChanged>>,
+    redo: Vec<Vec<BlockChanged>>,
+    // oldest edits are forgotten when there are more
+    pub history_limit: usize,
+}
+
+impl WorldEditor {
+    pub fn new(history_limit: usize) -> WorldEditor {
+        return WorldEditor {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            history_limit,
+        };
+    }
+
+    pub fn undo_count(&self) -> usize {
+        return self.undo.len();
+    }
+
+    pub fn redo_count(&self) -> usize {
+        return self.redo.len();
+    }
+
+    // all of the following edits return how many blocks changed
+
+    pub fn fill(&mut self, world: &mut GameWorld, aabb: BlockAabb, block: Block) -> usize {
+        return self.edit(world, aabb, |_, _| Some(block));
+    }
+
+    // blocks of the same type as from are replaced no matter their state
+    pub fn replace(&mut self, world: &mut GameWorld, aabb: BlockAabb, from: Block, to: Block) -> usize {
+        return self.edit(world, aabb, |_, old| if old.id == from.id { Some(to) } else { None });
+    }
+
+    // walls of the box are filled with block, everything inside of them becomes air
+    pub fn hollow(&mut self, world: &mut GameWorld, aabb: BlockAabb, block: Block) -> usize {
+        return self.edit(world, aabb, |pos, _| {
+            let wall = pos.0.cmpeq(aabb.min.0).any() || pos.0.cmpeq(aabb.max.0).any();
+            return Some(if wall { block } else { Block::AIR });
+        });
+    }
+
+    // Puts the lowest corner of the clipboard at given position. Air in the clipboard
+    // is left out unless paste_air is set.
+    pub fn paste(&mut self, world: &mut GameWorld, clipboard: &Clipboard, at: BlockPos, paste_air: bool) -> usize {
+        let aabb = BlockAabb::new(at, BlockPos(at.0 + clipboard.size() - 1));
+        return self.edit(world, aabb, |pos, _| {
+            let block = clipboard.get(pos.0 - at.0);
+            return if paste_air || !block.is_air() { Some(block) } else { None };
+        });
+    }
+
+    // puts the origin of the schematic at given position
+    pub fn paste_schematic(&mut self, world: &mut GameWorld, schematic: &Schematic, at: BlockPos, paste_air: bool) -> usize {
+        return self.paste(world, &schematic.blocks, BlockPos(at.0 - schematic.origin), paste_air);
+    }
+
+    // returns false if there is nothing to undo
+    pub fn undo(&mut self, world: &mut GameWorld) -> bool {
+        let changes = match self.undo.pop_back() {
+            Some(changes) => changes,
+            None => return false,
+        };
+        world.set_blocks(changes.iter().map(|change| (change.pos, change.old)));
+        self.redo.push(changes);
+        return true;
+    }
+
+    // returns false if there is nothing to redo
+    pub fn redo(&mut self, world: &mut GameWorld) -> bool {
+        let changes = match self.redo.pop() {
+            Some(changes) => changes,
+            None => return false,
+        };
+        world.set_blocks(changes.iter().map(|change| (change.pos, change.new)));
+        self.push_undo(changes);
+        return true;
+    }
+
+    // new block for every loaded block of the box, None keeps the block as it is
+    fn edit<F: Fn(BlockPos, Block) -> Option<Block>>(&mut self, world: &mut GameWorld, aabb: BlockAabb, new_block: F) -> usize {
+        let changes: Vec<BlockChanged> = world.region(aabb)
+            .iter()
+            .filter_map(|(pos, old)| match new_block(pos, old) {
+                Some(new) if new != old => Some(BlockChanged { pos, old, new }),
+                _ => None,
+            })
+            .collect();
+        if changes.is_empty() {
+            return 0;
+        }
+        let changed = world.set_blocks(changes.iter().map(|change| (change.pos, change.new)));
+        self.redo.clear();
+        self.push_undo(changes);
+        return changed;
+    }
+
+    fn push_undo(&mut self, changes: Vec<BlockChanged>) {
+        self.undo.push_back(changes);
+        while self.undo.len() > self.history_limit {
+            self.undo.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::engine::terrarin::chunk::ChunkPos;
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use super::*;
+
+    fn registry() -> BlockRegistry {
+        return BlockRegistry::from_json(r#"{ "blocks": [
+            { "name": "stone", "faces": { "side": { "color": [0.5, 0.5, 0.5] } } },
+            { "name": "log", "faces": { "side": { "color": [0.45, 0.3, 0.15] } },
+              "states": [{ "name": "axis", "values": ["y", "x", "z"] }] },
+            { "name": "furnace", "faces": { "side": { "color": [0.3, 0.3, 0.3] } },
+              "states": [{ "name": "facing", "values": ["north", "east", "south", "west", "up", "down"] }] }
+        ] }"#).unwrap();
+    }
+
+    // every state of the rotatable blocks once, the rest is stone and air
+    fn clipboard(registry: &BlockRegistry) -> Clipboard {
+        let log = registry.block("log");
+        let furnace = registry.block("furnace");
+        let mut blocks: Vec<Block> = ["y", "x", "z"].iter().map(|axis| registry.with_state_name(log, "axis", axis).unwrap()).collect();
+        for facing in ["north", "east", "south", "west", "up", "down"] {
+            blocks.push(registry.with_state_name(furnace, "facing", facing).unwrap());
+        }
+        blocks.extend([registry.block("stone"), Block::AIR, registry.block("stone")]);
+        return Clipboard::from_blocks(IVec3::new(3, 2, 2), blocks).unwrap();
+    }
+
+    fn facing(registry: &BlockRegistry, block: Block) -> &str {
+        return registry.get(block).get_state_name(block.state, "facing").unwrap();
+    }
+
+    #[test]
+    fn rotating_turns_facing_and_axis() {
+        let registry = registry();
+        let clipboard = clipboard(&registry);
+        let rotated = clipboard.rotated(&registry, 1);
+        assert_eq!(rotated.size(), IVec3::new(2, 2, 3));
+        for x in 0..3 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    let old = clipboard.get(IVec3::new(x, y, z));
+                    let new = rotated.get(IVec3::new(1 - z, y, x));
+                    assert_eq!(new.id, old.id);
+                    if old.id == registry.block("furnace").id {
+                        let expected = match facing(&registry, old) {
+                            "north" => "east",
+                            "east" => "south",
+                            "south" => "west",
+                            "west" => "north",
+                            vertical => vertical,
+                        };
+                        assert_eq!(facing(&registry, new), expected);
+                        // the top of the block points where the turned direction points
+                        let up = registry.get(old).up(old.state);
+                        assert_eq!(registry.get(new).up(new.state), IVec3::new(-up.z, up.y, up.x));
+                    }
+                }
+            }
+        }
+
+        let mut turned = clipboard.clone();
+        for _ in 0..4 {
+            turned = turned.rotated(&registry, 1);
+        }
+        assert_eq!(turned.size(), clipboard.size());
+        assert_eq!(turned.blocks(), clipboard.blocks());
+        assert_eq!(clipboard.rotated(&registry, -1).blocks(), clipboard.rotated(&registry, 3).blocks());
+    }
+
+    #[test]
+    fn mirroring_turns_facing_along_the_axis() {
+        let registry = registry();
+        let clipboard = clipboard(&registry);
+        for axis in 0..3 {
+            let mirrored = clipboard.mirrored(&registry, axis);
+            for pos in BlockAabb::new(BlockPos::new(0, 0, 0), BlockPos(clipboard.size() - 1)).iter() {
+                let old = clipboard.get(pos.0);
+                let mut flipped = pos.0;
+                flipped[axis] = clipboard.size()[axis] - 1 - flipped[axis];
+                let new = mirrored.get(flipped);
+                if old.id == registry.block("furnace").id {
+                    let mut up = registry.get(old).up(old.state);
+                    up[axis] = -up[axis];
+                    assert_eq!(registry.get(new).up(new.state), up);
+                } else {
+                    assert_eq!(new, old);
+                }
+            }
+
+            let back = mirrored.mirrored(&registry, axis);
+            assert_eq!(back.size(), clipboard.size());
+            assert_eq!(back.blocks(), clipboard.blocks());
+        }
+    }
+
+    // ground is at y = 7, chunks from -1 to 0 on x and z and from 0 to 1 on y are loaded
+    fn flat_world() -> GameWorld {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        let mut world = GameWorld::new(Box::new(FlatEarthGenerator::new(7, 5, &registry)), registry.clone());
+        for x in -1..=0 {
+            for y in 0..=1 {
+                for z in -1..=0 {
+                    world.chunk_at(ChunkPos::new(x, y, z));
+                }
+            }
+        }
+        world.take_dirty_meshes();
+        world.take_block_changes();
+        return world;
+    }
+
+    fn aabb(a: (i32, i32, i32), b: (i32, i32, i32)) -> BlockAabb {
+        return BlockAabb::new(BlockPos::new(a.0, a.1, a.2), BlockPos::new(b.0, b.1, b.2));
+    }
+
+    fn snapshot(world: &GameWorld) -> Vec<(BlockPos, Block)> {
+        return world.region(aabb((-16, 0, -16), (15, 31, 15))).iter().collect();
+    }
+
+    #[test]
+    fn edits_return_changed_block_counts() {
+        let mut world = flat_world();
+        let stone = world.registry().block("stone");
+        let sand = world.registry().block("sand");
+        let grass = world.registry().block("grass");
+        let dirt = world.registry().block("dirt");
+        let mut editor = WorldEditor::new(10);
+
+        assert_eq!(editor.fill(&mut world, aabb((-2, 8, -2), (1, 9, 1)), stone), 32);
+        // nothing changes the second time and nothing is remembered
+        assert_eq!(editor.fill(&mut world, aabb((-2, 8, -2), (1, 9, 1)), stone), 0);
+        assert_eq!(editor.undo_count(), 1);
+        // blocks in chunks that are not loaded are left out
+        assert_eq!(editor.fill(&mut world, aabb((14, 20, 0), (17, 20, 0)), stone), 2);
+
+        assert_eq!(editor.replace(&mut world, aabb((-3, 7, -3), (2, 9, 2)), stone, sand), 32);
+        assert_eq!(editor.replace(&mut world, aabb((-3, 7, -3), (2, 9, 2)), grass, dirt), 36);
+        assert_eq!(world.get_block(BlockPos::new(-3, 7, 2)), Some(dirt));
+        assert_eq!(world.get_block(BlockPos::new(0, 9, 0)), Some(sand));
+
+        // walls of 8 by 4 by 8 blocks around air, the inside stays air
+        assert_eq!(editor.hollow(&mut world, aabb((-4, 10, -4), (3, 13, 3)), stone), 8 * 4 * 8 - 6 * 2 * 6);
+        // filling the inside and hollowing again only clears it
+        editor.fill(&mut world, aabb((-3, 11, -3), (2, 12, 2)), sand);
+        assert_eq!(editor.hollow(&mut world, aabb((-4, 10, -4), (3, 13, 3)), stone), 6 * 2 * 6);
+
+        // 4 by 2 by 4 of sand with a stone layer above, copied on top of the walls
+        let clipboard = Clipboard::copy(&world, aabb((-2, 8, -2), (1, 10, 1)));
+        assert_eq!(editor.paste(&mut world, &clipboard, BlockPos::new(-2, 20, -2), false), 48);
+        assert_eq!(editor.paste(&mut world, &clipboard, BlockPos::new(-2, 20, -2), true), 0);
+        assert_eq!(editor.fill(&mut world, aabb((-2, 19, -2), (1, 22, 1)), sand), 16 + 16);
+        // air of the clipboard only replaces blocks when asked to
+        let air = Clipboard::copy(&world, aabb((-2, 25, -2), (1, 25, 1)));
+        assert_eq!(editor.paste(&mut world, &air, BlockPos::new(-2, 20, -2), false), 0);
+        assert_eq!(editor.paste(&mut world, &air, BlockPos::new(-2, 20, -2), true), 16);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_exact_blocks() {
+        let mut world = flat_world();
+        let stone = world.registry().block("stone");
+        let log = world.registry().block("log");
+        let log_x = world.registry().with_state_name(log, "axis", "x").unwrap();
+        let log_z = world.registry().with_state_name(log, "axis", "z").unwrap();
+        let mut editor = WorldEditor::new(10);
+        let mut states = vec![snapshot(&world)];
+        editor.fill(&mut world, aabb((-5, 6, -5), (4, 9, 4)), stone);
+        states.push(snapshot(&world));
+        editor.hollow(&mut world, aabb((-5, 6, -5), (4, 9, 4)), log_x);
+        states.push(snapshot(&world));
+        editor.replace(&mut world, aabb((-16, 0, -16), (15, 31, 15)), log, log_z);
+        states.push(snapshot(&world));
+
+        for state in states.iter().rev().skip(1) {
+            assert!(editor.undo(&mut world));
+            assert!(snapshot(&world) == *state);
+        }
+        assert!(!editor.undo(&mut world));
+        for state in states.iter().skip(1) {
+            assert!(editor.redo(&mut world));
+            assert!(snapshot(&world) == *state);
+        }
+        assert!(!editor.redo(&mut world));
+
+        // a new edit forgets what could be redone
+        editor.undo(&mut world);
+        editor.undo(&mut world);
+        assert_eq!(editor.redo_count(), 2);
+        editor.fill(&mut world, aabb((0, 20, 0), (0, 20, 0)), stone);
+        assert_eq!(editor.redo_count(), 0);
+        assert!(!editor.redo(&mut world));
+        assert!(editor.undo(&mut world));
+        assert!(snapshot(&world) == states[1]);
+    }
+
+    #[test]
+    fn oldest_edits_are_forgotten() {
+        let mut world = flat_world();
+        let stone = world.registry().block("stone");
+        let mut editor = WorldEditor::new(2);
+        editor.fill(&mut world, aabb((0, 8, 0), (0, 8, 0)), stone);
+        let first = snapshot(&world);
+        editor.fill(&mut world, aabb((1, 8, 0), (1, 8, 0)), stone);
+        editor.fill(&mut world, aabb((2, 8, 0), (2, 8, 0)), stone);
+        assert_eq!(editor.undo_count(), 2);
+        assert!(editor.undo(&mut world));
+        assert!(editor.undo(&mut world));
+        assert!(!editor.undo(&mut world));
+        assert!(snapshot(&world) == first);
+    }
+
+    #[test]
+    fn edits_mark_chunks_dirty_once() {
+        let mut world = flat_world();
+        let stone = world.registry().block("stone");
+        let mut editor = WorldEditor::new(10);
+        // corner where four chunk columns meet
+        let changed = editor.fill(&mut world, aabb((-3, 8, -3), (2, 9, 2)), stone);
+        assert_eq!(changed, 72);
+        assert_eq!(world.take_block_changes().len(), 72);
+        let mut dirty = world.take_dirty_meshes();
+        let count = dirty.len();
+        dirty.sort_by_key(|pos| (pos.x, pos.y, pos.z));
+        dirty.dedup();
+        assert_eq!(dirty.len(), count);
+        for x in -1..=0 {
+            for z in -1..=0 {
+                assert!(dirty.contains(&ChunkPos::new(x, 0, z)));
+            }
+        }
+        assert!(world.take_dirty_meshes().is_empty());
+
+        editor.undo(&mut world);
+        assert_eq!(world.take_block_changes().len(), 72);
+        let dirty = world.take_dirty_meshes();
+        assert!(dirty.contains(&ChunkPos::new(-1, 0, -1)) && dirty.contains(&ChunkPos::new(0, 0, 0)));
+        assert!(world.take_dirty_meshes().is_empty());
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use glam::IVec3;
+use crate::engine::terrarin::biome::Biome;
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::block_region::BlockAabb;
+use crate::engine::terrarin::chunk::{Chunk, CHUNK_SIZE_I};
+use crate::engine::terrarin::noise::{hash_seed, Random};
+use crate::engine::terrarin::registry::BlockRegistry;
+use crate::engine::terrarin::schematic::Schematic;
+
+// Blocks of a feature relative to its root, root is the first block above the ground.
+#[derive(Debug, Clone)]
+pub struct FeatureTemplate {
+    pub blocks: Vec<(IVec3, Block)>,
+}
+
+impl FeatureTemplate {
+    pub fn tree(trunk_height: i32, crown_radius: i32, log: Block, leaves: Block) -> FeatureTemplate {
+        let mut blocks = Vec::new();
+        for y in 0..trunk_height {
+            blocks.push((IVec3::new(0, y, 0), log));
+        }
+        let crown_center = trunk_height - 1;
+        for x in -crown_radius..=crown_radius {
+            for y in -1..=crown_radius {
+                for z in -crown_radius..=crown_radius {
+                    let offset = IVec3::new(x, y, z);
+                    if offset.x == 0 && offset.z == 0 && offset.y < 1 {
+                        continue;
+                    }
+                    if offset.x * offset.x + offset.y * offset.y + offset.z * offset.z > crown_radius * crown_radius + 1 {
+                        continue;
+                    }
+                    blocks.push((IVec3::new(x, crown_center + y, z), leaves));
+                }
+            }
+        }
+        return FeatureTemplate { blocks };
+    }
+
+    pub fn boulder(radius: i32, block: Block) -> FeatureTemplate {
+        let mut blocks = Vec::new();
+        for x in -radius..=radius {
+            // sunk one block into the ground
+            for y in -1..=radius {
+                for z in -radius..=radius {
+                    if x * x + y * y + z * z <= radius * radius {
+                        blocks.push((IVec3::new(x, y, z), block));
+                    }
+                }
+            }
+        }
+        return FeatureTemplate { blocks };
+    }
+
+    // origin of the schematic becomes the root, air of the schematic is left out
+    pub fn from_schematic(schematic: &Schematic) -> FeatureTemplate {
+        let clipboard = &schematic.blocks;
+        let blocks = clipboard.blocks()
+            .iter()
+            .zip(BlockAabb::new(BlockPos(IVec3::ZERO), BlockPos(clipboard.size() - 1)).iter())
+            .filter(|(block, _)| !block.is_air())
+            .map(|(block, pos)| (pos.0 - schematic.origin, *block))
+            .collect();
+        return FeatureTemplate { blocks };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlacementRules {
+    // how many places are tried in every chunk
+    pub attempts_per_chunk: u32,
+    // chance for each attempt to be used, multiplied by biome vegetation density when enabled
+    pub chance: f64,
+    pub use_biome_density: bool,
+    // feature is only placed on top of these blocks
+    pub allowed_ground: Vec<Block>,
+    // minimal horizontal distance between two features of the same kind rooted in the same chunk
+    pub spacing: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Feature {
+    pub name: String,
+    pub template: FeatureTemplate,
+    pub rules: PlacementRules,
+    // blocks the feature can overwrite, air is always replaceable
+    pub replaceable: Vec<Block>,
+}
+
+impl Feature {
+    pub fn tree(registry: &BlockRegistry) -> Feature {
+        return Feature {
+            name: "tree".to_string(),
+            template: FeatureTemplate::tree(5, 2, registry.block("log"), registry.block("leaves")),
+            rules: PlacementRules {
+                attempts_per_chunk: 16,
+                chance: 16.0,
+                use_biome_density: true,
+                allowed_ground: vec![registry.block("grass"), registry.block("dirt")],
+                spacing: 4,
+            },
+            replaceable: vec![registry.block("leaves")],
+        };
+    }
+
+    pub fn boulder(registry: &BlockRegistry) -> Feature {
+        let ground = vec![registry.block("grass"), registry.block("dirt"), registry.block("sand")];
+        let mut replaceable = ground.clone();
+        replaceable.push(registry.block("leaves"));
+        return Feature {
+            name: "boulder".to_string(),
+            template: FeatureTemplate::boulder(2, registry.block("stone")),
+            rules: PlacementRules {
+                attempts_per_chunk: 1,
+                chance: 0.05,
+                use_biome_density: false,
+                allowed_ground: ground,
+                spacing: 8,
+            },
+            replaceable,
+        };
+    }
+
+    pub fn from_schematic(name: &str, schematic: &Schematic, rules: PlacementRules, replaceable: Vec<Block>) -> Feature {
+        return Feature {
+            name: name.to_string(),
+            template: FeatureTemplate::from_schematic(schematic),
+            rules,
+            replaceable,
+        };
+    }
+}
+
+// Single block written by a feature. When two features want the same block the one with
+// higher priority wins, priorities only depend on seed and position so the result does not
+// depend on the order chunks were generated in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FeatureWrite {
+    pub pos: BlockPos,
+    pub block: Block,
+    pub priority: u64,
+    // index into the features of the decorator, saved with writes waiting for their chunk
+    pub(crate) feature: usize,
+}
+
+// remembers which feature wrote a block and what was there before
+#[derive(Debug, Copy, Clone)]
+pub struct FeatureOwner {
+    pub priority: u64,
+    pub original: Block,
+}
+
+pub type FeatureOwners = HashMap<BlockPos, FeatureOwner>;
+
+pub struct FeatureDecorator {
+    seed: u64,
+    features: Vec<Feature>,
+}
+
+impl FeatureDecorator {
+    pub fn new(seed: u64, features: Vec<Feature>) -> FeatureDecorator {
+        return FeatureDecorator { seed, features };
+    }
+
+    // Decides where features rooted in this chunk go. Only blocks of the chunk itself are checked,
+    // so the decision is the same no matter which neighbours exist. Ground in the top layer needs
+    // the block above it, block_above gives it for local x and z from the generated chunk above.
+    // Returned writes can point outside of the chunk.
+    pub fn decorate(&self, chunk: &Chunk, biome_at: &dyn Fn(BlockPos) -> Biome, block_above: &dyn Fn(i32, i32) -> Block) -> Vec<FeatureWrite> {
+        let pos = chunk.get_position();
+        let min = pos.block_min();
+        let mut writes = Vec::new();
+        for (feature_index, feature) in self.features.iter().enumerate() {
+            let mut random = Random::at(self.seed, &[pos.x as i64, pos.y as i64, pos.z as i64, feature_index as i64]);
+            let mut roots: Vec<IVec3> = Vec::new();
+            for attempt in 0..feature.rules.attempts_per_chunk {
+                let x = random.next_i32(0, CHUNK_SIZE_I);
+                let z = random.next_i32(0, CHUNK_SIZE_I);
+                let roll = random.next_f64();
+                let root = match Self::find_root(chunk, x, z, &feature.rules.allowed_ground, block_above) {
+                    Some(root) => root,
+                    None => continue,
+                };
+                let mut chance = feature.rules.chance;
+                if feature.rules.use_biome_density {
+                    chance *= biome_at(BlockPos(min.0 + root)).properties().vegetation_density;
+                }
+                if roll >= chance {
+                    continue;
+                }
+                let spacing = feature.rules.spacing;
+                if roots.iter().any(|other| (other.x - root.x).abs() < spacing && (other.z - root.z).abs() < spacing) {
+                    continue;
+                }
+                roots.push(root);
+
+                let priority = hash_seed(self.seed, &[pos.x as i64, pos.y as i64, pos.z as i64, feature_index as i64, attempt as i64]);
+                for (offset, block) in &feature.template.blocks {
+                    writes.push(FeatureWrite {
+                        pos: BlockPos(min.0 + root + *offset),
+                        block: *block,
+                        priority,
+                        feature: feature_index,
+                    });
+                }
+            }
+        }
+        return writes;
+    }
+
+    // Writes the block if it is replaceable or was written by a feature with lower priority.
+    // Original block is remembered, so the winner is always the highest priority write that
+    // could replace it, whatever order writes arrive in. Returns true if chunk changed.
+    pub fn apply(&self, chunk: &mut Chunk, owners: &mut FeatureOwners, write: &FeatureWrite) -> bool {
+        let original = match owners.get(&write.pos) {
+            Some(owner) if owner.priority >= write.priority => return false,
+            Some(owner) => owner.original,
+            None => chunk.get_block(write.pos),
+        };
+        // saved writes can point to features that were removed since
+        let feature = match self.features.get(write.feature) {
+            Some(feature) => feature,
+            None => return false,
+        };
+        if !original.is_air() && !feature.replaceable.contains(&original) {
+            return false;
+        }
+        owners.insert(write.pos, FeatureOwner { priority: write.priority, original });
+        chunk.set_block(write.pos, write.block);
+        return true;
+    }
+
+    // highest allowed ground block in the column with air above it, ground is inside of the chunk
+    fn find_root(chunk: &Chunk, x: i32, z: i32, allowed_ground: &[Block], block_above: &dyn Fn(i32, i32) -> Block) -> Option<IVec3> {
+        for y in (0..CHUNK_SIZE_I).rev() {
+            let ground = chunk.get(x as usize, y as usize, z as usize);
+            if ground.is_air() {
+                continue;
+            }
+            if !allowed_ground.contains(&ground) {
+                return None;
+            }
+            let above = if y == CHUNK_SIZE_I - 1 {
+                block_above(x, z)
+            } else {
+                chunk.get(x as usize, (y + 1) as usize, z as usize)
+            };
+            if above.is_air() {
+                return Some(IVec3::new(x, y + 1, z));
+            }
+        }
+        return None;
+    }
+}
@@ -0,0 +1,304 @@
+use std::sync::Arc;
+use glam::{const_ivec3, IVec3};
+use crate::engine::terrarin::block::{Block, BlockPos, FACE_NORMALS};
+use crate::engine::terrarin::registry::BlockRegistry;
+use crate::engine::terrarin::ticks::{BlockTickHandler, TickContext};
+use crate::engine::terrarin::world::GameWorld;
+
+const UP: IVec3 = const_ivec3!([0, 1, 0]);
+const DOWN: IVec3 = const_ivec3!([0, -1, 0]);
+const HORIZONTAL: [IVec3; 4] = [
+    const_ivec3!([1, 0, 0]),
+    const_ivec3!([-1, 0, 0]),
+    const_ivec3!([0, 0, 1]),
+    const_ivec3!([0, 0, -1]),
+];
+
+// Liquids keep their state in "level" and "falling" block states. Level 0 is a source, flowing
+// liquid gets a higher level with every block it flows away from its source. Falling liquid
+// is fed from above and acts like a source for the blocks around it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FluidState {
+    pub level: u16,
+    pub falling: bool,
+}
+
+impl FluidState {
+    pub const SOURCE: FluidState = FluidState { level: 0, falling: false };
+
+    pub fn is_source(self) -> bool {
+        return self == Self::SOURCE;
+    }
+
+    // level the liquid spreads sideways from
+    fn spreading_level(self) -> u16 {
+        return if self.falling { 0 } else { self.level };
+    }
+}
+
+// None if block is not a liquid
+pub fn fluid_state(registry: &BlockRegistry, block: Block) -> Option<FluidState> {
+    if !registry.get(block).liquid {
+        return None;
+    }
+    return Some(FluidState {
+        level: registry.get_state(block, "level").unwrap_or(0),
+        falling: registry.get_state(block, "falling").unwrap_or(0) != 0,
+    });
+}
+
+// liquid in given state, states the liquid doesn't have are left out
+pub fn with_fluid_state(registry: &BlockRegistry, block: Block, state: FluidState) -> Block {
+    let block = registry.with_state(block, "level", state.level).unwrap_or(block);
+    return registry.with_state(block, "falling", state.falling as u16).unwrap_or(block);
+}
+
+// how far the liquid flows from its source, liquids without levels don't flow at all
+pub fn max_level(registry: &BlockRegistry, block: Block) -> u16 {
+    return registry.get(block).max_state("level").unwrap_or(0);
+}
+
+// Height of the liquid surface inside of its block, sources and falling liquid fill the whole block.
+pub fn fluid_height(registry: &BlockRegistry, block: Block) -> f32 {
+    return match fluid_state(registry, block) {
+        Some(state) if !state.falling => 1.0 - state.level as f32 / (max_level(registry, block) + 1) as f32,
+        _ => 1.0,
+    };
+}
+
+// Flows liquids one step at a time, registered as tick handler of every liquid by register_fluids.
+// Liquid and blocks next to it are ticked when something around them changes, after the flow
+// delay of the liquid. Liquid only flows into air and never into chunks that are not loaded,
+// different liquids don't mix.
+pub struct FluidFlow;
+
+pub fn register_fluids(registry: &mut BlockRegistry) {
+    let liquids = registry.blocks_where(|definition| definition.liquid);
+    let handler: Arc<dyn BlockTickHandler> = Arc::new(FluidFlow);
+    for liquid in liquids {
+        registry.set_tick_handler(liquid, handler.clone());
+    }
+}
+
+impl BlockTickHandler for FluidFlow {
+    fn scheduled_tick(&self, ticks: &mut TickContext, pos: BlockPos) {
+        if let Some(block) = flow(ticks.world, pos) {
+            ticks.world.set_block(pos, block);
+        }
+    }
+
+    // liquid itself may flow differently now and so may the changed block, a new or changed
+    // liquid block may also flow into any of its neighbours
+    fn neighbour_changed(&self, ticks: &mut TickContext, pos: BlockPos, changed: BlockPos) {
+        let liquid = match ticks.world.get_block(pos) {
+            Some(liquid) => liquid,
+            None => return,
+        };
+        let delay = ticks.world.registry().get(liquid).flow_delay;
+        ticks.schedule(pos, liquid, delay);
+        if changed != pos {
+            ticks.schedule(changed, liquid, delay);
+            return;
+        }
+        for normal in FACE_NORMALS {
+            ticks.schedule(BlockPos(pos.0 + normal), liquid, delay);
+        }
+    }
+}
+
+// what the block should become, None if it stays as it is
+fn flow(world: &GameWorld, pos: BlockPos) -> Option<Block> {
+    let registry: &BlockRegistry = world.registry();
+    let block = world.get_block(pos)?;
+    let current = fluid_state(registry, block);
+    match current {
+        Some(state) if state.is_source() => return None,
+        Some(_) => {}
+        None if !block.is_air() => return None,
+        None => {}
+    }
+    // flowing liquid can only be replaced by the same liquid, air by any
+    let accepts = |liquid: Block| current.is_none() || liquid.id == block.id;
+
+    let desired = match world.get_block(BlockPos(pos.0 + UP)) {
+        Some(above) if registry.get(above).liquid && accepts(above) => {
+            with_fluid_state(registry, above, FluidState { level: 0, falling: true })
+        }
+        _ => fed_from_sides(world, pos, &accepts).unwrap_or(Block::AIR),
+    };
+    if desired == block {
+        return None;
+    }
+    return Some(desired);
+}
+
+// Liquid with the lowest level flowing in from the sides. Liquid only spreads sideways
+// when it can't fall down.
+fn fed_from_sides<F: Fn(Block) -> bool>(world: &GameWorld, pos: BlockPos, accepts: &F) -> Option<Block> {
+    let registry: &BlockRegistry = world.registry();
+    let mut best: Option<(Block, u16)> = None;
+    for normal in HORIZONTAL {
+        let side = BlockPos(pos.0 + normal);
+        let liquid = match world.get_block(side) {
+            Some(liquid) if accepts(liquid) => liquid,
+            _ => continue,
+        };
+        let state = match fluid_state(registry, liquid) {
+            Some(state) => state,
+            None => continue,
+        };
+        if !rests_on_ground(world, side, liquid) {
+            continue;
+        }
+        let level = state.spreading_level() + 1;
+        if level > max_level(registry, liquid) {
+            continue;
+        }
+        if best.map_or(true, |(_, best_level)| level < best_level) {
+            best = Some((liquid, level));
+        }
+    }
+    return best.map(|(liquid, level)| with_fluid_state(registry, liquid, FluidState { level, falling: false }));
+}
+
+// true if liquid at pos can't flow down, it rests on a block or on a source of itself
+fn rests_on_ground(world: &GameWorld, pos: BlockPos, liquid: Block) -> bool {
+    let registry: &BlockRegistry = world.registry();
+    return match world.get_block(BlockPos(pos.0 + DOWN)) {
+        Some(below) if below.is_air() => false,
+        Some(below) if below.id == liquid.id => fluid_state(registry, below).map_or(false, |state| state.is_source()),
+        Some(_) => true,
+        // unloaded chunks can't be flowed into, so liquid spreads as if they were solid
+        None => true,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::terrarin::block_region::BlockAabb;
+    use crate::engine::terrarin::chunk::{Chunk, ChunkPos};
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use crate::engine::terrarin::mesher::ChunkMesher;
+    use crate::engine::terrarin::ticks::{BlockTicks, TickSettings};
+    use super::*;
+
+    fn registry() -> Arc<BlockRegistry> {
+        let mut registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        register_fluids(&mut registry);
+        return Arc::new(registry);
+    }
+
+    // ground is at y = 7, chunks from -1 to 0 on every axis are loaded
+    fn flat_world() -> (GameWorld, BlockTicks) {
+        let registry = registry();
+        let mut world = GameWorld::new(Box::new(FlatEarthGenerator::new(7, 5, &registry)), registry);
+        for x in -1..=0 {
+            for y in 0..=1 {
+                for z in -1..=0 {
+                    world.chunk_at(ChunkPos::new(x, y, z));
+                }
+            }
+        }
+        let settings = TickSettings { random_tick_rate: 0, ..TickSettings::default() };
+        return (world, BlockTicks::new(settings, 1));
+    }
+
+    // game updates the way the game runs them, changes are sent to the ticks before every tick
+    fn run(world: &mut GameWorld, ticks: &mut BlockTicks, updates: u32) {
+        for _ in 0..updates {
+            for change in world.take_block_changes() {
+                ticks.block_changed(world, change.pos);
+            }
+            ticks.tick(world);
+        }
+    }
+
+    fn state(world: &GameWorld, pos: BlockPos) -> Option<FluidState> {
+        return fluid_state(world.registry(), world.get_block(pos).unwrap());
+    }
+
+    #[test]
+    fn water_spreads_on_a_floor() {
+        let (mut world, mut ticks) = flat_world();
+        let water = world.registry().block("water");
+        world.set_block(BlockPos::new(0, 8, 0), water);
+
+        // every flow step takes the flow delay of water
+        run(&mut world, &mut ticks, 36);
+        assert_eq!(state(&world, BlockPos::new(-1, 8, 0)), Some(FluidState { level: 1, falling: false }));
+        assert_eq!(state(&world, BlockPos::new(2, 8, 0)), None);
+        run(&mut world, &mut ticks, 36);
+        assert_eq!(state(&world, BlockPos::new(2, 8, 0)), Some(FluidState { level: 2, falling: false }));
+        assert_eq!(state(&world, BlockPos::new(1, 8, 2)), None);
+
+        run(&mut world, &mut ticks, 36 * 10);
+        assert_eq!(ticks.scheduled_count(), 0);
+        for x in -10..=10i32 {
+            for z in -10..=10i32 {
+                let distance = x.abs() + z.abs();
+                let expected = if distance <= 7 { Some(FluidState { level: distance as u16, falling: false }) } else { None };
+                assert_eq!(state(&world, BlockPos::new(x, 8, z)), expected, "water at {} {}", x, z);
+                assert_eq!(state(&world, BlockPos::new(x, 9, z)), None);
+            }
+        }
+    }
+
+    #[test]
+    fn water_falls_down_a_ledge() {
+        let (mut world, mut ticks) = flat_world();
+        let water = world.registry().block("water");
+        world.set_block(BlockPos::new(0, 9, 0), world.registry().block("stone"));
+        world.set_block(BlockPos::new(0, 10, 0), water);
+        run(&mut world, &mut ticks, 36 * 12);
+
+        assert_eq!(state(&world, BlockPos::new(1, 10, 0)), Some(FluidState { level: 1, falling: false }));
+        // it doesn't spread further on top as it can fall
+        assert_eq!(state(&world, BlockPos::new(2, 10, 0)), None);
+        assert_eq!(state(&world, BlockPos::new(1, 9, 0)), Some(FluidState { level: 0, falling: true }));
+        assert_eq!(state(&world, BlockPos::new(1, 8, 0)), Some(FluidState { level: 0, falling: true }));
+        // and spreads again from where it lands
+        assert_eq!(state(&world, BlockPos::new(2, 8, 0)), Some(FluidState { level: 1, falling: false }));
+        assert_eq!(state(&world, BlockPos::new(0, 8, 0)), Some(FluidState { level: 1, falling: false }));
+        assert_eq!(state(&world, BlockPos::new(4, 8, 3)), Some(FluidState { level: 6, falling: false }));
+    }
+
+    #[test]
+    fn flowing_water_drains_without_its_source() {
+        let (mut world, mut ticks) = flat_world();
+        let water = world.registry().block("water");
+        world.set_block(BlockPos::new(-3, 8, 2), water);
+        run(&mut world, &mut ticks, 36 * 10);
+        assert_eq!(state(&world, BlockPos::new(0, 8, 2)), Some(FluidState { level: 3, falling: false }));
+
+        world.set_block(BlockPos::new(-3, 8, 2), Block::AIR);
+        run(&mut world, &mut ticks, 36 * 20);
+        assert_eq!(ticks.scheduled_count(), 0);
+        for pos in world.region(BlockAabb::new(BlockPos::new(-16, 8, -16), BlockPos::new(15, 9, 15))).iter() {
+            assert!(pos.1.is_air(), "water left at {:?}", pos.0);
+        }
+    }
+
+    #[test]
+    fn lowered_water_is_meshed_at_its_height() {
+        let registry = registry();
+        let water = registry.block("water");
+        let mut chunk = Chunk::filled(ChunkPos::new(0, 0, 0), Block::AIR);
+        for level in 0..=7 {
+            chunk.set((level * 2) as usize, 3, 0, with_fluid_state(&registry, water, FluidState { level, falling: false }));
+        }
+        chunk.set(0, 3, 4, with_fluid_state(&registry, water, FluidState { level: 5, falling: true }));
+        let mesh = ChunkMesher::new(registry.clone()).build(&chunk, 1);
+
+        let top = |x: f32, z: f32| -> Vec<f32> {
+            return mesh.vertices.iter()
+                .filter(|vertex| vertex.normal == [0.0, 1.0, 0.0] && vertex.position[0] >= x && vertex.position[0] <= x + 1.0 && vertex.position[2] >= z && vertex.position[2] <= z + 1.0)
+                .map(|vertex| vertex.position[1])
+                .collect();
+        };
+        for level in 0..=7 {
+            let height = 3.0 + 1.0 - level as f32 / 8.0;
+            assert_eq!(top(level as f32 * 2.0, 0.0), vec![height; 4], "level {}", level);
+        }
+        assert_eq!(top(0.0, 4.0), vec![4.0; 4]);
+    }
+}
@@ -0,0 +1,455 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use glam::IVec2;
+use image::{DynamicImage, GenericImageView, ImageError};
+use crate::engine::terrarin::biome::Biome;
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::chunk::{Chunk, CHUNK_SIZE, ChunkPos};
+use crate::engine::terrarin::chunk_generator::ChunkGenerator;
+use crate::engine::terrarin::registry::BlockRegistry;
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Image(ImageError),
+    // image has no pixels
+    Empty,
+    // blocks_per_pixel of the settings is not a positive number
+    BlocksPerPixel(f64),
+}
+
+impl Display for HeightmapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            HeightmapError::Image(error) => write!(f, "failed to read image: {}", error),
+            HeightmapError::Empty => write!(f, "image is empty"),
+            HeightmapError::BlocksPerPixel(blocks) => write!(f, "blocks per pixel has to be above 0, got {}", blocks),
+        };
+    }
+}
+
+impl From<ImageError> for HeightmapError {
+    fn from(error: ImageError) -> HeightmapError {
+        return HeightmapError::Image(error);
+    }
+}
+
+// What the world looks like outside of the area covered by the image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutOfBounds {
+    // edge pixels go on forever
+    Clamp,
+    // image is tiled
+    Repeat,
+    // image is tiled with every other tile flipped, so tiles meet without seams
+    Mirror,
+    // flat terrain of given height with default surface
+    Height(i32),
+    // nothing but air, not even water
+    Void,
+}
+
+// Surface of all columns painted with one colour of the surface map.
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceMapEntry {
+    pub biome: Biome,
+    pub surface_block: Block,
+    pub filler_block: Block,
+}
+
+impl SurfaceMapEntry {
+    // surface and filler of the biome
+    pub fn biome(biome: Biome, registry: &BlockRegistry) -> SurfaceMapEntry {
+        let properties = biome.properties();
+        return SurfaceMapEntry {
+            biome,
+            surface_block: registry.block(properties.surface_block),
+            filler_block: registry.block(properties.filler_block),
+        };
+    }
+
+    // given blocks in plains
+    pub fn material(surface_block: Block, filler_block: Block) -> SurfaceMapEntry {
+        return SurfaceMapEntry { biome: Biome::Plains, surface_block, filler_block };
+    }
+}
+
+// Colour coded image of biomes or materials, stretched over the same area as the heightmap.
+// Every pixel takes the entry of the closest colour, so blurred or antialiased edges still work.
+pub struct SurfaceMap {
+    width: u32,
+    height: u32,
+    // index of the entry of every pixel, row by row
+    pixels: Vec<u8>,
+    entries: Vec<SurfaceMapEntry>,
+}
+
+impl SurfaceMap {
+    // at most 256 colours are used, colours are RGB
+    pub fn new(image: &DynamicImage, colors: &[([u8; 3], SurfaceMapEntry)]) -> Result<SurfaceMap, HeightmapError> {
+        if image.width() == 0 || image.height() == 0 {
+            return Err(HeightmapError::Empty);
+        }
+        let colors = &colors[..colors.len().min(256)];
+        let image = image.to_rgb8();
+        let pixels = image.pixels()
+            .map(|pixel| {
+                let distance = |color: &[u8; 3]| (0..3).map(|i| (color[i] as i32 - pixel[i] as i32).pow(2)).sum::<i32>();
+                return (0..colors.len()).min_by_key(|i| distance(&colors[*i].0)).unwrap_or(0) as u8;
+            })
+            .collect();
+        return Ok(SurfaceMap {
+            width: image.width(),
+            height: image.height(),
+            pixels,
+            entries: colors.iter().map(|(_, entry)| *entry).collect(),
+        });
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, colors: &[([u8; 3], SurfaceMapEntry)]) -> Result<SurfaceMap, HeightmapError> {
+        return Self::new(&image::open(path)?, colors);
+    }
+
+    // u and v go from 0 to 1 over the image, None if there are no colours
+    fn get(&self, u: f64, v: f64) -> Option<SurfaceMapEntry> {
+        let x = ((u * self.width as f64) as u32).min(self.width - 1);
+        let y = ((v * self.height as f64) as u32).min(self.height - 1);
+        return self.entries.get(self.pixels[(y * self.width + x) as usize] as usize).copied();
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HeightmapSettings {
+    // world x and z of the top left corner of the image, image goes towards +x and +z
+    pub origin: IVec2,
+    // how many blocks a pixel of the heightmap covers in both directions, can be less than 1
+    pub blocks_per_pixel: f64,
+    // height of black and white pixels, shades in between are spread evenly
+    pub min_height: i32,
+    pub max_height: i32,
+    pub out_of_bounds: OutOfBounds,
+    // air below this level is filled with water
+    pub sea_level: i32,
+    // how many filler blocks are between surface and stone
+    pub filler_depth: i32,
+    // surface where there is no surface map
+    pub surface_block: Block,
+    pub filler_block: Block,
+    pub stone_block: Block,
+    pub water_block: Block,
+}
+
+impl HeightmapSettings {
+    pub fn new(registry: &BlockRegistry) -> Self {
+        return HeightmapSettings {
+            origin: IVec2::ZERO,
+            blocks_per_pixel: 1.0,
+            min_height: -8,
+            max_height: 56,
+            out_of_bounds: OutOfBounds::Clamp,
+            sea_level: 0,
+            filler_depth: 3,
+            surface_block: registry.block("grass"),
+            filler_block: registry.block("dirt"),
+            stone_block: registry.block("stone"),
+            water_block: registry.block("water"),
+        };
+    }
+}
+
+// Terrain from a grayscale image, so maps can be painted in an image editor. Heights between pixels
+// are interpolated, so slopes stay smooth when a pixel covers many blocks. 16 bit images are read
+// with full precision.
+pub struct HeightmapGenerator {
+    settings: HeightmapSettings,
+    width: u32,
+    height: u32,
+    // 0 for black and 1 for white, row by row
+    values: Vec<f64>,
+    surface_map: Option<SurfaceMap>,
+}
+
+// what a single column of blocks is made of
+struct Column {
+    height: i32,
+    surface: SurfaceMapEntry,
+}
+
+impl HeightmapGenerator {
+    pub fn new(heightmap: &DynamicImage, surface_map: Option<SurfaceMap>, settings: HeightmapSettings) -> Result<HeightmapGenerator, HeightmapError> {
+        if heightmap.width() == 0 || heightmap.height() == 0 {
+            return Err(HeightmapError::Empty);
+        }
+        if !(settings.blocks_per_pixel > 0.0 && settings.blocks_per_pixel.is_finite()) {
+            return Err(HeightmapError::BlocksPerPixel(settings.blocks_per_pixel));
+        }
+        let image = heightmap.to_luma16();
+        return Ok(HeightmapGenerator {
+            settings,
+            width: image.width(),
+            height: image.height(),
+            values: image.pixels().map(|pixel| pixel[0] as f64 / u16::MAX as f64).collect(),
+            surface_map,
+        });
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, surface_map: Option<SurfaceMap>, settings: HeightmapSettings) -> Result<HeightmapGenerator, HeightmapError> {
+        return Self::new(&image::open(path)?, surface_map, settings);
+    }
+
+    pub fn settings(&self) -> &HeightmapSettings {
+        return &self.settings;
+    }
+
+    // y of the highest terrain block in given column, water not included, None outside of the image with void around it
+    pub fn height_at(&self, x: i32, z: i32) -> Option<i32> {
+        return self.column_at(x, z).map(|column| column.height);
+    }
+
+    fn column_at(&self, x: i32, z: i32) -> Option<Column> {
+        let settings = &self.settings;
+        let default_surface = SurfaceMapEntry {
+            biome: Biome::Plains,
+            surface_block: settings.surface_block,
+            filler_block: settings.filler_block,
+        };
+        // position in pixels, pixel centres are at .5 like block centres
+        let px = (x as f64 + 0.5 - settings.origin.x as f64) / settings.blocks_per_pixel;
+        let py = (z as f64 + 0.5 - settings.origin.y as f64) / settings.blocks_per_pixel;
+        let inside = px >= 0.0 && py >= 0.0 && px < self.width as f64 && py < self.height as f64;
+        if !inside {
+            match settings.out_of_bounds {
+                OutOfBounds::Height(height) => return Some(Column { height, surface: default_surface }),
+                OutOfBounds::Void => return None,
+                _ => {}
+            }
+        }
+        let value = self.interpolate(px - 0.5, py - 0.5);
+        let height = settings.min_height as f64 + value * (settings.max_height - settings.min_height) as f64;
+        let surface = match &self.surface_map {
+            Some(map) => {
+                let u = (self.wrap(px.floor() as i64, self.width) as f64 + 0.5) / self.width as f64;
+                let v = (self.wrap(py.floor() as i64, self.height) as f64 + 0.5) / self.height as f64;
+                map.get(u, v).unwrap_or(default_surface)
+            }
+            None => default_surface,
+        };
+        return Some(Column { height: height.round() as i32, surface });
+    }
+
+    // bilinear interpolation between the four pixels around the point
+    fn interpolate(&self, px: f64, py: f64) -> f64 {
+        let (x0, y0) = (px.floor(), py.floor());
+        let (tx, ty) = (px - x0, py - y0);
+        let pixel = |dx: i64, dy: i64| {
+            let x = self.wrap(x0 as i64 + dx, self.width);
+            let y = self.wrap(y0 as i64 + dy, self.height);
+            return self.values[(y * self.width + x) as usize];
+        };
+        let top = pixel(0, 0) * (1.0 - tx) + pixel(1, 0) * tx;
+        let bottom = pixel(0, 1) * (1.0 - tx) + pixel(1, 1) * tx;
+        return top * (1.0 - ty) + bottom * ty;
+    }
+
+    // pixel index inside of the image, modes without wrapping clamp so edges blend only with themselves
+    fn wrap(&self, i: i64, size: u32) -> u32 {
+        let size = size as i64;
+        return match self.settings.out_of_bounds {
+            OutOfBounds::Repeat => i.rem_euclid(size),
+            OutOfBounds::Mirror => {
+                let i = i.rem_euclid(size * 2);
+                if i < size { i } else { size * 2 - 1 - i }
+            }
+            _ => i.clamp(0, size - 1),
+        } as u32;
+    }
+
+    fn block_at(&self, column: &Column, y: i32) -> Block {
+        let settings = &self.settings;
+        let height = column.height;
+        if y > height {
+            return if y <= settings.sea_level { settings.water_block } else { Block::AIR };
+        }
+        if y <= height - settings.filler_depth {
+            return settings.stone_block;
+        }
+        // no grass under water
+        if y == height && height >= settings.sea_level {
+            return column.surface.surface_block;
+        }
+        return column.surface.filler_block;
+    }
+}
+
+impl ChunkGenerator for HeightmapGenerator {
+    fn generate_chunk(&self, pos: ChunkPos) -> Chunk {
+        let mut chunk = Chunk::empty(pos);
+        let min = pos.block_min();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column = match self.column_at(min.x + x as i32, min.z + z as i32) {
+                    Some(column) => column,
+                    None => continue,
+                };
+                for y in 0..CHUNK_SIZE {
+                    let block = self.block_at(&column, min.y + y as i32);
+                    if !block.is_air() {
+                        chunk.set(x, y, z, block);
+                    }
+                }
+            }
+        }
+        return chunk;
+    }
+
+    fn biome_at(&self, pos: BlockPos) -> Biome {
+        return self.column_at(pos.x, pos.z).map_or(Biome::Plains, |column| column.surface.biome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, RgbImage};
+    use super::*;
+
+    fn registry() -> BlockRegistry {
+        return BlockRegistry::load("resources/blocks.json").unwrap();
+    }
+
+    // heightmap of a single row of pixels, black is 0 and white 255 blocks high
+    fn row(values: &[u8], settings: HeightmapSettings) -> HeightmapGenerator {
+        let image = GrayImage::from_raw(values.len() as u32, 1, values.to_vec()).unwrap();
+        let settings = HeightmapSettings { min_height: 0, max_height: 255, ..settings };
+        return HeightmapGenerator::new(&DynamicImage::ImageLuma8(image), None, settings).unwrap();
+    }
+
+    fn heights(generator: &HeightmapGenerator, xs: std::ops::Range<i32>) -> Vec<Option<i32>> {
+        return xs.map(|x| generator.height_at(x, 0)).collect();
+    }
+
+    #[test]
+    fn empty_images_are_rejected() {
+        let registry = registry();
+        let settings = HeightmapSettings::new(&registry);
+        let empty = DynamicImage::new_luma8(0, 0);
+        assert!(matches!(HeightmapGenerator::new(&empty, None, settings), Err(HeightmapError::Empty)));
+        assert!(matches!(HeightmapGenerator::new(&DynamicImage::new_luma8(4, 0), None, settings), Err(HeightmapError::Empty)));
+        let colors = [([0, 0, 0], SurfaceMapEntry::biome(Biome::Desert, &registry))];
+        assert!(matches!(SurfaceMap::new(&DynamicImage::new_rgb8(0, 3), &colors), Err(HeightmapError::Empty)));
+    }
+
+    #[test]
+    fn single_pixel_covers_everything_when_clamped() {
+        let registry = registry();
+        let mut settings = HeightmapSettings::new(&registry);
+        settings.min_height = 0;
+        settings.max_height = 10;
+        let generator = HeightmapGenerator::new(&DynamicImage::new_luma8(1, 1), None, settings).unwrap();
+        assert_eq!(generator.height_at(-1000, 1000), Some(0));
+        assert_eq!(generator.height_at(0, 0), Some(0));
+    }
+
+    #[test]
+    fn pixels_are_stretched_and_interpolated() {
+        let settings = HeightmapSettings::new(&registry());
+        let generator = row(&[0, 255], HeightmapSettings { blocks_per_pixel: 4.0, ..settings });
+        // pixel centres are at x 2 and 6, heights in between are blended
+        let expected = [0, 0, 32, 96, 159, 223, 255, 255, 255].map(Some);
+        assert_eq!(heights(&generator, 0..9), expected);
+
+        let generator = row(&[0, 255], HeightmapSettings { blocks_per_pixel: 4.0, origin: IVec2::new(-4, 3), ..settings });
+        assert_eq!(heights(&generator, -4..5), expected);
+        assert_eq!(generator.height_at(1, -100), Some(223));
+
+        // both pixels in one block
+        let generator = row(&[0, 255], HeightmapSettings { blocks_per_pixel: 0.5, ..settings });
+        assert_eq!(heights(&generator, -1..2), vec![Some(0), Some(128), Some(255)]);
+    }
+
+    #[test]
+    fn black_and_white_are_min_and_max_height() {
+        let registry = registry();
+        let image = image::ImageBuffer::from_raw(3, 1, vec![0u16, u16::MAX, 32768]).unwrap();
+        let settings = HeightmapSettings::new(&registry);
+        let generator = HeightmapGenerator::new(&DynamicImage::ImageLuma16(image), None, settings).unwrap();
+        assert_eq!(heights(&generator, 0..3), vec![Some(-8), Some(56), Some(24)]);
+
+        let chunk = generator.generate_chunk(ChunkPos::new(0, 3, 0));
+        assert_eq!(chunk.get(1, 8, 0), registry.block("grass"));
+        assert_eq!(chunk.get(1, 7, 0), registry.block("dirt"));
+        assert_eq!(chunk.get(1, 6, 0), registry.block("dirt"));
+        assert_eq!(chunk.get(1, 5, 0), registry.block("stone"));
+        assert!(chunk.get(1, 9, 0).is_air());
+        // below sea level there is water above and no grass
+        let chunk = generator.generate_chunk(ChunkPos::new(0, -1, 0));
+        assert_eq!(chunk.get(0, 8, 0), registry.block("dirt"));
+        assert_eq!(chunk.get(0, 9, 0), registry.block("water"));
+        assert_eq!(generator.generate_chunk(ChunkPos::new(0, 0, 0)).get(0, 0, 0), registry.block("water"));
+        assert!(generator.generate_chunk(ChunkPos::new(0, 0, 0)).get(0, 1, 0).is_air());
+    }
+
+    #[test]
+    fn outside_of_the_image_follows_the_mode() {
+        let settings = HeightmapSettings::new(&registry());
+        let generator = |out_of_bounds| row(&[0, 51, 255], HeightmapSettings { out_of_bounds, ..settings });
+
+        let clamp = generator(OutOfBounds::Clamp);
+        assert_eq!(heights(&clamp, -2..5), [0, 0, 0, 51, 255, 255, 255].map(Some));
+        assert_eq!(clamp.height_at(1, 100), Some(51));
+        let repeat = generator(OutOfBounds::Repeat);
+        assert_eq!(heights(&repeat, -3..6), [0, 51, 255, 0, 51, 255, 0, 51, 255].map(Some));
+        assert_eq!(repeat.height_at(1, -7), Some(51));
+        let mirror = generator(OutOfBounds::Mirror);
+        assert_eq!(heights(&mirror, -3..9), [255, 51, 0, 0, 51, 255, 255, 51, 0, 0, 51, 255].map(Some));
+
+        let height = generator(OutOfBounds::Height(20));
+        assert_eq!(heights(&height, -1..4), [20, 0, 51, 255, 20].map(Some));
+        assert_eq!(height.height_at(1, 1), Some(20));
+        assert_eq!(height.biome_at(BlockPos::new(-1, 0, 0)), Biome::Plains);
+
+        let void = generator(OutOfBounds::Void);
+        assert_eq!(heights(&void, -1..4), vec![None, Some(0), Some(51), Some(255), None]);
+        assert_eq!(void.height_at(1, -1), None);
+        // not even water below sea level
+        let chunk = void.generate_chunk(ChunkPos::new(-1, -1, 0));
+        assert_eq!(chunk.single_block(), Some(Block::AIR));
+    }
+
+    #[test]
+    fn surface_map_picks_the_nearest_colour() {
+        let registry = registry();
+        let colors = [
+            ([255, 0, 0], SurfaceMapEntry::biome(Biome::Desert, &registry)),
+            ([0, 0, 255], SurfaceMapEntry::biome(Biome::Mountains, &registry)),
+            ([0, 255, 0], SurfaceMapEntry::material(registry.block("log"), registry.block("sand"))),
+        ];
+        let pixels = [[250, 10, 10], [30, 40, 200], [90, 160, 80], [200, 60, 60]].concat();
+        let image = DynamicImage::ImageRgb8(RgbImage::from_raw(4, 1, pixels).unwrap());
+        let surface_map = SurfaceMap::new(&image, &colors).unwrap();
+        let heightmap = DynamicImage::new_luma8(4, 1);
+        let settings = HeightmapSettings { min_height: 5, max_height: 5, ..HeightmapSettings::new(&registry) };
+        let generator = HeightmapGenerator::new(&heightmap, Some(surface_map), settings).unwrap();
+
+        let chunk = generator.generate_chunk(ChunkPos::new(0, 0, 0));
+        let surfaces = (0..4).map(|x| chunk.get(x, 5, 0)).collect::<Vec<_>>();
+        assert_eq!(surfaces, ["sand", "stone", "log", "sand"].map(|name| registry.block(name)));
+        assert_eq!(chunk.get(2, 4, 0), registry.block("sand"));
+        let biomes = (0..4).map(|x| generator.biome_at(BlockPos::new(x, 5, 0))).collect::<Vec<_>>();
+        assert_eq!(biomes, [Biome::Desert, Biome::Mountains, Biome::Plains, Biome::Desert]);
+
+        // without colours the surface of the settings is used
+        let surface_map = SurfaceMap::new(&image, &[]).unwrap();
+        let generator = HeightmapGenerator::new(&heightmap, Some(surface_map), settings).unwrap();
+        assert_eq!(generator.generate_chunk(ChunkPos::new(0, 0, 0)).get(0, 5, 0), registry.block("grass"));
+    }
+
+    #[test]
+    fn blocks_per_pixel_has_to_be_positive() {
+        let settings = HeightmapSettings::new(&registry());
+        let image = DynamicImage::new_luma8(2, 2);
+        for blocks_per_pixel in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let result = HeightmapGenerator::new(&image, None, HeightmapSettings { blocks_per_pixel, ..settings });
+            assert!(matches!(result, Err(HeightmapError::BlocksPerPixel(_))));
+        }
+        assert!(HeightmapGenerator::new(&image, None, HeightmapSettings { blocks_per_pixel: 0.25, ..settings }).is_ok());
+    }
+}
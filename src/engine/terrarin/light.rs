@@ -0,0 +1,528 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use glam::{const_ivec3, IVec3};
+use crate::engine::terrarin::block::{BlockPos, FACE_NORMALS};
+use crate::engine::terrarin::chunk::{Chunk, ChunkPos, CHUNK_SIZE, CHUNK_SIZE_I};
+use crate::engine::terrarin::registry::BlockRegistry;
+
+pub const MAX_LIGHT: u8 = 15;
+
+const UP: IVec3 = const_ivec3!([0, 1, 0]);
+const DOWN: IVec3 = const_ivec3!([0, -1, 0]);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LightKind {
+    // comes from the top of the world, full sky light goes straight down without getting weaker
+    Sky,
+    // comes from blocks with light emission
+    Block,
+}
+
+impl LightKind {
+    pub const ALL: [LightKind; 2] = [LightKind::Sky, LightKind::Block];
+}
+
+// both light levels of a single block, each in 0..=MAX_LIGHT
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Light {
+    pub sky: u8,
+    pub block: u8,
+}
+
+impl Light {
+    pub const DARK: Light = Light { sky: 0, block: 0 };
+    pub const SKY: Light = Light { sky: MAX_LIGHT, block: 0 };
+
+    pub fn get(self, kind: LightKind) -> u8 {
+        return match kind {
+            LightKind::Sky => self.sky,
+            LightKind::Block => self.block,
+        };
+    }
+}
+
+// Light level for every block of a chunk, two levels are packed into a byte.
+// Nothing is allocated while all levels are the same, which is the case for most chunks
+// deep underground or high in the sky.
+#[derive(Clone)]
+pub struct NibbleArray {
+    len: usize,
+    uniform: u8,
+    data: Option<Vec<u8>>,
+}
+
+impl NibbleArray {
+    pub fn filled(len: usize, level: u8) -> NibbleArray {
+        return NibbleArray { len, uniform: level & 0xF, data: None };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn get(&self, i: usize) -> u8 {
+        debug_assert!(i < self.len);
+        return match &self.data {
+            Some(data) => (data[i >> 1] >> ((i & 1) * 4)) & 0xF,
+            None => self.uniform,
+        };
+    }
+
+    pub fn set(&mut self, i: usize, level: u8) {
+        debug_assert!(i < self.len);
+        let level = level & 0xF;
+        if self.data.is_none() {
+            if level == self.uniform {
+                return;
+            }
+            self.data = Some(vec![self.uniform | (self.uniform << 4); (self.len + 1) / 2]);
+        }
+        let data = self.data.as_mut().unwrap();
+        let shift = (i & 1) * 4;
+        data[i >> 1] = (data[i >> 1] & !(0xF << shift)) | (level << shift);
+    }
+
+    pub fn fill(&mut self, level: u8) {
+        self.uniform = level & 0xF;
+        self.data = None;
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        return self.data.as_ref().map_or(0, |data| data.capacity());
+    }
+}
+
+// Flood fills light through loaded chunks, light never spreads into chunks that are not loaded.
+// Sky light of a column without a loaded chunk above it is taken as full, so it's only correct
+// once the chunks above are loaded, which relights the chunks below them.
+pub(crate) struct LightEngine<'a> {
+    chunks: &'a HashMap<ChunkPos, RefCell<Chunk>>,
+    registry: &'a BlockRegistry,
+    // blocks whose light should spread to their neighbours
+    increase: VecDeque<BlockPos>,
+    // blocks that lost their light, with the level they had
+    decrease: VecDeque<(BlockPos, u8)>,
+    changed: HashSet<ChunkPos>,
+}
+
+impl<'a> LightEngine<'a> {
+    pub fn new(chunks: &'a HashMap<ChunkPos, RefCell<Chunk>>, registry: &'a BlockRegistry) -> LightEngine<'a> {
+        return LightEngine {
+            chunks,
+            registry,
+            increase: VecDeque::new(),
+            decrease: VecDeque::new(),
+            changed: HashSet::new(),
+        };
+    }
+
+    // Chunks whose meshes show changed light, chunks next to a changed border block included.
+    pub fn changed(self) -> HashSet<ChunkPos> {
+        return self.changed;
+    }
+
+    // Lights a chunk that was just inserted into the world, light of its neighbours spreads into it
+    // and its own light spreads into them.
+    pub fn light_chunk(&mut self, pos: ChunkPos) {
+        let min = pos.block_min();
+        let emitting = {
+            let chunk = self.chunks[&pos].borrow();
+            chunk.storage().palette().iter().any(|block| self.registry.get(*block).light_emission > 0)
+        };
+
+        // full sky light falls down every column until it hits an opaque block, then only blocks
+        // next to darker ones have to spread, which skips most of the open sky
+        {
+            let mut chunk = self.chunks[&pos].borrow_mut();
+            let above = self.chunks.get(&ChunkPos(pos.0 + UP)).map(|above| above.borrow());
+            for x in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let sky = match &above {
+                        Some(above) => above.light(LightKind::Sky, x, 0, z) == MAX_LIGHT,
+                        None => true,
+                    };
+                    if !sky {
+                        continue;
+                    }
+                    for y in (0..CHUNK_SIZE).rev() {
+                        if !self.registry.get(chunk.get(x, y, z)).transparent {
+                            break;
+                        }
+                        chunk.set_light(LightKind::Sky, x, y, z, MAX_LIGHT);
+                    }
+                }
+            }
+            for x in 0..CHUNK_SIZE_I {
+                for y in 0..CHUNK_SIZE_I {
+                    for z in 0..CHUNK_SIZE_I {
+                        let local = IVec3::new(x, y, z);
+                        if chunk.light(LightKind::Sky, x as usize, y as usize, z as usize) != MAX_LIGHT {
+                            continue;
+                        }
+                        let spreads = FACE_NORMALS.iter().any(|normal| {
+                            let next = local + *normal;
+                            if next.min_element() < 0 || next.max_element() >= CHUNK_SIZE_I {
+                                return true;
+                            }
+                            let (nx, ny, nz) = (next.x as usize, next.y as usize, next.z as usize);
+                            return chunk.light(LightKind::Sky, nx, ny, nz) != MAX_LIGHT && self.registry.get(chunk.get(nx, ny, nz)).transparent;
+                        });
+                        if spreads {
+                            self.increase.push_back(BlockPos(min.0 + local));
+                        }
+                    }
+                }
+            }
+        }
+        self.changed.insert(pos);
+        self.seed_from_neighbours(LightKind::Sky, pos);
+        self.spread(LightKind::Sky);
+
+        if emitting {
+            for x in 0..CHUNK_SIZE_I {
+                for y in 0..CHUNK_SIZE_I {
+                    for z in 0..CHUNK_SIZE_I {
+                        let block = BlockPos(min.0 + IVec3::new(x, y, z));
+                        let emission = self.emission(block);
+                        if emission > 0 {
+                            self.set_light(LightKind::Block, block, emission);
+                            self.increase.push_back(block);
+                        }
+                    }
+                }
+            }
+        }
+        self.seed_from_neighbours(LightKind::Block, pos);
+        self.spread(LightKind::Block);
+
+        // chunk below could have taken full sky light for granted while this one was not loaded
+        if self.chunks.contains_key(&ChunkPos(pos.0 + DOWN)) {
+            for x in 0..CHUNK_SIZE_I {
+                for z in 0..CHUNK_SIZE_I {
+                    let bottom = BlockPos(min.0 + IVec3::new(x, 0, z));
+                    let below = BlockPos(bottom.0 + DOWN);
+                    if self.light(LightKind::Sky, below) == Some(MAX_LIGHT) && self.light(LightKind::Sky, bottom) != Some(MAX_LIGHT) {
+                        self.set_light(LightKind::Sky, below, 0);
+                        self.decrease.push_back((below, MAX_LIGHT));
+                    }
+                }
+            }
+            self.remove(LightKind::Sky);
+            self.spread(LightKind::Sky);
+        }
+    }
+
+    // Fixes light around blocks that were changed, blocks in chunks that are not loaded are skipped.
+    pub fn update_blocks(&mut self, positions: &[BlockPos]) {
+        for kind in LightKind::ALL {
+            for pos in positions {
+                match self.light(kind, *pos) {
+                    Some(level) if level > 0 => {
+                        self.set_light(kind, *pos, 0);
+                        self.decrease.push_back((*pos, level));
+                    }
+                    _ => {}
+                }
+            }
+            self.remove(kind);
+
+            for pos in positions {
+                if !self.chunks.contains_key(&pos.chunk()) {
+                    continue;
+                }
+                let emission = match kind {
+                    LightKind::Sky => 0,
+                    LightKind::Block => self.emission(*pos),
+                };
+                if emission > 0 {
+                    self.set_light(kind, *pos, emission);
+                    self.increase.push_back(*pos);
+                }
+                if !self.is_transparent(*pos) {
+                    continue;
+                }
+                let above = BlockPos(pos.0 + UP);
+                if kind == LightKind::Sky && !self.chunks.contains_key(&above.chunk()) {
+                    self.set_light(kind, *pos, MAX_LIGHT);
+                    self.increase.push_back(*pos);
+                }
+                for normal in FACE_NORMALS {
+                    let neighbour = BlockPos(pos.0 + normal);
+                    if self.light(kind, neighbour).unwrap_or(0) > 0 {
+                        self.increase.push_back(neighbour);
+                    }
+                }
+            }
+            self.spread(kind);
+        }
+    }
+
+    // border blocks of loaded neighbours spread their light into the chunk
+    fn seed_from_neighbours(&mut self, kind: LightKind, pos: ChunkPos) {
+        let min = pos.block_min();
+        for normal in FACE_NORMALS {
+            if !self.chunks.contains_key(&ChunkPos(pos.0 + normal)) {
+                continue;
+            }
+            let axis = if normal.x != 0 { 0 } else if normal.y != 0 { 1 } else { 2 };
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+            for i in 0..CHUNK_SIZE_I {
+                for j in 0..CHUNK_SIZE_I {
+                    let mut local = IVec3::ZERO;
+                    local[axis] = if normal[axis] > 0 { CHUNK_SIZE_I } else { -1 };
+                    local[u] = i;
+                    local[v] = j;
+                    let neighbour = BlockPos(min.0 + local);
+                    if self.light(kind, neighbour).unwrap_or(0) > 1 {
+                        self.increase.push_back(neighbour);
+                    }
+                }
+            }
+        }
+    }
+
+    fn spread(&mut self, kind: LightKind) {
+        while let Some(pos) = self.increase.pop_front() {
+            let level = match self.light(kind, pos) {
+                Some(level) => level,
+                None => continue,
+            };
+            for normal in FACE_NORMALS {
+                let spread = if kind == LightKind::Sky && level == MAX_LIGHT && normal == DOWN {
+                    MAX_LIGHT
+                } else {
+                    level.saturating_sub(1)
+                };
+                if spread == 0 {
+                    continue;
+                }
+                let neighbour = BlockPos(pos.0 + normal);
+                match self.light(kind, neighbour) {
+                    Some(current) if current < spread && self.is_transparent(neighbour) => {
+                        self.set_light(kind, neighbour, spread);
+                        self.increase.push_back(neighbour);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Darkens blocks that got their light from removed light, brighter blocks at the edge of
+    // the darkened area are queued to spread their light back into it.
+    fn remove(&mut self, kind: LightKind) {
+        while let Some((pos, level)) = self.decrease.pop_front() {
+            for normal in FACE_NORMALS {
+                let neighbour = BlockPos(pos.0 + normal);
+                let current = match self.light(kind, neighbour) {
+                    Some(current) if current > 0 => current,
+                    _ => continue,
+                };
+                let lit_by_pos = current < level || (kind == LightKind::Sky && level == MAX_LIGHT && normal == DOWN);
+                if !lit_by_pos {
+                    self.increase.push_back(neighbour);
+                    continue;
+                }
+                self.set_light(kind, neighbour, 0);
+                self.decrease.push_back((neighbour, current));
+                if kind == LightKind::Block {
+                    let emission = self.emission(neighbour);
+                    if emission > 0 {
+                        self.set_light(kind, neighbour, emission);
+                        self.increase.push_back(neighbour);
+                    }
+                }
+            }
+        }
+    }
+
+    // None if chunk is not loaded
+    fn light(&self, kind: LightKind, pos: BlockPos) -> Option<u8> {
+        return self.chunks.get(&pos.chunk()).map(|chunk| chunk.borrow().get_light(pos, kind));
+    }
+
+    fn set_light(&mut self, kind: LightKind, pos: BlockPos, level: u8) {
+        let chunk_pos = pos.chunk();
+        if let Some(chunk) = self.chunks.get(&chunk_pos) {
+            chunk.borrow_mut().set_light_at(pos, kind, level);
+            self.changed.insert(chunk_pos);
+            // faces of neighbours touching a border block show its light too
+            let local = pos.chunk_relative().0;
+            for axis in 0..3 {
+                let mut offset = IVec3::ZERO;
+                if local[axis] == 0 {
+                    offset[axis] = -1;
+                } else if local[axis] == CHUNK_SIZE_I - 1 {
+                    offset[axis] = 1;
+                } else {
+                    continue;
+                }
+                self.changed.insert(ChunkPos(chunk_pos.0 + offset));
+            }
+        }
+    }
+
+    fn is_transparent(&self, pos: BlockPos) -> bool {
+        return match self.chunks.get(&pos.chunk()) {
+            Some(chunk) => self.registry.get(chunk.borrow().get_block(pos)).transparent,
+            None => false,
+        };
+    }
+
+    fn emission(&self, pos: BlockPos) -> u8 {
+        return match self.chunks.get(&pos.chunk()) {
+            Some(chunk) => self.registry.get(chunk.borrow().get_block(pos)).light_emission.min(MAX_LIGHT),
+            None => 0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::engine::terrarin::block::Block;
+    use crate::engine::terrarin::block_region::BlockAabb;
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use crate::engine::terrarin::world::GameWorld;
+    use super::*;
+
+    // chunks from -1 to 0 on x and z and from -1 to 1 on y, ground is at y = 7
+    fn flat_world() -> GameWorld {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        let mut world = GameWorld::new(Box::new(FlatEarthGenerator::new(7, 5, &registry)), registry.clone());
+        // lower chunks first, so loading the ones above has to darken them again
+        for y in [-1, 0, 1] {
+            for x in -1..=0 {
+                for z in -1..=0 {
+                    world.chunk_at(ChunkPos::new(x, y, z));
+                }
+            }
+        }
+        return world;
+    }
+
+    fn loaded_box() -> BlockAabb {
+        return BlockAabb::new(ChunkPos::new(-1, -1, -1).block_min(), ChunkPos::new(0, 1, 0).block_max());
+    }
+
+    // Light of every loaded block computed from scratch, levels are raised until nothing changes.
+    // Nothing is loaded above the box, so sky light falls in from its top.
+    fn recomputed(world: &GameWorld, kind: LightKind) -> HashMap<BlockPos, u8> {
+        let registry = world.registry();
+        let aabb = loaded_box();
+        let transparent = |pos: BlockPos| registry.get(world.get_block(pos).unwrap()).transparent;
+        let mut levels: HashMap<BlockPos, u8> = HashMap::new();
+        for pos in aabb.iter() {
+            let level = match kind {
+                LightKind::Sky => {
+                    let open = (pos.y..=aabb.max.y).all(|y| transparent(BlockPos::new(pos.x, y, pos.z)));
+                    if open { MAX_LIGHT } else { 0 }
+                }
+                LightKind::Block => registry.get(world.get_block(pos).unwrap()).light_emission.min(MAX_LIGHT),
+            };
+            levels.insert(pos, level);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for pos in aabb.iter() {
+                if !transparent(pos) {
+                    continue;
+                }
+                let mut level = levels[&pos];
+                for normal in FACE_NORMALS {
+                    let neighbour = match levels.get(&BlockPos(pos.0 + normal)) {
+                        Some(neighbour) => *neighbour,
+                        None => continue,
+                    };
+                    let spread = if kind == LightKind::Sky && neighbour == MAX_LIGHT && normal == UP { MAX_LIGHT } else { neighbour.saturating_sub(1) };
+                    level = level.max(spread);
+                }
+                if level != levels[&pos] {
+                    levels.insert(pos, level);
+                    changed = true;
+                }
+            }
+        }
+        return levels;
+    }
+
+    fn assert_fully_lit(world: &GameWorld) {
+        for kind in LightKind::ALL {
+            let expected = recomputed(world, kind);
+            for pos in loaded_box().iter() {
+                assert_eq!(world.get_light(pos, kind), Some(expected[&pos]), "{:?} light at {:?}", kind, pos);
+            }
+        }
+    }
+
+    #[test]
+    fn sky_light_goes_around_a_roof() {
+        let mut world = flat_world();
+        let stone = world.registry().block("stone");
+        assert_eq!(world.get_light(BlockPos::new(3, 8, -9), LightKind::Sky), Some(MAX_LIGHT));
+        assert_eq!(world.get_light(BlockPos::new(3, 7, -9), LightKind::Sky), Some(0));
+        world.set_blocks(BlockAabb::new(BlockPos::new(0, 20, 0), BlockPos::new(4, 20, 4)).iter().map(|pos| (pos, stone)));
+
+        assert_eq!(world.get_light(BlockPos::new(2, 21, 2), LightKind::Sky), Some(MAX_LIGHT));
+        // three blocks from the open columns next to the roof
+        assert_eq!(world.get_light(BlockPos::new(2, 19, 2), LightKind::Sky), Some(12));
+        assert_eq!(world.get_light(BlockPos::new(2, 8, 2), LightKind::Sky), Some(12));
+        assert_eq!(world.get_light(BlockPos::new(0, 8, 2), LightKind::Sky), Some(14));
+        assert_eq!(world.get_light(BlockPos::new(2, 20, 2), LightKind::Sky), Some(0));
+        assert_fully_lit(&world);
+    }
+
+    #[test]
+    fn block_light_falls_off_with_distance() {
+        let mut world = flat_world();
+        let lava = world.registry().block("lava");
+        world.set_block(BlockPos::new(3, 12, 3), lava);
+        for distance in 0..=MAX_LIGHT as i32 {
+            let level = MAX_LIGHT - distance.min(MAX_LIGHT as i32) as u8;
+            assert_eq!(world.get_light(BlockPos::new(3, 12, 3 - distance), LightKind::Block), Some(level));
+        }
+        assert_eq!(world.get_light(BlockPos::new(4, 13, 1), LightKind::Block), Some(11));
+        // the ground is opaque, blocks below it stay dark
+        assert_eq!(world.get_light(BlockPos::new(3, 8, 3), LightKind::Block), Some(11));
+        assert_eq!(world.get_light(BlockPos::new(3, 7, 3), LightKind::Block), Some(0));
+        assert_eq!(world.get_light(BlockPos::new(3, 6, 3), LightKind::Block), Some(0));
+        assert_fully_lit(&world);
+    }
+
+    #[test]
+    fn edits_across_chunk_borders_match_full_recompute() {
+        let mut world = flat_world();
+        let stone = world.registry().block("stone");
+        let lava = world.registry().block("lava");
+        assert_fully_lit(&world);
+
+        // roof over the corner where all four chunk columns meet, with a lamp under it
+        let roof = BlockAabb::new(BlockPos::new(-5, 14, -5), BlockPos::new(4, 15, 4));
+        world.set_blocks(roof.iter().map(|pos| (pos, stone)));
+        assert_fully_lit(&world);
+        world.set_block(BlockPos::new(-1, 10, 0), lava);
+        assert_fully_lit(&world);
+        // walls on both sides of the x border next to the lamp
+        world.set_block(BlockPos::new(0, 10, 0), stone);
+        world.set_block(BlockPos::new(-2, 10, 0), stone);
+        assert_fully_lit(&world);
+        // and one in the roof on the y border of the chunks above
+        world.set_block(BlockPos::new(-1, 16, -1), lava);
+        assert_fully_lit(&world);
+
+        world.set_block(BlockPos::new(-1, 10, 0), Block::AIR);
+        assert_fully_lit(&world);
+        world.set_block(BlockPos::new(0, 10, 0), Block::AIR);
+        world.set_block(BlockPos::new(-1, 16, -1), Block::AIR);
+        assert_fully_lit(&world);
+        world.set_blocks(roof.iter().filter(|pos| pos.x == -1 || pos.x == 0).map(|pos| (pos, Block::AIR)));
+        assert_fully_lit(&world);
+        world.set_blocks(roof.iter().map(|pos| (pos, Block::AIR)));
+        world.set_block(BlockPos::new(-2, 10, 0), Block::AIR);
+        assert_fully_lit(&world);
+        assert_eq!(world.get_light(BlockPos::new(-1, 10, 0), LightKind::Sky), Some(MAX_LIGHT));
+        assert_eq!(world.get_light(BlockPos::new(-1, 10, 0), LightKind::Block), Some(0));
+    }
+}
@@ -0,0 +1,184 @@
+use glam::IVec3;
+use crate::engine::terrarin::block::Block;
+use crate::engine::terrarin::chunk::{CHUNK_SIZE_I, ChunkPos};
+use crate::engine::terrarin::light::Light;
+use crate::engine::terrarin::neighbourhood::ChunkNeighbourhood;
+use crate::engine::terrarin::registry::BlockRegistry;
+
+// Level 0 is the full chunk, every next level halves its resolution,
+// so the last level meshes a chunk from 2³ cells of 8³ blocks.
+pub const MAX_LOD: u32 = 3;
+
+// size of a cell in blocks
+pub fn lod_scale(level: u32) -> i32 {
+    return 1 << level.min(MAX_LOD);
+}
+
+// Chunk downsampled to cells of lod_scale³ blocks, with one cell of padding around it like
+// ChunkNeighbourhood. Padding cells are air unless every block of the neighbourhood padding behind
+// them is opaque, so meshes of downsampled chunks close their own borders with faces neighbours
+// of another level don't have. Finer neighbours don't hide their border faces behind them either,
+// see ChunkMesher::build_lod, so there are no cracks where a cell of the coarser chunk is air.
+pub struct LodChunk {
+    position: ChunkPos,
+    level: u32,
+    size: i32,
+    blocks: Vec<Block>,
+    light: Vec<Light>,
+    // average grass colour of the block columns of every cell column, x * size + z
+    grass_colors: Option<Vec<[f32; 3]>>,
+}
+
+impl LodChunk {
+    // A cell gets the most common block in it if at least half of it is filled, otherwise it is air.
+    // Liquids count as their full source, lowered surfaces can't be shown at this size anyway.
+    // Light of a cell is the brightest light in it, padding cells take light from the neighbourhood padding.
+    pub fn new(chunk: &ChunkNeighbourhood, level: u32, registry: &BlockRegistry) -> LodChunk {
+        let scale = lod_scale(level);
+        let size = CHUNK_SIZE_I / scale;
+        let padded = (size + 2) as usize;
+        let mut lod = LodChunk {
+            position: chunk.get_position(),
+            level: level.min(MAX_LOD),
+            size,
+            blocks: vec![Block::AIR; padded * padded * padded],
+            light: vec![Light::DARK; padded * padded * padded],
+            grass_colors: None,
+        };
+        let mut counts: Vec<(Block, i32)> = Vec::new();
+        for x in -1..=size {
+            for y in -1..=size {
+                for z in -1..=size {
+                    let cell = IVec3::new(x, y, z);
+                    // padding cells cover only the single block of neighbourhood padding
+                    let min = (cell * scale).max(IVec3::splat(-1));
+                    let max = ((cell + 1) * scale).min(IVec3::splat(CHUNK_SIZE_I + 1));
+                    let inside = cell.cmpge(IVec3::ZERO).all() && cell.cmplt(IVec3::splat(size)).all();
+                    let mut light = Light::DARK;
+                    let mut padding: Option<Block> = None;
+                    let mut opaque_padding = true;
+                    counts.clear();
+                    for bx in min.x..max.x {
+                        for by in min.y..max.y {
+                            for bz in min.z..max.z {
+                                let local = IVec3::new(bx, by, bz);
+                                let block_light = chunk.get_light_local(local);
+                                light.sky = light.sky.max(block_light.sky);
+                                light.block = light.block.max(block_light.block);
+                                let mut block = chunk.get_local(local);
+                                if !inside {
+                                    opaque_padding &= !registry.get(block).transparent;
+                                    padding.get_or_insert(block);
+                                    continue;
+                                }
+                                if block.is_air() {
+                                    continue;
+                                }
+                                if registry.get(block).liquid {
+                                    block = Block::new(block.id);
+                                }
+                                match counts.iter_mut().find(|(counted, _)| *counted == block) {
+                                    Some((_, count)) => *count += 1,
+                                    None => counts.push((block, 1)),
+                                }
+                            }
+                        }
+                    }
+                    let filled: i32 = counts.iter().map(|(_, count)| count).sum();
+                    let block = if !inside {
+                        if opaque_padding { padding.unwrap_or(Block::AIR) } else { Block::AIR }
+                    } else if filled * 2 >= scale * scale * scale {
+                        // first of the most common blocks, so ties don't depend on anything but the blocks
+                        counts.iter().rev().max_by_key(|(_, count)| *count).map_or(Block::AIR, |(block, _)| *block)
+                    } else {
+                        Block::AIR
+                    };
+                    let i = lod.index(cell).unwrap();
+                    lod.blocks[i] = block;
+                    lod.light[i] = light;
+                }
+            }
+        }
+        // Skirt faces are mostly inside of neighbouring blocks and would be black where cracks
+        // show them, so padding takes the brightest light above it in its column.
+        for x in -1..=size {
+            for z in -1..=size {
+                for y in (-1..size).rev() {
+                    let cell = IVec3::new(x, y, z);
+                    if cell.cmpge(IVec3::ZERO).all() && cell.cmplt(IVec3::splat(size)).all() {
+                        continue;
+                    }
+                    let above = lod.light[lod.index(IVec3::new(x, y + 1, z)).unwrap()];
+                    let i = lod.index(cell).unwrap();
+                    lod.light[i].sky = lod.light[i].sky.max(above.sky);
+                    lod.light[i].block = lod.light[i].block.max(above.block);
+                }
+            }
+        }
+        if chunk.get_grass_color_local(0, 0).is_some() {
+            let mut colors = Vec::with_capacity((size * size) as usize);
+            for x in 0..size {
+                for z in 0..size {
+                    let mut color = [0.0; 3];
+                    for bx in x * scale..(x + 1) * scale {
+                        for bz in z * scale..(z + 1) * scale {
+                            let column = chunk.get_grass_color_local(bx, bz).unwrap();
+                            for channel in 0..3 {
+                                color[channel] += column[channel] / (scale * scale) as f32;
+                            }
+                        }
+                    }
+                    colors.push(color);
+                }
+            }
+            lod.grass_colors = Some(colors);
+        }
+        return lod;
+    }
+
+    pub fn get_position(&self) -> ChunkPos {
+        return self.position;
+    }
+
+    pub fn level(&self) -> u32 {
+        return self.level;
+    }
+
+    // cells along each axis of the chunk, without padding
+    pub fn size(&self) -> i32 {
+        return self.size;
+    }
+
+    // cell relative to the chunk, valid range is -1..=size on each axis, air outside of it
+    pub fn get_local(&self, cell: IVec3) -> Block {
+        return match self.index(cell) {
+            Some(i) => self.blocks[i],
+            None => Block::AIR,
+        };
+    }
+
+    // full sky light outside of the chunk and its padding
+    pub fn get_light_local(&self, cell: IVec3) -> Light {
+        return match self.index(cell) {
+            Some(i) => self.light[i],
+            None => Light::SKY,
+        };
+    }
+
+    // padding cells use the nearest cell column of the chunk
+    pub fn get_grass_color_local(&self, x: i32, z: i32) -> Option<[f32; 3]> {
+        let colors = self.grass_colors.as_ref()?;
+        let x = x.clamp(0, self.size - 1);
+        let z = z.clamp(0, self.size - 1);
+        return Some(colors[(x * self.size + z) as usize]);
+    }
+
+    fn index(&self, cell: IVec3) -> Option<usize> {
+        let padded = self.size + 2;
+        let cell = cell + 1;
+        if cell.cmplt(IVec3::ZERO).any() || cell.cmpge(IVec3::splat(padded)).any() {
+            return None;
+        }
+        return Some(((cell.x * padded + cell.y) * padded + cell.z) as usize);
+    }
+}
@@ -0,0 +1,170 @@
+use std::ops::RangeInclusive;
+use glam::{IVec2, IVec3};
+use image::{Rgba, RgbaImage};
+use crate::engine::terrarin::block::Block;
+use crate::engine::terrarin::chunk::{CHUNK_SIZE, CHUNK_SIZE_I, ChunkPos};
+use crate::engine::terrarin::world::GameWorld;
+
+#[derive(Debug, Copy, Clone)]
+pub struct MapSettings {
+    // heights of the darkest and brightest shade, colours are scaled linearly between them
+    pub shade_min_height: i32,
+    pub shade_max_height: i32,
+    // brightness at shade_min_height and below, 1 is full colour
+    pub min_brightness: f32,
+    // how much brighter or darker a pixel gets per block it is higher or lower than the one
+    // to its top left, so slopes show like lit from there, big drops are black
+    pub relief: f32,
+}
+
+impl Default for MapSettings {
+    fn default() -> Self {
+        return MapSettings {
+            shade_min_height: -32,
+            shade_max_height: 96,
+            min_brightness: 0.4,
+            relief: 0.08,
+        };
+    }
+}
+
+// Renders a top down map of all chunk columns between two corners, given as chunk x and z.
+// Every block is one pixel, x goes right and z down. Each pixel has the top colour of the highest
+// block that isn't air with y in y_range, blocks above or below it are not looked at, so the range
+// has to reach above the highest terrain. Columns without any block in the range are transparent.
+// Chunks that are not loaded are loaded or generated one by one and thrown away, see
+// GameWorld::peek_chunk, chunk layers are searched from the top only until every column has a block.
+// Such chunks miss blocks of features rooted in chunks that aren't loaded, so tree tops reaching
+// up out of the chunk below or over from a chunk next to it are cut off where they cross into it.
+pub fn render_map(world: &mut GameWorld, a: IVec2, b: IVec2, y_range: RangeInclusive<i32>, settings: &MapSettings) -> RgbaImage {
+    let min = a.min(b);
+    let max = a.max(b);
+    let width = ((max.x - min.x + 1) * CHUNK_SIZE_I) as usize;
+    let depth = ((max.y - min.y + 1) * CHUNK_SIZE_I) as usize;
+    // highest block and its height of every pixel
+    let mut tops: Vec<Option<(Block, i32)>> = vec![None; width * depth];
+    if y_range.is_empty() {
+        return shade(world, &tops, width, depth, settings);
+    }
+    let (bottom, top) = (*y_range.start(), *y_range.end());
+    for chunk_x in min.x..=max.x {
+        for chunk_z in min.y..=max.y {
+            let pixel_x = ((chunk_x - min.x) * CHUNK_SIZE_I) as usize;
+            let pixel_z = ((chunk_z - min.y) * CHUNK_SIZE_I) as usize;
+            let mut missing = CHUNK_SIZE * CHUNK_SIZE;
+            for chunk_y in (bottom.div_euclid(CHUNK_SIZE_I)..=top.div_euclid(CHUNK_SIZE_I)).rev() {
+                let pos = ChunkPos::new(chunk_x, chunk_y, chunk_z);
+                let min_y = pos.block_min().y;
+                // part of the chunk inside of the range
+                let local_bottom = (bottom - min_y).max(0) as usize;
+                let local_top = (top - min_y).min(CHUNK_SIZE_I - 1) as usize;
+                world.peek_chunk(pos, |chunk| {
+                    for x in 0..CHUNK_SIZE {
+                        for z in 0..CHUNK_SIZE {
+                            let pixel = &mut tops[(pixel_z + z) * width + pixel_x + x];
+                            if pixel.is_some() {
+                                continue;
+                            }
+                            if let Some(y) = (local_bottom..=local_top).rev().find(|y| !chunk.get(x, *y, z).is_air()) {
+                                *pixel = Some((chunk.get(x, y, z), min_y + y as i32));
+                                missing -= 1;
+                            }
+                        }
+                    }
+                });
+                if missing == 0 {
+                    break;
+                }
+            }
+        }
+    }
+    return shade(world, &tops, width, depth, settings);
+}
+
+// colours highest blocks found by render_map
+fn shade(world: &GameWorld, tops: &[Option<(Block, i32)>], width: usize, depth: usize, settings: &MapSettings) -> RgbaImage {
+    let registry = world.registry();
+    let range = (settings.shade_max_height - settings.shade_min_height).max(1) as f32;
+    let mut image = RgbaImage::new(width as u32, depth as u32);
+    for z in 0..depth {
+        for x in 0..width {
+            let (block, height) = match tops[z * width + x] {
+                Some(top) => top,
+                None => continue,
+            };
+            let t = ((height - settings.shade_min_height) as f32 / range).clamp(0.0, 1.0);
+            let mut brightness = settings.min_brightness + (1.0 - settings.min_brightness) * t;
+            if x > 0 && z > 0 {
+                if let Some((_, lit_from)) = tops[(z - 1) * width + x - 1] {
+                    brightness *= (1.0 + (height - lit_from) as f32 * settings.relief).max(0.0);
+                }
+            }
+            let color = registry.face(block, IVec3::Y).color.map(|c| ((c * brightness).clamp(0.0, 1.0) * 255.0).round() as u8);
+            image.put_pixel(x as u32, z as u32, Rgba([color[0], color[1], color[2], 255]));
+        }
+    }
+    return image;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::engine::terrarin::block::BlockPos;
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use crate::engine::terrarin::registry::BlockRegistry;
+    use super::*;
+
+    fn flat_world() -> GameWorld {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        return GameWorld::new(Box::new(FlatEarthGenerator::new(7, 5, &registry)), registry.clone());
+    }
+
+    fn color(world: &GameWorld, name: &str) -> [u8; 3] {
+        let registry = world.registry();
+        return registry.face(registry.block(name), IVec3::Y).color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    }
+
+    #[test]
+    fn only_blocks_in_range_are_drawn() {
+        let mut world = flat_world();
+        world.chunk_at(ChunkPos::new(0, 2, 0));
+        let sand = world.registry().block("sand");
+        world.set_block(BlockPos::new(3, 40, 4), sand);
+        let settings = MapSettings { shade_min_height: 0, shade_max_height: 1, min_brightness: 1.0, relief: 0.0 };
+
+        let map = render_map(&mut world, IVec2::new(1, 0), IVec2::new(-1, 0), -16..=47, &settings);
+        assert_eq!(map.dimensions(), (48, 16));
+        assert_eq!(map.get_pixel(19, 4).0[..3], color(&world, "sand"));
+        assert_eq!(map.get_pixel(0, 0).0[..3], color(&world, "grass"));
+        // chunks that were not loaded stay unloaded
+        assert_eq!(world.loaded_positions().count(), 1);
+
+        let below = render_map(&mut world, IVec2::new(0, 0), IVec2::new(0, 0), -16..=39, &settings);
+        assert_eq!(below.get_pixel(3, 4).0[..3], color(&world, "grass"));
+        let stone = render_map(&mut world, IVec2::new(0, 0), IVec2::new(0, 0), -16..=5, &settings);
+        assert_eq!(stone.get_pixel(3, 4).0[..3], color(&world, "stone"));
+        let sky = render_map(&mut world, IVec2::new(0, 0), IVec2::new(0, 0), 41..=100, &settings);
+        assert!(sky.pixels().all(|pixel| pixel.0[3] == 0));
+    }
+
+    #[test]
+    fn slopes_are_lit_from_the_top_left() {
+        let mut world = flat_world();
+        world.chunk_at(ChunkPos::new(0, 0, 0));
+        world.chunk_at(ChunkPos::new(0, 1, 0));
+        let stone = world.registry().block("stone");
+        world.set_block(BlockPos::new(4, 8, 4), stone);
+        world.set_block(BlockPos::new(8, 27, 8), stone);
+        let settings = MapSettings { shade_min_height: 0, shade_max_height: 1, min_brightness: 1.0, relief: 0.08 };
+        let map = render_map(&mut world, IVec2::new(0, 0), IVec2::new(0, 0), 0..=31, &settings);
+
+        let registry = world.registry();
+        let shaded = |name: &str, factor: f32| registry.face(registry.block(name), IVec3::Y).color
+            .map(|c| ((c * factor).clamp(0.0, 1.0) * 255.0).round() as u8);
+        assert_eq!(map.get_pixel(3, 3).0[..3], shaded("grass", 1.0));
+        assert_eq!(map.get_pixel(4, 4).0[..3], shaded("stone", 1.08));
+        assert_eq!(map.get_pixel(5, 5).0[..3], shaded("grass", 0.92));
+        // 20 blocks down from the pillar would be below zero brightness
+        assert_eq!(map.get_pixel(9, 9).0, [0, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,552 @@
+use std::sync::Arc;
+use glam::{IVec3, Vec3};
+use crate::engine::object::gameobject::Mesh;
+use crate::engine::renderer::renderer::{Vertex, VertexIndex};
+use crate::engine::terrarin::block::{Block, BlockPos, FACE_NORMALS};
+use crate::engine::terrarin::chunk::{Chunk, CHUNK_SIZE, CHUNK_SIZE_I};
+use crate::engine::terrarin::fluids::fluid_height;
+use crate::engine::terrarin::light::{Light, MAX_LIGHT};
+use crate::engine::terrarin::lod::{lod_scale, LodChunk, MAX_LOD};
+use crate::engine::terrarin::neighbourhood::ChunkNeighbourhood;
+use crate::engine::terrarin::registry::BlockRegistry;
+
+// Turns a whole chunk into a single mesh, vertex positions are relative to the chunk origin
+// so the entity holding the mesh should be placed at ChunkPos::world_min.
+pub struct ChunkMesher {
+    registry: Arc<BlockRegistry>,
+}
+
+// Only equal faces are merged into one quad.
+#[derive(Copy, Clone, PartialEq)]
+struct Face {
+    block: Block,
+    // face colour with grass tint applied
+    color: [f32; 3],
+    // light of the block in front of the face
+    light: Light,
+    // ambient occlusion of the corners at (u, v) = (0, 0), (1, 0), (1, 1), (0, 1), 0 is the darkest
+    ao: [u8; 4],
+}
+
+// Cells to mesh, full blocks of a chunk or downsampled ones. Positions are relative to the chunk
+// and valid in -1..=size on each axis, cells outside of the chunk are only looked at.
+trait Voxels {
+    fn size(&self) -> i32;
+    fn block(&self, cell: IVec3) -> Block;
+    fn light(&self, cell: IVec3) -> Light;
+    fn grass_color(&self, cell: IVec3) -> Option<[f32; 3]>;
+}
+
+impl Voxels for ChunkNeighbourhood {
+    fn size(&self) -> i32 {
+        return CHUNK_SIZE_I;
+    }
+
+    fn block(&self, cell: IVec3) -> Block {
+        return self.get_local(cell);
+    }
+
+    fn light(&self, cell: IVec3) -> Light {
+        return self.get_light_local(cell);
+    }
+
+    fn grass_color(&self, cell: IVec3) -> Option<[f32; 3]> {
+        return self.get_grass_color_local(cell.x, cell.z);
+    }
+}
+
+impl Voxels for LodChunk {
+    fn size(&self) -> i32 {
+        return LodChunk::size(self);
+    }
+
+    fn block(&self, cell: IVec3) -> Block {
+        return self.get_local(cell);
+    }
+
+    fn light(&self, cell: IVec3) -> Light {
+        return self.get_light_local(cell);
+    }
+
+    fn grass_color(&self, cell: IVec3) -> Option<[f32; 3]> {
+        return self.get_grass_color_local(cell.x, cell.z);
+    }
+}
+
+// Cells behind borders towards coarser neighbours are treated as air, so faces on these borders
+// are never hidden. Borders are in the order of FACE_NORMALS.
+struct OpenBorders<'a, V: Voxels> {
+    voxels: &'a V,
+    open: [bool; 6],
+}
+
+impl<'a, V: Voxels> OpenBorders<'a, V> {
+    fn is_open(&self, cell: IVec3) -> bool {
+        let size = self.voxels.size();
+        return FACE_NORMALS.iter().zip(self.open).any(|(normal, open)| {
+            let axis = if normal.x != 0 { 0 } else if normal.y != 0 { 1 } else { 2 };
+            return open && if normal[axis] > 0 { cell[axis] >= size } else { cell[axis] < 0 };
+        });
+    }
+}
+
+impl<'a, V: Voxels> Voxels for OpenBorders<'a, V> {
+    fn size(&self) -> i32 {
+        return self.voxels.size();
+    }
+
+    fn block(&self, cell: IVec3) -> Block {
+        if self.is_open(cell) {
+            return Block::AIR;
+        }
+        return self.voxels.block(cell);
+    }
+
+    fn light(&self, cell: IVec3) -> Light {
+        return self.voxels.light(cell);
+    }
+
+    fn grass_color(&self, cell: IVec3) -> Option<[f32; 3]> {
+        return self.voxels.grass_color(cell);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct Quad {
+    face: Face,
+    axis: usize,
+    dir: i32,
+    // corner with lowest coordinates in cells, already placed on the face plane
+    origin: IVec3,
+    width: i32,
+    height: i32,
+}
+
+impl ChunkMesher {
+    pub fn new(registry: Arc<BlockRegistry>) -> ChunkMesher {
+        return ChunkMesher { registry };
+    }
+
+    // meshes the chunk as if it was surrounded by air lit by the sky
+    pub fn build(&self, chunk: &Chunk, mesh_id: u32) -> Mesh {
+        return self.build_with_neighbours(&ChunkNeighbourhood::alone(chunk), mesh_id);
+    }
+
+    // faces hidden by blocks of neighbouring chunks are skipped
+    pub fn build_with_neighbours(&self, chunk: &ChunkNeighbourhood, mesh_id: u32) -> Mesh {
+        return self.build_lod(chunk, 0, [0; 6], mesh_id);
+    }
+
+    // Meshes the chunk downsampled to given level of detail, see LodChunk. Vertices are still
+    // in blocks, so the mesh is placed the same way.
+    // Neighbour levels are in the order of FACE_NORMALS. Downsampled blocks of a coarser neighbour
+    // don't always cover the blocks of this chunk behind them, so faces on borders towards coarser
+    // neighbours are never hidden. Level 0 next to neighbours of level 0 is build_with_neighbours.
+    pub fn build_lod(&self, chunk: &ChunkNeighbourhood, level: u32, neighbour_levels: [u32; 6], mesh_id: u32) -> Mesh {
+        let level = level.min(MAX_LOD);
+        let open = neighbour_levels.map(|neighbour| neighbour.min(MAX_LOD) > level);
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices: Vec<VertexIndex> = Vec::new();
+        if level == 0 {
+            for quad in self.greedy_quads(&OpenBorders { voxels: chunk, open }) {
+                self.push_quad(&quad, 1, &mut vertices, &mut indices);
+            }
+            self.push_lowered_fluids(chunk, &mut vertices, &mut indices);
+        } else {
+            let lod = LodChunk::new(chunk, level, &self.registry);
+            for quad in self.greedy_quads(&OpenBorders { voxels: &lod, open }) {
+                self.push_quad(&quad, lod_scale(lod.level()), &mut vertices, &mut indices);
+            }
+        }
+        return Mesh {
+            id: mesh_id,
+            vertices,
+            indices,
+        };
+    }
+
+    // Sweeps every axis in both directions, builds a mask of visible faces for each slice
+    // and merges equal neighbouring faces into as big rectangles as possible.
+    // Faces are only equal when they have the same block, light and ambient occlusion.
+    // Liquids lower than a full block are left for push_lowered_fluids.
+    fn greedy_quads<V: Voxels>(&self, voxels: &V) -> Vec<Quad> {
+        let size = voxels.size();
+        let mut quads = Vec::new();
+        let mut mask: [Option<Face>; CHUNK_SIZE * CHUNK_SIZE] = [None; CHUNK_SIZE * CHUNK_SIZE];
+        for axis in 0..3 {
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+            for dir in [-1, 1] {
+                for slice in 0..size {
+                    for i in 0..size {
+                        for j in 0..size {
+                            let mut pos = IVec3::ZERO;
+                            pos[axis] = slice;
+                            pos[u] = i;
+                            pos[v] = j;
+                            let block = voxels.block(pos);
+                            let mut facing = pos;
+                            facing[axis] += dir;
+                            let visible = self.is_face_visible(block, voxels.block(facing), axis != 1)
+                                && fluid_height(&self.registry, block) >= 1.0;
+                            mask[(i * size + j) as usize] = if visible {
+                                Some(Face {
+                                    block,
+                                    color: self.face_color(block, facing - pos, voxels.grass_color(pos)),
+                                    light: voxels.light(facing),
+                                    ao: self.face_ao(voxels, facing, u, v),
+                                })
+                            } else {
+                                None
+                            };
+                        }
+                    }
+                    Self::merge_mask(&mut mask, size as usize, axis, dir, slice, &mut quads);
+                }
+            }
+        }
+        return quads;
+    }
+
+    // Faces are hidden behind opaque blocks and between two blocks of the same transparent kind.
+    // Liquid of the same kind hides faces no matter its state, unless it is lower than a full
+    // block and the face is a side one.
+    fn is_face_visible(&self, block: Block, facing: Block, side: bool) -> bool {
+        if block.is_air() || !self.registry.get(facing).transparent {
+            return false;
+        }
+        if self.registry.get(block).liquid && facing.id == block.id {
+            return side && fluid_height(&self.registry, facing) < 1.0;
+        }
+        return facing != block;
+    }
+
+    // Liquid lower than a full block is meshed as a box of its height, one block at a time.
+    // Its top is lit by the liquid itself, as the surface is inside of its block.
+    fn push_lowered_fluids(&self, chunk: &ChunkNeighbourhood, vertices: &mut Vec<Vertex>, indices: &mut Vec<VertexIndex>) {
+        let min = chunk.get_position().block_min();
+        for x in 0..CHUNK_SIZE_I {
+            for y in 0..CHUNK_SIZE_I {
+                for z in 0..CHUNK_SIZE_I {
+                    let local = IVec3::new(x, y, z);
+                    let pos = BlockPos(min.0 + local);
+                    let block = chunk.get(pos);
+                    let height = fluid_height(&self.registry, block);
+                    if height >= 1.0 {
+                        continue;
+                    }
+                    let box_min = local.as_vec3();
+                    let box_max = box_min + Vec3::new(1.0, height, 1.0);
+                    for normal in FACE_NORMALS {
+                        let facing = BlockPos(pos.0 + normal);
+                        let facing_block = chunk.get(facing);
+                        let visible = if normal.y > 0 {
+                            facing_block.id != block.id
+                        } else if normal.y < 0 {
+                            facing_block.id != block.id && self.registry.get(facing_block).transparent
+                        } else {
+                            self.registry.get(facing_block).transparent
+                                && (facing_block.id != block.id || fluid_height(&self.registry, facing_block) < height)
+                        };
+                        if !visible {
+                            continue;
+                        }
+                        let axis = if normal.x != 0 { 0 } else if normal.y != 0 { 1 } else { 2 };
+                        let dir = normal[axis];
+                        let face = Face {
+                            block,
+                            color: self.face_color(block, normal, chunk.get_grass_color_local(x, z)),
+                            light: chunk.get_light(if normal.y > 0 { pos } else { facing }),
+                            ao: [3; 4],
+                        };
+                        self.push_face(&face, axis, dir, box_min, box_max, vertices, indices);
+                    }
+                }
+            }
+        }
+    }
+
+    // grass tinted faces take the grass colour of their column when it is known
+    fn face_color(&self, block: Block, normal: IVec3, grass_color: Option<[f32; 3]>) -> [f32; 3] {
+        let face = self.registry.face(block, normal);
+        return match grass_color {
+            Some(grass_color) if face.grass_tint => grass_color,
+            _ => face.color,
+        };
+    }
+
+    // Classic voxel ambient occlusion, each corner of a face is darkened by the two blocks next to it
+    // and the block diagonal to it, all in the layer in front of the face. Two side blocks hide
+    // the corner completely, no matter the diagonal one.
+    fn face_ao<V: Voxels>(&self, voxels: &V, facing: IVec3, u: usize, v: usize) -> [u8; 4] {
+        let occludes = |du: i32, dv: i32| {
+            let mut offset = IVec3::ZERO;
+            offset[u] = du;
+            offset[v] = dv;
+            return !self.registry.get(voxels.block(facing + offset)).transparent;
+        };
+        return [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(du, dv)| {
+            let side_u = occludes(du, 0);
+            let side_v = occludes(0, dv);
+            if side_u && side_v {
+                return 0;
+            }
+            return 3 - side_u as u8 - side_v as u8 - occludes(du, dv) as u8;
+        });
+    }
+
+    // only the first size² faces of the mask are used, face (i, j) is at i * size + j
+    fn merge_mask(mask: &mut [Option<Face>], size: usize, axis: usize, dir: i32, slice: i32, quads: &mut Vec<Quad>) {
+        let u = (axis + 1) % 3;
+        let v = (axis + 2) % 3;
+        for (face, i, j, width, height) in merge_rectangles(&mut mask[..size * size], size, size) {
+            let mut origin = IVec3::ZERO;
+            origin[axis] = if dir > 0 { slice + 1 } else { slice };
+            origin[u] = i as i32;
+            origin[v] = j as i32;
+            quads.push(Quad {
+                face,
+                axis,
+                dir,
+                origin,
+                width: width as i32,
+                height: height as i32,
+            });
+        }
+    }
+
+    // scale is the size of a cell in blocks
+    fn push_quad(&self, quad: &Quad, scale: i32, vertices: &mut Vec<Vertex>, indices: &mut Vec<VertexIndex>) {
+        let u = (quad.axis + 1) % 3;
+        let v = (quad.axis + 2) % 3;
+        let mut max = quad.origin;
+        max[u] += quad.width;
+        max[v] += quad.height;
+        let min = quad.origin * scale;
+        self.push_face(&quad.face, quad.axis, quad.dir, min.as_vec3(), (max * scale).as_vec3(), vertices, indices);
+    }
+
+    fn push_face(&self, face: &Face, axis: usize, dir: i32, min: Vec3, max: Vec3, vertices: &mut Vec<Vertex>, indices: &mut Vec<VertexIndex>) {
+        let light = [face.light.sky as f32 / MAX_LIGHT as f32, face.light.block as f32 / MAX_LIGHT as f32];
+        push_box_face(axis, dir, min, max, face.color, light, face.ao, vertices, indices);
+    }
+}
+
+// Splits the faces of a mask into as big rectangles of equal faces as possible and clears the mask.
+// Face (i, j) is at i * height + j. Returns the face, its lowest corner and size of every rectangle.
+pub(crate) fn merge_rectangles<T: Copy + PartialEq>(mask: &mut [Option<T>], width: usize, height: usize) -> Vec<(T, usize, usize, usize, usize)> {
+    let mut rectangles = Vec::new();
+    for j in 0..height {
+        let mut i = 0;
+        while i < width {
+            let face = match mask[i * height + j] {
+                Some(face) => face,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let mut rect_width = 1;
+            while i + rect_width < width && mask[(i + rect_width) * height + j] == Some(face) {
+                rect_width += 1;
+            }
+            let mut rect_height = 1;
+            'grow: while j + rect_height < height {
+                for k in i..(i + rect_width) {
+                    if mask[k * height + j + rect_height] != Some(face) {
+                        break 'grow;
+                    }
+                }
+                rect_height += 1;
+            }
+            for k in i..(i + rect_width) {
+                for l in j..(j + rect_height) {
+                    mask[k * height + l] = None;
+                }
+            }
+            rectangles.push((face, i, j, rect_width, rect_height));
+            i += rect_width;
+        }
+    }
+    return rectangles;
+}
+
+// Face of the box between min and max pointing along axis in dir. Ambient occlusion of the corners
+// is at (u, v) = (0, 0), (1, 0), (1, 1), (0, 1) with u and v the next two axes, 0 is the darkest.
+pub(crate) fn push_box_face(axis: usize, dir: i32, min: Vec3, max: Vec3, color: [f32; 3], light: [f32; 2], ao: [u8; 4], vertices: &mut Vec<Vertex>, indices: &mut Vec<VertexIndex>) {
+    let u = (axis + 1) % 3;
+    let v = (axis + 2) % 3;
+    let mut normal = Vec3::ZERO;
+    normal[axis] = dir as f32;
+
+    // corners in the order of ao, listed counter-clockwise when looking at the face from outside
+    let uv = [(0, 0), (1, 0), (1, 1), (0, 1)];
+    let order = if dir > 0 { [0, 1, 2, 3] } else { [0, 3, 2, 1] };
+    let corners = order.map(|i| {
+        let (a, b) = uv[i];
+        let mut corner = if dir > 0 { max } else { min };
+        corner[u] = if a == 0 { min[u] } else { max[u] };
+        corner[v] = if b == 0 { min[v] } else { max[v] };
+        return (corner, ao[i]);
+    });
+    let start = vertices.len() as VertexIndex;
+    for (corner, ao) in corners {
+        vertices.push(Vertex {
+            position: corner.into(),
+            normal: normal.into(),
+            color,
+            light,
+            ao: ao as f32 / 3.0,
+        });
+    }
+    // Triangles are split along the diagonal with brighter corners, otherwise the darkness
+    // of one corner is stretched over the whole quad.
+    let ao = corners.map(|(_, ao)| ao);
+    if ao[0] + ao[2] >= ao[1] + ao[3] {
+        indices.extend_from_slice(&[start, start + 1, start + 2, start, start + 2, start + 3]);
+    } else {
+        indices.extend_from_slice(&[start, start + 1, start + 3, start + 1, start + 2, start + 3]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::terrarin::chunk::ChunkPos;
+    use super::*;
+
+    fn mesher() -> (ChunkMesher, Arc<BlockRegistry>) {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        return (ChunkMesher::new(registry.clone()), registry);
+    }
+
+    #[test]
+    fn full_chunk_is_six_quads() {
+        let (mesher, registry) = mesher();
+        let chunk = Chunk::filled(ChunkPos::new(0, 0, 0), registry.block("stone"));
+        let mesh = mesher.build(&chunk, 7);
+        assert_eq!(mesh.id, 7);
+        assert_eq!(mesh.vertices.len(), 24);
+        assert_eq!(mesh.indices.len(), 36);
+        for normal in FACE_NORMALS {
+            assert_eq!(mesh.vertices.iter().filter(|vertex| vertex.normal == normal.as_vec3().to_array()).count(), 4);
+        }
+    }
+
+    #[test]
+    fn empty_chunk_has_no_faces() {
+        let (mesher, _) = mesher();
+        let mesh = mesher.build(&Chunk::empty(ChunkPos::new(0, 0, 0)), 1);
+        assert!(mesh.vertices.is_empty());
+        assert!(mesh.indices.is_empty());
+    }
+
+    #[test]
+    fn solid_neighbours_hide_border_faces() {
+        let (mesher, registry) = mesher();
+        let stone = registry.block("stone");
+        let pos = ChunkPos::new(-1, 0, 2);
+        let chunk = Chunk::filled(pos, stone);
+        let neighbours: Vec<Chunk> = pos.neighbours().iter().map(|neighbour| Chunk::filled(*neighbour, stone)).collect();
+        let references: Vec<&Chunk> = neighbours.iter().collect();
+        let mesh = mesher.build_with_neighbours(&ChunkNeighbourhood::new(&chunk, &references), 1);
+        assert!(mesh.vertices.is_empty());
+        assert!(mesh.indices.is_empty());
+
+        // only the face towards the missing neighbour is left
+        let without_top: Vec<&Chunk> = neighbours.iter().filter(|neighbour| neighbour.get_position() != ChunkPos::new(-1, 1, 2)).collect();
+        let mesh = mesher.build_with_neighbours(&ChunkNeighbourhood::new(&chunk, &without_top), 1);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices.len(), 6);
+        assert!(mesh.vertices.iter().all(|vertex| vertex.normal == [0.0, 1.0, 0.0] && vertex.position[1] == 16.0));
+    }
+
+    #[test]
+    fn transparent_neighbours_keep_border_faces() {
+        let (mesher, registry) = mesher();
+        let pos = ChunkPos::new(0, 0, 0);
+        let chunk = Chunk::filled(pos, registry.block("stone"));
+        let leaves = Chunk::filled(ChunkPos::new(1, 0, 0), registry.block("leaves"));
+        let mesh = mesher.build_with_neighbours(&ChunkNeighbourhood::new(&chunk, &[&leaves]), 1);
+        assert_eq!(mesh.vertices.len(), 24);
+    }
+
+    #[test]
+    fn grass_tops_take_grass_color() {
+        let (mesher, registry) = mesher();
+        let chunk = Chunk::filled(ChunkPos::new(0, 0, 0), registry.block("grass"));
+        let mut neighbourhood = ChunkNeighbourhood::alone(&chunk);
+        neighbourhood.set_grass_colors(|column| if column.x < 8 { [1.0, 0.0, 0.0] } else { [0.0, 0.0, 1.0] });
+        let side = registry.face(registry.block("grass"), IVec3::X).color;
+        for level in [0, 1] {
+            let mesh = mesher.build_lod(&neighbourhood, level, [0; 6], 1);
+            let top: Vec<&Vertex> = mesh.vertices.iter().filter(|vertex| vertex.normal == [0.0, 1.0, 0.0]).collect();
+            // tops of differently coloured columns are not merged
+            assert_eq!(top.len(), 8);
+            for vertex in top {
+                let min_x = if vertex.color == [1.0, 0.0, 0.0] { 0.0 } else { 8.0 };
+                assert!(vertex.position[0] >= min_x && vertex.position[0] <= min_x + 8.0);
+            }
+            assert!(mesh.vertices.iter().filter(|vertex| vertex.normal[1] == 0.0).all(|vertex| vertex.color == side));
+        }
+
+        // without grass colours the face keeps its own colour
+        let mesh = mesher.build(&chunk, 1);
+        let top = registry.face(registry.block("grass"), IVec3::Y).color;
+        assert!(mesh.vertices.iter().filter(|vertex| vertex.normal == [0.0, 1.0, 0.0]).all(|vertex| vertex.color == top));
+    }
+
+    // area of the faces pointing to the normal
+    fn face_area(mesh: &Mesh, normal: IVec3) -> f32 {
+        let normal = normal.as_vec3();
+        let axis = if normal.x != 0.0 { 0 } else if normal.y != 0.0 { 1 } else { 2 };
+        return mesh.vertices.chunks_exact(4)
+            .filter(|quad| quad[0].normal == normal.to_array())
+            .map(|quad| {
+                let min = quad.iter().fold(Vec3::splat(f32::MAX), |min, vertex| min.min(Vec3::from(vertex.position)));
+                let max = quad.iter().fold(Vec3::splat(f32::MIN), |max, vertex| max.max(Vec3::from(vertex.position)));
+                let mut size = max - min;
+                size[axis] = 1.0;
+                size.x * size.y * size.z
+            })
+            .sum();
+    }
+
+    #[test]
+    fn borders_towards_coarser_neighbours_are_meshed() {
+        let (mesher, registry) = mesher();
+        let stone = registry.block("stone");
+        let chunk = Chunk::filled(ChunkPos::new(0, 0, 0), stone);
+        // too sparse to fill any cell of level 1, so the coarser mesh has nothing on this border
+        let mut sparse = Chunk::empty(ChunkPos::new(1, 0, 0));
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                if (y + z) % 2 == 0 {
+                    sparse.set(0, y, z, stone);
+                }
+            }
+        }
+        let coarse = mesher.build_lod(&ChunkNeighbourhood::new(&sparse, &[&chunk]), 1, [0; 6], 1);
+        assert!(coarse.vertices.is_empty());
+
+        let neighbourhood = ChunkNeighbourhood::new(&chunk, &[&sparse]);
+        let same_level = mesher.build_lod(&neighbourhood, 0, [0; 6], 1);
+        assert_eq!(face_area(&same_level, IVec3::X), 128.0);
+        let mut levels = [0; 6];
+        levels[0] = 1;
+        let next_to_coarse = mesher.build_lod(&neighbourhood, 0, levels, 1);
+        assert_eq!(face_area(&next_to_coarse, IVec3::X), 256.0);
+        // other borders are not changed
+        for normal in &FACE_NORMALS[1..] {
+            assert_eq!(face_area(&next_to_coarse, *normal), face_area(&same_level, *normal));
+        }
+
+        // the same between two downsampled levels, neighbours of the same or a finer level hide faces
+        let solid = Chunk::filled(ChunkPos::new(1, 0, 0), stone);
+        let neighbourhood = ChunkNeighbourhood::new(&chunk, &[&solid]);
+        for (neighbour_level, area) in [(0, 0.0), (1, 0.0), (2, 256.0), (3, 256.0)] {
+            let mesh = mesher.build_lod(&neighbourhood, 1, [neighbour_level, 0, 0, 0, 0, 0], 1);
+            assert_eq!(face_area(&mesh, IVec3::X), area, "neighbour of level {}", neighbour_level);
+        }
+    }
+}
+
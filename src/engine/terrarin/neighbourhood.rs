@@ -0,0 +1,139 @@
+use glam::IVec3;
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::chunk::{Chunk, CHUNK_SIZE, CHUNK_SIZE_I, ChunkPos};
+use crate::engine::terrarin::light::Light;
+
+pub const PADDED_CHUNK_SIZE: usize = CHUNK_SIZE + 2;
+const PADDED_CHUNK_SIZE_I: i32 = PADDED_CHUNK_SIZE as i32;
+
+// Copy of a chunk with one block of padding on each side filled from the chunks around it,
+// so blocks right behind the chunk border can be checked without touching other chunks.
+// Padding of neighbours that are not loaded stays air lit by full sky light.
+pub struct ChunkNeighbourhood {
+    position: ChunkPos,
+    blocks: Vec<Block>,
+    light: Vec<Light>,
+    // grass colour of every block column of the chunk, x * CHUNK_SIZE + z
+    grass_colors: Option<Vec<[f32; 3]>>,
+}
+
+impl ChunkNeighbourhood {
+    // neighbours can be any of the 26 chunks touching this one, other chunks are ignored
+    pub fn new(chunk: &Chunk, neighbours: &[&Chunk]) -> ChunkNeighbourhood {
+        let mut neighbourhood = ChunkNeighbourhood {
+            position: chunk.get_position(),
+            blocks: vec![Block::AIR; PADDED_CHUNK_SIZE * PADDED_CHUNK_SIZE * PADDED_CHUNK_SIZE],
+            light: vec![Light::SKY; PADDED_CHUNK_SIZE * PADDED_CHUNK_SIZE * PADDED_CHUNK_SIZE],
+            grass_colors: None,
+        };
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let local = IVec3::new(x as i32, y as i32, z as i32);
+                    neighbourhood.set_local(local, chunk.get(x, y, z), chunk.light_levels(x, y, z));
+                }
+            }
+        }
+        for neighbour in neighbours {
+            neighbourhood.copy_border(neighbour);
+        }
+        return neighbourhood;
+    }
+
+    // chunk without any neighbours, everything around it is treated as air
+    pub fn alone(chunk: &Chunk) -> ChunkNeighbourhood {
+        return Self::new(chunk, &[]);
+    }
+
+    pub fn get_position(&self) -> ChunkPos {
+        return self.position;
+    }
+
+    // grass colour is looked up once per block column of the chunk, padding uses the nearest column
+    pub fn set_grass_colors<F: Fn(BlockPos) -> [f32; 3]>(&mut self, grass_color: F) {
+        let min = self.position.block_min();
+        let mut colors = Vec::with_capacity(CHUNK_SIZE * CHUNK_SIZE);
+        for x in 0..CHUNK_SIZE_I {
+            for z in 0..CHUNK_SIZE_I {
+                colors.push(grass_color(BlockPos(min.0 + IVec3::new(x, 0, z))));
+            }
+        }
+        self.grass_colors = Some(colors);
+    }
+
+    // None if grass colours were not set, grass tinted faces then keep their own colour
+    pub fn get_grass_color_local(&self, x: i32, z: i32) -> Option<[f32; 3]> {
+        let colors = self.grass_colors.as_ref()?;
+        let x = x.clamp(0, CHUNK_SIZE_I - 1) as usize;
+        let z = z.clamp(0, CHUNK_SIZE_I - 1) as usize;
+        return Some(colors[x * CHUNK_SIZE + z]);
+    }
+
+    // returns air for anything outside of the chunk and its padding
+    pub fn get(&self, pos: BlockPos) -> Block {
+        return self.get_local(pos.0 - self.position.block_min().0);
+    }
+
+    // position relative to the chunk, valid range is -1..=CHUNK_SIZE on each axis
+    pub fn get_local(&self, pos: IVec3) -> Block {
+        return match Self::index(pos) {
+            Some(i) => self.blocks[i],
+            None => Block::AIR,
+        };
+    }
+
+    pub fn get_light(&self, pos: BlockPos) -> Light {
+        return self.get_light_local(pos.0 - self.position.block_min().0);
+    }
+
+    // full sky light outside of the chunk and its padding
+    pub fn get_light_local(&self, pos: IVec3) -> Light {
+        return match Self::index(pos) {
+            Some(i) => self.light[i],
+            None => Light::SKY,
+        };
+    }
+
+    fn set_local(&mut self, pos: IVec3, block: Block, light: Light) {
+        if let Some(i) = Self::index(pos) {
+            self.blocks[i] = block;
+            self.light[i] = light;
+        }
+    }
+
+    fn copy_border(&mut self, neighbour: &Chunk) {
+        let offset = neighbour.get_position().0 - self.position.0;
+        if offset == IVec3::ZERO || offset.abs().max_element() != 1 {
+            return;
+        }
+        // on every axis the neighbour is next to, only its layer touching this chunk lands in the padding
+        let mut min = IVec3::ZERO;
+        let mut max = IVec3::splat(CHUNK_SIZE_I - 1);
+        for axis in 0..3 {
+            if offset[axis] > 0 {
+                max[axis] = 0;
+            } else if offset[axis] < 0 {
+                min[axis] = CHUNK_SIZE_I - 1;
+            }
+        }
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let source = IVec3::new(x, y, z);
+                    let target = source + offset * CHUNK_SIZE_I;
+                    let (x, y, z) = (x as usize, y as usize, z as usize);
+                    self.set_local(target, neighbour.get(x, y, z), neighbour.light_levels(x, y, z));
+                }
+            }
+        }
+    }
+
+    fn index(pos: IVec3) -> Option<usize> {
+        let padded = pos + 1;
+        if padded.min_element() < 0 || padded.max_element() >= PADDED_CHUNK_SIZE_I {
+            return None;
+        }
+        let padded = padded.as_uvec3();
+        return Some(((padded.x as usize * PADDED_CHUNK_SIZE) + padded.y as usize) * PADDED_CHUNK_SIZE + padded.z as usize);
+    }
+}
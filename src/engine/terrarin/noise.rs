@@ -0,0 +1,230 @@
+// Small deterministic random and noise helpers used by world generation.
+// Everything here must only depend on the seed and input, never on generation order.
+
+// SplitMix64, good enough for world generation and trivial to keep stable between versions
+#[derive(Copy, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        return Random { state: seed };
+    }
+
+    // random generator for some specific place in the world, for example a chunk column
+    pub fn at(seed: u64, values: &[i64]) -> Random {
+        return Random::new(hash_seed(seed, values));
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        return mix(self.state);
+    }
+
+    // in range 0.0..1.0
+    pub fn next_f64(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+
+    // in range min..max, max excluded
+    pub fn next_i32(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min {
+            return min;
+        }
+        let range = (max as i64 - min as i64) as u64;
+        return (min as i64 + (self.next_u64() % range) as i64) as i32;
+    }
+}
+
+pub fn hash_seed(seed: u64, values: &[i64]) -> u64 {
+    let mut hash = mix(seed);
+    for value in values {
+        hash = mix(hash ^ (*value as u64).wrapping_mul(0xD6E8FEB86659FD93));
+    }
+    return hash;
+}
+
+fn mix(value: u64) -> u64 {
+    let mut z = value;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    return z ^ (z >> 31);
+}
+
+// Improved Perlin noise with permutation table shuffled by the seed, output is roughly in -1.0..1.0
+pub struct PerlinNoise {
+    permutation: [u8; 512],
+}
+
+impl PerlinNoise {
+    pub fn new(seed: u64) -> PerlinNoise {
+        let mut table = [0u8; 256];
+        for i in 0..256 {
+            table[i] = i as u8;
+        }
+        let mut random = Random::new(seed);
+        for i in (1..256).rev() {
+            let j = (random.next_u64() % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+        let mut permutation = [0u8; 512];
+        for i in 0..512 {
+            permutation[i] = table[i & 255];
+        }
+        return PerlinNoise { permutation };
+    }
+
+    pub fn get2(&self, x: f64, y: f64) -> f64 {
+        let xi = x.floor();
+        let yi = y.floor();
+        let xf = x - xi;
+        let yf = y - yi;
+        let xi = (xi as i64 & 255) as usize;
+        let yi = (yi as i64 & 255) as usize;
+        let u = fade(xf);
+        let v = fade(yf);
+
+        let p = &self.permutation;
+        let aa = p[p[xi] as usize + yi];
+        let ab = p[p[xi] as usize + yi + 1];
+        let ba = p[p[xi + 1] as usize + yi];
+        let bb = p[p[xi + 1] as usize + yi + 1];
+
+        let x1 = lerp(u, grad2(aa, xf, yf), grad2(ba, xf - 1.0, yf));
+        let x2 = lerp(u, grad2(ab, xf, yf - 1.0), grad2(bb, xf - 1.0, yf - 1.0));
+        return lerp(v, x1, x2);
+    }
+
+    pub fn get3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let xi = x.floor();
+        let yi = y.floor();
+        let zi = z.floor();
+        let xf = x - xi;
+        let yf = y - yi;
+        let zf = z - zi;
+        let xi = (xi as i64 & 255) as usize;
+        let yi = (yi as i64 & 255) as usize;
+        let zi = (zi as i64 & 255) as usize;
+        let u = fade(xf);
+        let v = fade(yf);
+        let w = fade(zf);
+
+        let p = &self.permutation;
+        let a = p[xi] as usize + yi;
+        let aa = p[a] as usize + zi;
+        let ab = p[a + 1] as usize + zi;
+        let b = p[xi + 1] as usize + yi;
+        let ba = p[b] as usize + zi;
+        let bb = p[b + 1] as usize + zi;
+
+        let x1 = lerp(u, grad3(p[aa], xf, yf, zf), grad3(p[ba], xf - 1.0, yf, zf));
+        let x2 = lerp(u, grad3(p[ab], xf, yf - 1.0, zf), grad3(p[bb], xf - 1.0, yf - 1.0, zf));
+        let y1 = lerp(v, x1, x2);
+        let x1 = lerp(u, grad3(p[aa + 1], xf, yf, zf - 1.0), grad3(p[ba + 1], xf - 1.0, yf, zf - 1.0));
+        let x2 = lerp(u, grad3(p[ab + 1], xf, yf - 1.0, zf - 1.0), grad3(p[bb + 1], xf - 1.0, yf - 1.0, zf - 1.0));
+        let y2 = lerp(v, x1, x2);
+        return lerp(w, y1, y2);
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    return a + t * (b - a);
+}
+
+fn grad2(hash: u8, x: f64, y: f64) -> f64 {
+    return match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    };
+}
+
+fn grad3(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    return (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v });
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct NoiseSettings {
+    pub octaves: u32,
+    // frequency of the first octave, in noise cycles per block
+    pub frequency: f64,
+    // how much amplitude is kept for each next octave
+    pub persistence: f64,
+    // how much frequency grows for each next octave
+    pub lacunarity: f64,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        return NoiseSettings {
+            octaves: 4,
+            frequency: 1.0 / 64.0,
+            persistence: 0.5,
+            lacunarity: 2.0,
+        };
+    }
+}
+
+// Layered Perlin noise, every octave has its own permutation so they don't line up.
+// Output is normalized back to roughly -1.0..1.0.
+pub struct FractalNoise {
+    settings: NoiseSettings,
+    octaves: Vec<PerlinNoise>,
+    normalization: f64,
+}
+
+impl FractalNoise {
+    pub fn new(seed: u64, settings: NoiseSettings) -> FractalNoise {
+        let octaves: Vec<PerlinNoise> = (0..settings.octaves.max(1))
+            .map(|octave| PerlinNoise::new(hash_seed(seed, &[octave as i64])))
+            .collect();
+        let mut amplitude = 1.0;
+        let mut sum = 0.0;
+        for _ in 0..octaves.len() {
+            sum += amplitude;
+            amplitude *= settings.persistence;
+        }
+        return FractalNoise {
+            settings,
+            octaves,
+            normalization: 1.0 / sum,
+        };
+    }
+
+    pub fn get2(&self, x: f64, y: f64) -> f64 {
+        let mut frequency = self.settings.frequency;
+        let mut amplitude = 1.0;
+        let mut value = 0.0;
+        for octave in &self.octaves {
+            value += octave.get2(x * frequency, y * frequency) * amplitude;
+            frequency *= self.settings.lacunarity;
+            amplitude *= self.settings.persistence;
+        }
+        return value * self.normalization;
+    }
+
+    pub fn get3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let mut frequency = self.settings.frequency;
+        let mut amplitude = 1.0;
+        let mut value = 0.0;
+        for octave in &self.octaves {
+            value += octave.get3(x * frequency, y * frequency, z * frequency) * amplitude;
+            frequency *= self.settings.lacunarity;
+            amplitude *= self.settings.persistence;
+        }
+        return value * self.normalization;
+    }
+}
@@ -0,0 +1,362 @@
+use std::mem::size_of;
+use crate::engine::terrarin::block::Block;
+use crate::engine::terrarin::registry::BlockRegistry;
+
+// Blocks stored as indices into a palette of distinct blocks. Indices are bit-packed and only
+// use as many bits as the palette needs, a chunk made of a single block needs no indices at all.
+// Bits per index are rounded up to a power of two, so indices never span two words and finding
+// one takes only shifts.
+#[derive(Clone)]
+pub struct PalettedStorage {
+    len: usize,
+    palette: Vec<Block>,
+    bits: u32,
+    data: Vec<u64>,
+}
+
+impl PalettedStorage {
+    pub fn filled(len: usize, block: Block) -> PalettedStorage {
+        return PalettedStorage {
+            len,
+            palette: vec![block],
+            bits: 0,
+            data: Vec::new(),
+        };
+    }
+
+    // Rebuilds storage from its parts, for example when loading it from disk.
+    // None if parts don't match or some index points outside of the palette.
+    pub fn from_parts(len: usize, palette: Vec<Block>, bits: u32, data: Vec<u64>) -> Option<PalettedStorage> {
+        if palette.is_empty() || bits != Self::bits_for(palette.len()) {
+            return None;
+        }
+        let storage = PalettedStorage { len, palette, bits, data };
+        if bits == 0 {
+            return Some(storage);
+        }
+        if storage.data.len() != Self::words_for(len, bits) {
+            return None;
+        }
+        if (0..len).any(|i| storage.index_at(i) >= storage.palette.len()) {
+            return None;
+        }
+        return Some(storage);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn palette(&self) -> &[Block] {
+        return &self.palette;
+    }
+
+    pub fn bits_per_block(&self) -> u32 {
+        return self.bits;
+    }
+
+    // bit-packed palette indices, empty if every block is the same
+    pub fn data(&self) -> &[u64] {
+        return &self.data;
+    }
+
+    // Some if every block is the same
+    pub fn single(&self) -> Option<Block> {
+        if self.bits == 0 {
+            return Some(self.palette[0]);
+        }
+        return None;
+    }
+
+    pub fn get(&self, i: usize) -> Block {
+        if self.bits == 0 {
+            return self.palette[0];
+        }
+        return self.palette[self.index_at(i)];
+    }
+
+    pub fn set(&mut self, i: usize, block: Block) {
+        if self.bits == 0 && self.palette[0] == block {
+            return;
+        }
+        let index = match self.palette.iter().position(|entry| *entry == block) {
+            Some(index) => index,
+            None => {
+                self.palette.push(block);
+                let needed = Self::bits_for(self.palette.len());
+                if needed > self.bits {
+                    self.resize(needed);
+                }
+                self.palette.len() - 1
+            }
+        };
+        self.set_index_at(i, index);
+    }
+
+    // Drops palette entries that are no longer used and packs indices again, for example before saving.
+    pub fn compact(&mut self) {
+        if self.bits == 0 {
+            return;
+        }
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..self.len {
+            used[self.index_at(i)] = true;
+        }
+        if used.iter().all(|used| *used) {
+            return;
+        }
+        let blocks: Vec<Block> = (0..self.len).map(|i| self.get(i)).collect();
+        let mut compacted = PalettedStorage::filled(self.len, blocks[0]);
+        for (i, block) in blocks.into_iter().enumerate() {
+            compacted.set(i, block);
+        }
+        *self = compacted;
+    }
+
+    // Palette as block names and states, because ids can change between versions, then bits
+    // per block u8 and the packed palette indices as u64 words, numbers are little endian.
+    pub fn write_named(&self, registry: &BlockRegistry, data: &mut Vec<u8>) {
+        data.extend_from_slice(&(self.palette.len() as u16).to_le_bytes());
+        for block in &self.palette {
+            write_named_block(*block, registry, data);
+        }
+        data.push(self.bits as u8);
+        for word in &self.data {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    // Reads what write_named wrote, indices take up the rest of the data.
+    // Blocks missing from the registry are read as air.
+    pub fn read_named(len: usize, registry: &BlockRegistry, mut data: &[u8]) -> Option<PalettedStorage> {
+        let palette_len = read_u16(&mut data)?;
+        let mut palette = Vec::with_capacity(palette_len as usize);
+        for _ in 0..palette_len {
+            palette.push(read_named_block(registry, &mut data)?);
+        }
+        let (bits, rest) = data.split_first()?;
+        data = rest;
+        if data.len() % 8 != 0 {
+            return None;
+        }
+        let words = data.chunks_exact(8).map(|word| u64::from_le_bytes(word.try_into().unwrap())).collect();
+        return Self::from_parts(len, palette, *bits as u32, words);
+    }
+
+    // bytes used by this storage, including heap allocations
+    pub fn memory_usage(&self) -> usize {
+        return size_of::<Self>() + self.palette.capacity() * size_of::<Block>() + self.data.capacity() * size_of::<u64>();
+    }
+
+    fn bits_for(palette_len: usize) -> u32 {
+        if palette_len <= 1 {
+            return 0;
+        }
+        let bits = usize::BITS - (palette_len - 1).leading_zeros();
+        return bits.next_power_of_two();
+    }
+
+    fn words_for(len: usize, bits: u32) -> usize {
+        let per_word = (64 / bits) as usize;
+        return (len + per_word - 1) / per_word;
+    }
+
+    // log2 of indices per word
+    fn per_word_exp(&self) -> u32 {
+        return 6 - self.bits.trailing_zeros();
+    }
+
+    fn resize(&mut self, bits: u32) {
+        let old = self.clone();
+        self.bits = bits;
+        self.data = vec![0; Self::words_for(self.len, bits)];
+        if old.bits == 0 {
+            return;
+        }
+        for i in 0..self.len {
+            self.set_index_at(i, old.index_at(i));
+        }
+    }
+
+    fn index_at(&self, i: usize) -> usize {
+        let exp = self.per_word_exp();
+        let word = self.data[i >> exp];
+        let shift = (i & ((1 << exp) - 1)) as u32 * self.bits;
+        return ((word >> shift) & Self::mask(self.bits)) as usize;
+    }
+
+    fn set_index_at(&mut self, i: usize, index: usize) {
+        let exp = self.per_word_exp();
+        let shift = (i & ((1 << exp) - 1)) as u32 * self.bits;
+        let mask = Self::mask(self.bits) << shift;
+        let word = &mut self.data[i >> exp];
+        *word = (*word & !mask) | (((index as u64) << shift) & mask);
+    }
+
+    fn mask(bits: u32) -> u64 {
+        if bits == 64 {
+            return u64::MAX;
+        }
+        return (1u64 << bits) - 1;
+    }
+}
+
+// block name length u16, name and state u16
+pub(crate) fn write_named_block(block: Block, registry: &BlockRegistry, data: &mut Vec<u8>) {
+    let name = registry.name(block).as_bytes();
+    data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    data.extend_from_slice(name);
+    data.extend_from_slice(&block.state.to_le_bytes());
+}
+
+// blocks missing from the registry are read as air
+pub(crate) fn read_named_block(registry: &BlockRegistry, data: &mut &[u8]) -> Option<Block> {
+    let name_len = read_u16(data)? as usize;
+    if data.len() < name_len {
+        return None;
+    }
+    let (name, rest) = data.split_at(name_len);
+    *data = rest;
+    let state = read_u16(data)?;
+    return match registry.by_name(std::str::from_utf8(name).ok()?) {
+        Some(block) => Some(block.with_state(state)),
+        None => Some(Block::AIR),
+    };
+}
+
+pub(crate) fn read_u16(data: &mut &[u8]) -> Option<u16> {
+    if data.len() < 2 {
+        return None;
+    }
+    let value = u16::from_le_bytes([data[0], data[1]]);
+    *data = &data[2..];
+    return Some(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hint::black_box;
+    use std::time::Instant;
+    use super::*;
+
+    const LEN: usize = 4096;
+
+    fn registry() -> BlockRegistry {
+        return BlockRegistry::load("resources/blocks.json").unwrap();
+    }
+
+    #[test]
+    fn bits_grow_with_palette() {
+        let mut storage = PalettedStorage::filled(LEN, Block::AIR);
+        assert_eq!(storage.bits_per_block(), 0);
+        assert!(storage.data().is_empty());
+        // palette length at which bits per block grow and the bits it grows to
+        let growth = [(2, 1), (3, 2), (5, 4), (17, 8), (257, 16)];
+        for palette_len in 2..=300usize {
+            let i = (palette_len * 37) % LEN;
+            storage.set(i, Block::new(1).with_state(palette_len as u16));
+            assert_eq!(storage.palette().len(), palette_len);
+            let expected = growth.iter().rev().find(|(len, _)| palette_len >= *len).unwrap().1;
+            assert_eq!(storage.bits_per_block(), expected, "palette of {} blocks", palette_len);
+            assert_eq!(storage.data().len(), LEN * expected as usize / 64);
+            // blocks set before growing keep their values
+            for earlier in 2..=palette_len {
+                assert_eq!(storage.get((earlier * 37) % LEN), Block::new(1).with_state(earlier as u16));
+            }
+            assert_eq!(storage.get(1), Block::AIR);
+        }
+    }
+
+    #[test]
+    fn named_blocks_round_trip() {
+        let registry = registry();
+        let log_x = registry.with_state_name(registry.block("log"), "axis", "x").unwrap();
+        let mut storage = PalettedStorage::filled(LEN, registry.block("stone"));
+        storage.set(0, log_x);
+        storage.set(100, registry.block("water"));
+        storage.set(LEN - 1, Block::AIR);
+        let mut data = Vec::new();
+        storage.write_named(&registry, &mut data);
+
+        let read = PalettedStorage::read_named(LEN, &registry, &data).unwrap();
+        assert_eq!(read.palette(), storage.palette());
+        assert_eq!(read.bits_per_block(), storage.bits_per_block());
+        assert!((0..LEN).all(|i| read.get(i) == storage.get(i)));
+        assert_eq!(read.get(0), log_x);
+
+        let mut single = Vec::new();
+        PalettedStorage::filled(LEN, log_x).write_named(&registry, &mut single);
+        assert_eq!(PalettedStorage::read_named(LEN, &registry, &single).unwrap().single(), Some(log_x));
+
+        assert!(PalettedStorage::read_named(LEN, &registry, &data[..data.len() - 3]).is_none());
+        assert!(PalettedStorage::read_named(LEN, &registry, &data[..5]).is_none());
+        assert!(PalettedStorage::read_named(LEN + 1000, &registry, &data).is_none());
+    }
+
+    #[test]
+    fn unknown_names_are_read_as_air() {
+        let registry = registry();
+        let mut data = Vec::new();
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&(b"glass".len() as u16).to_le_bytes());
+        data.extend_from_slice(b"glass");
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.push(0);
+        assert_eq!(PalettedStorage::read_named(LEN, &registry, &data).unwrap().single(), Some(Block::AIR));
+    }
+
+    // Compares memory and access speed with a plain array of blocks,
+    // run with cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn compare_with_dense_array() {
+        for kinds in [1, 4, 16, 200] {
+            let blocks: Vec<Block> = (0..LEN).map(|i| Block::new(((i * 7 + i / 16) % kinds) as u16)).collect();
+            let mut dense = Box::new([Block::AIR; LEN]);
+            let mut paletted = PalettedStorage::filled(LEN, Block::AIR);
+            let rounds = 2000;
+
+            let start = Instant::now();
+            for _ in 0..rounds {
+                for (i, block) in blocks.iter().enumerate() {
+                    dense[i] = black_box(*block);
+                }
+            }
+            let dense_set = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..rounds {
+                for (i, block) in blocks.iter().enumerate() {
+                    paletted.set(i, black_box(*block));
+                }
+            }
+            let paletted_set = start.elapsed();
+
+            let start = Instant::now();
+            let mut dense_sum = 0u64;
+            for _ in 0..rounds {
+                for i in 0..LEN {
+                    dense_sum += black_box(&dense)[i].id as u64;
+                }
+            }
+            let dense_get = start.elapsed();
+            let start = Instant::now();
+            let mut paletted_sum = 0u64;
+            for _ in 0..rounds {
+                for i in 0..LEN {
+                    paletted_sum += black_box(&paletted).get(i).id as u64;
+                }
+            }
+            let paletted_get = start.elapsed();
+            assert_eq!(dense_sum, paletted_sum);
+
+            let dense_memory = size_of::<[Block; LEN]>();
+            println!("{} kinds of blocks: {} bits per block", kinds, paletted.bits_per_block());
+            println!("  memory: dense {} bytes, paletted {} bytes", dense_memory, paletted.memory_usage());
+            println!("  get: dense {:?}, paletted {:?} for {} blocks", dense_get, paletted_get, rounds * LEN);
+            println!("  set: dense {:?}, paletted {:?} for {} blocks", dense_set, paletted_set, rounds * LEN);
+            if kinds <= 16 {
+                assert!(paletted.memory_usage() < dense_memory / 2);
+            }
+        }
+    }
+}
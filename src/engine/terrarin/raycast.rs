@@ -0,0 +1,275 @@
+use glam::{IVec3, Vec3};
+use crate::engine::object::transform::Pos;
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::chunk::ChunkPos;
+use crate::engine::terrarin::world::GameWorld;
+
+#[derive(Debug, Copy, Clone)]
+pub struct RaycastHit {
+    pub block: BlockPos,
+    // normal of the face the ray entered through, zero if the ray started inside of the block
+    pub face: IVec3,
+    pub point: Pos,
+    pub distance: f32,
+}
+
+impl RaycastHit {
+    // where a block placed against the hit face goes
+    pub fn adjacent(&self) -> BlockPos {
+        return BlockPos(self.block.0 + self.face);
+    }
+}
+
+// Walks every block the ray passes through in order, using the DDA from "A Fast Voxel Traversal
+// Algorithm" by Amanatides and Woo. Filter decides which blocks stop the ray, blocks in chunks
+// that are not loaded never do, so the ray ends once it leaves the box around all loaded chunks.
+// Direction doesn't have to be normalized.
+pub fn raycast<F: Fn(Block) -> bool>(world: &GameWorld, origin: Pos, dir: Vec3, max_dist: f32, filter: F) -> Option<RaycastHit> {
+    // scaled to its largest component first, so lengths of tiny directions don't underflow to zero
+    let largest = dir.abs().max_element();
+    if largest == 0.0 || !dir.is_finite() || !origin.0.is_finite() || !max_dist.is_finite() {
+        return None;
+    }
+    let mut chunks = world.loaded_positions();
+    let first = chunks.next()?;
+    let (min_chunk, max_chunk) = chunks.fold((first.0, first.0), |(min, max), pos| (min.min(pos.0), max.max(pos.0)));
+    let loaded_min = ChunkPos(min_chunk).block_min().0;
+    let loaded_max = ChunkPos(max_chunk).block_max().0;
+    let dir = (dir / largest).normalize();
+    let origin = origin.0;
+
+    let mut block = origin.floor().as_ivec3();
+    let mut step = [0; 3];
+    // distance along the ray to the next block border on each axis
+    let mut t_max = [f32::INFINITY; 3];
+    // distance along the ray between block borders on each axis
+    let mut t_delta = [f32::INFINITY; 3];
+    for axis in 0..3 {
+        if dir[axis] > 0.0 {
+            step[axis] = 1;
+            t_max[axis] = (block[axis] as f32 + 1.0 - origin[axis]) / dir[axis];
+            t_delta[axis] = 1.0 / dir[axis];
+        } else if dir[axis] < 0.0 {
+            step[axis] = -1;
+            t_max[axis] = (origin[axis] - block[axis] as f32) / -dir[axis];
+            t_delta[axis] = 1.0 / -dir[axis];
+        }
+    }
+
+    let mut face = IVec3::ZERO;
+    let mut distance = 0.0;
+    while distance <= max_dist {
+        // outside of the loaded box and moving away from it on some axis
+        let leaving = (0..3).any(|axis| {
+            return (block[axis] < loaded_min[axis] && step[axis] <= 0) || (block[axis] > loaded_max[axis] && step[axis] >= 0);
+        });
+        if leaving {
+            return None;
+        }
+        let pos = BlockPos(block);
+        if let Some(hit) = world.get_block(pos) {
+            if filter(hit) {
+                return Some(RaycastHit {
+                    block: pos,
+                    face,
+                    point: Pos(origin + dir * distance),
+                    distance,
+                });
+            }
+        }
+        let axis = if t_max[0] < t_max[1] {
+            if t_max[0] < t_max[2] { 0 } else { 2 }
+        } else {
+            if t_max[1] < t_max[2] { 1 } else { 2 }
+        };
+        distance = t_max[axis];
+        block[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+        face = IVec3::ZERO;
+        face[axis] = -step[axis];
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::engine::terrarin::block::FACE_NORMALS;
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use crate::engine::terrarin::registry::BlockRegistry;
+    use super::*;
+
+    // loaded chunks from -2 to 1 on every axis, all air
+    fn empty_world() -> (GameWorld, Block) {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        let stone = registry.block("stone");
+        let mut world = GameWorld::new(Box::new(FlatEarthGenerator::new(-100, -100, &registry)), registry);
+        for x in -2..=1 {
+            for y in -2..=1 {
+                for z in -2..=1 {
+                    world.chunk_at(ChunkPos::new(x, y, z));
+                }
+            }
+        }
+        return (world, stone);
+    }
+
+    fn solid(block: Block) -> bool {
+        return !block.is_air();
+    }
+
+    fn cast(world: &GameWorld, origin: Vec3, dir: Vec3, max_dist: f32) -> Option<RaycastHit> {
+        return raycast(world, Pos(origin), dir, max_dist, solid);
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn axis_aligned_rays_hit_the_facing_side() {
+        let (mut world, stone) = empty_world();
+        let origin = Vec3::new(0.5, 0.5, 0.5);
+        for normal in FACE_NORMALS {
+            let target = BlockPos(normal * 5);
+            world.set_block(target, stone);
+            let hit = cast(&world, origin, normal.as_vec3() * 3.0, 20.0).unwrap();
+            assert_eq!(hit.block, target);
+            assert_eq!(hit.face, -normal);
+            assert_eq!(hit.adjacent(), BlockPos(normal * 4));
+            assert_close(hit.distance, 4.5);
+            assert!(hit.point.0.abs_diff_eq(origin + normal.as_vec3() * 4.5, 1e-4));
+        }
+    }
+
+    #[test]
+    fn diagonal_rays_match_marching() {
+        let (mut world, stone) = empty_world();
+        let mut state = 0x9e3779b97f4a7c15u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            return (state >> 40) as f32 / (1u64 << 24) as f32;
+        };
+        for _ in 0..400 {
+            let pos = IVec3::new((random() * 40.0) as i32 - 20, (random() * 40.0) as i32 - 20, (random() * 40.0) as i32 - 20);
+            world.set_block(BlockPos(pos), stone);
+        }
+        let mut hits = 0;
+        for _ in 0..300 {
+            let origin = Vec3::new(random() * 30.0 - 15.0, random() * 30.0 - 15.0, random() * 30.0 - 15.0);
+            let dir = Vec3::new(random() * 2.0 - 1.0, random() * 2.0 - 1.0, random() * 2.0 - 1.0);
+            let hit = cast(&world, origin, dir, 12.0);
+
+            // first solid block found by small steps along the ray, and the block before it
+            let step = 0.001;
+            let mut expected = None;
+            let mut previous = origin.floor().as_ivec3();
+            let mut t = 0.0;
+            while t <= 12.0 {
+                let block = (origin + dir.normalize() * t).floor().as_ivec3();
+                if solid(world.get_block(BlockPos(block)).unwrap()) {
+                    expected = Some((block, previous - block, t));
+                    break;
+                }
+                previous = block;
+                t += step;
+            }
+            match (hit, expected) {
+                (Some(hit), Some((block, face, t))) => {
+                    hits += 1;
+                    assert_eq!(hit.block.0, block);
+                    if t > 0.0 {
+                        assert_eq!(hit.face, face);
+                    }
+                    assert!(hit.distance <= t && t - hit.distance <= step * 2.0);
+                }
+                (None, None) => {}
+                (hit, expected) => panic!("ray from {} along {}: hit {:?}, expected {:?}", origin, dir, hit, expected),
+            }
+        }
+        assert!(hits > 30);
+    }
+
+    #[test]
+    fn origins_on_chunk_borders() {
+        let (mut world, stone) = empty_world();
+        let corner = Vec3::new(-16.0, 0.0, -16.0);
+        world.set_block(BlockPos::new(-17, 0, -16), stone);
+        // the block the origin is on the border of is entered right away
+        let hit = cast(&world, corner, -Vec3::X, 10.0).unwrap();
+        assert_eq!(hit.block, BlockPos::new(-17, 0, -16));
+        assert_eq!(hit.face, IVec3::X);
+        assert_close(hit.distance, 0.0);
+
+        world.set_block(BlockPos::new(-1, 0, -16), stone);
+        let hit = cast(&world, corner, Vec3::X, 20.0).unwrap();
+        assert_eq!(hit.block, BlockPos::new(-1, 0, -16));
+        assert_eq!(hit.face, -IVec3::X);
+        assert_close(hit.distance, 15.0);
+
+        world.set_block(BlockPos::new(-16, -3, -16), stone);
+        let hit = cast(&world, corner + Vec3::new(0.5, 0.0, 0.5), -Vec3::Y, 20.0).unwrap();
+        assert_eq!(hit.block, BlockPos::new(-16, -3, -16));
+        assert_eq!(hit.face, IVec3::Y);
+        assert_close(hit.distance, 2.0);
+
+        // rays starting inside of a block hit it with no face
+        world.set_block(BlockPos::new(-16, 0, -16), stone);
+        let hit = cast(&world, corner, Vec3::new(1.0, 1.0, 1.0), 10.0).unwrap();
+        assert_eq!(hit.block, BlockPos::new(-16, 0, -16));
+        assert_eq!(hit.face, IVec3::ZERO);
+        assert_close(hit.distance, 0.0);
+        assert_eq!(hit.point.0, corner);
+    }
+
+    #[test]
+    fn max_dist_cuts_the_ray() {
+        let (mut world, stone) = empty_world();
+        world.set_block(BlockPos::new(-6, -1, 2), stone);
+        let origin = Vec3::new(-0.5, -0.5, 2.5);
+        assert!(cast(&world, origin, -Vec3::X, 4.4).is_none());
+        assert_close(cast(&world, origin, -Vec3::X, 4.5).unwrap().distance, 4.5);
+        // filter decides what stops the ray
+        assert!(raycast(&world, Pos(origin), -Vec3::X, 10.0, |block| block.id == 1000).is_none());
+        // chunks that are not loaded don't stop it
+        assert!(cast(&world, origin, Vec3::Y, 1000.0).is_none());
+    }
+
+    #[test]
+    fn degenerate_rays_hit_nothing() {
+        let (mut world, stone) = empty_world();
+        world.set_block(BlockPos::new(0, 0, 0), stone);
+        let origin = Vec3::new(0.5, 0.5, 0.5);
+        assert!(cast(&world, origin, Vec3::ZERO, 10.0).is_none());
+        assert!(cast(&world, origin, Vec3::new(f32::NAN, 0.0, 1.0), 10.0).is_none());
+        assert!(cast(&world, origin, Vec3::new(f32::INFINITY, 0.0, 0.0), 10.0).is_none());
+        assert!(cast(&world, Vec3::new(f32::NAN, 0.5, 0.5), Vec3::X, 10.0).is_none());
+        assert!(cast(&world, Vec3::new(0.5, f32::NEG_INFINITY, 0.5), Vec3::X, 10.0).is_none());
+        // tiny directions are normalized
+        assert!(cast(&world, origin, Vec3::new(0.0, 1e-30, 0.0), 10.0).is_some());
+    }
+
+    #[test]
+    fn rays_end_outside_of_loaded_chunks() {
+        let (mut world, stone) = empty_world();
+        let origin = Vec3::new(0.5, 0.5, 0.5);
+        assert!(cast(&world, origin, Vec3::X, f32::INFINITY).is_none());
+        assert!(cast(&world, origin, Vec3::X, f32::NAN).is_none());
+        // would walk through billions of blocks if it didn't stop at the last loaded chunk
+        assert!(cast(&world, origin, Vec3::new(1.0, 0.3, -0.2), f32::MAX).is_none());
+        assert!(cast(&world, Vec3::new(1e6, 0.5, 0.5), Vec3::X, f32::MAX).is_none());
+
+        // rays starting far outside still reach the loaded chunks
+        world.set_block(BlockPos::new(-20, 3, 7), stone);
+        let hit = cast(&world, Vec3::new(-1e5, 3.5, 7.5), Vec3::X, 1e6).unwrap();
+        assert_eq!(hit.block, BlockPos::new(-20, 3, 7));
+        assert_eq!(hit.face, -IVec3::X);
+
+        let mut empty = GameWorld::new(Box::new(FlatEarthGenerator::new(0, 0, world.registry())), world.registry().clone());
+        assert!(cast(&empty, origin, Vec3::X, 10.0).is_none());
+        empty.chunk_at(ChunkPos::new(0, -1, 0));
+        assert!(cast(&empty, origin, -Vec3::Y, 10.0).is_some());
+    }
+}
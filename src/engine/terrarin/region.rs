@@ -0,0 +1,531 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use glam::IVec3;
+use crate::engine::terrarin::block::BlockPos;
+use crate::engine::terrarin::chunk::{Chunk, ChunkPos, CHUNK_SIZE, CHUNK_SIZE_I, CHUNK_VOLUME};
+use crate::engine::terrarin::features::{FeatureOwner, FeatureOwners, FeatureWrite};
+use crate::engine::terrarin::palette::{PalettedStorage, read_named_block, read_u16, write_named_block};
+use crate::engine::terrarin::registry::BlockRegistry;
+
+// Region file layout, numbers are little endian:
+//   magic "AMRG" and format version u32
+//   offset table with an entry for every chunk of the region, first sector u32 and sector count u32,
+//   both are 0 for chunks that were never saved
+//   chunks aligned to sectors, each is its compressed length u32 followed by zlib compressed data
+// Compressed chunk data is:
+//   feature owner count u32, each is the block index in the chunk u16, priority u64 and original block
+//   pending feature count u32, each is the block index u16, block, priority u64 and feature index u32
+//   u8 that is 1 if the chunk was generated, then its block storage written by PalettedStorage::write_named
+// Blocks are written as their name and state, see write_named_block.
+pub const REGION_VERSION: u32 = 2;
+const REGION_MAGIC: [u8; 4] = *b"AMRG";
+
+// regions are cubes of 8x8x8 chunks
+pub const REGION_SIZE_EXP: u32 = 3;
+pub const REGION_SIZE: i32 = 1 << REGION_SIZE_EXP;
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+// compressed chunks are mostly a few hundred bytes
+const SECTOR_SIZE: u64 = 512;
+const HEADER_SIZE: u64 = 8 + REGION_CHUNKS as u64 * 8;
+const HEADER_SECTORS: u64 = (HEADER_SIZE + SECTOR_SIZE - 1) / SECTOR_SIZE;
+
+#[derive(Debug)]
+pub enum RegionError {
+    Io(std::io::Error),
+    NotARegion(PathBuf),
+    UnsupportedVersion(u32),
+    CorruptedChunk(ChunkPos),
+}
+
+impl Display for RegionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            RegionError::Io(error) => write!(f, "failed to access region file: {}", error),
+            RegionError::NotARegion(path) => write!(f, "'{}' is not a region file", path.display()),
+            RegionError::UnsupportedVersion(version) => write!(f, "region format version {} is not supported, expected {}", version, REGION_VERSION),
+            RegionError::CorruptedChunk(pos) => write!(f, "saved data of chunk {:?} is corrupted", pos.0),
+        };
+    }
+}
+
+impl From<std::io::Error> for RegionError {
+    fn from(error: std::io::Error) -> RegionError {
+        return RegionError::Io(error);
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct ChunkEntry {
+    sector: u32,
+    sectors: u32,
+}
+
+struct RegionFile {
+    file: File,
+    entries: Vec<ChunkEntry>,
+}
+
+impl RegionFile {
+    fn open(path: &Path) -> Result<RegionFile, RegionError> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+        let mut entries = vec![ChunkEntry::default(); REGION_CHUNKS];
+        if file.metadata()?.len() == 0 {
+            let mut header = Vec::with_capacity((HEADER_SECTORS * SECTOR_SIZE) as usize);
+            header.extend_from_slice(&REGION_MAGIC);
+            header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+            header.resize((HEADER_SECTORS * SECTOR_SIZE) as usize, 0);
+            file.write_all(&header)?;
+            return Ok(RegionFile { file, entries });
+        }
+
+        let mut header = vec![0; HEADER_SIZE as usize];
+        if let Err(error) = file.read_exact(&mut header) {
+            if error.kind() == ErrorKind::UnexpectedEof {
+                return Err(RegionError::NotARegion(path.to_path_buf()));
+            }
+            return Err(RegionError::Io(error));
+        }
+        if header[0..4] != REGION_MAGIC {
+            return Err(RegionError::NotARegion(path.to_path_buf()));
+        }
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != REGION_VERSION {
+            return Err(RegionError::UnsupportedVersion(version));
+        }
+        for (i, entry) in entries.iter_mut().enumerate() {
+            let at = 8 + i * 8;
+            entry.sector = u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+            entry.sectors = u32::from_le_bytes([header[at + 4], header[at + 5], header[at + 6], header[at + 7]]);
+        }
+        return Ok(RegionFile { file, entries });
+    }
+
+    // compressed chunk data, None if chunk was never saved
+    fn read(&mut self, index: usize) -> std::io::Result<Option<Vec<u8>>> {
+        let entry = self.entries[index];
+        if entry.sector == 0 {
+            return Ok(None);
+        }
+        self.file.seek(SeekFrom::Start(entry.sector as u64 * SECTOR_SIZE))?;
+        let mut length = [0; 4];
+        self.file.read_exact(&mut length)?;
+        let length = u32::from_le_bytes(length) as u64;
+        if length + 4 > entry.sectors as u64 * SECTOR_SIZE {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "chunk is longer than its sectors"));
+        }
+        let mut data = vec![0; length as usize];
+        self.file.read_exact(&mut data)?;
+        return Ok(Some(data));
+    }
+
+    // Chunk is written over its old data if it still fits, otherwise it is moved to the end of
+    // the file. Sectors left behind by moved chunks are not reused.
+    fn write(&mut self, index: usize, data: &[u8]) -> std::io::Result<()> {
+        let needed = ((4 + data.len() as u64 + SECTOR_SIZE - 1) / SECTOR_SIZE) as u32;
+        let old = self.entries[index];
+        let entry = if old.sector != 0 && old.sectors >= needed {
+            old
+        } else {
+            let end = self.file.seek(SeekFrom::End(0))?;
+            let sector = ((end + SECTOR_SIZE - 1) / SECTOR_SIZE).max(HEADER_SECTORS);
+            ChunkEntry { sector: sector as u32, sectors: needed }
+        };
+
+        let mut sectors = Vec::with_capacity((needed as u64 * SECTOR_SIZE) as usize);
+        sectors.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        sectors.extend_from_slice(data);
+        sectors.resize((needed as u64 * SECTOR_SIZE) as usize, 0);
+        self.file.seek(SeekFrom::Start(entry.sector as u64 * SECTOR_SIZE))?;
+        self.file.write_all(&sectors)?;
+
+        let mut table_entry = [0; 8];
+        table_entry[0..4].copy_from_slice(&entry.sector.to_le_bytes());
+        table_entry[4..8].copy_from_slice(&entry.sectors.to_le_bytes());
+        self.file.seek(SeekFrom::Start(8 + index as u64 * 8))?;
+        self.file.write_all(&table_entry)?;
+        self.entries[index] = entry;
+        return Ok(());
+    }
+}
+
+// Everything saved for a chunk position, see GameWorld for how features are kept.
+#[derive(Default)]
+pub struct SavedChunk {
+    // None if the chunk was never generated, only feature blocks its neighbours put into it were saved
+    pub chunk: Option<Chunk>,
+    // which feature wrote each block of the chunk and what was there before
+    pub feature_owners: FeatureOwners,
+    // feature blocks waiting for the chunk to be generated
+    pub pending_features: Vec<FeatureWrite>,
+}
+
+// Saves chunks into region files inside of a directory, region files are opened when first needed.
+pub struct RegionStorage {
+    directory: PathBuf,
+    registry: Arc<BlockRegistry>,
+    regions: HashMap<IVec3, RegionFile>,
+}
+
+impl RegionStorage {
+    pub fn open<P: AsRef<Path>>(directory: P, registry: Arc<BlockRegistry>) -> Result<RegionStorage, RegionError> {
+        fs::create_dir_all(directory.as_ref())?;
+        return Ok(RegionStorage {
+            directory: directory.as_ref().to_path_buf(),
+            registry,
+            regions: HashMap::new(),
+        });
+    }
+
+    pub fn directory(&self) -> &Path {
+        return &self.directory;
+    }
+
+    // None if nothing was saved for the chunk
+    pub fn load_chunk(&mut self, pos: ChunkPos) -> Result<Option<SavedChunk>, RegionError> {
+        let region_pos = Self::region_pos(pos);
+        if !self.regions.contains_key(&region_pos) && !self.region_path(region_pos).exists() {
+            return Ok(None);
+        }
+        let compressed = match self.region(region_pos)?.read(Self::index_in_region(pos))? {
+            Some(compressed) => compressed,
+            None => return Ok(None),
+        };
+        let mut data = Vec::new();
+        if ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut data).is_err() {
+            return Err(RegionError::CorruptedChunk(pos));
+        }
+        return match self.decode(pos, &data) {
+            Some(saved) => Ok(Some(saved)),
+            None => Err(RegionError::CorruptedChunk(pos)),
+        };
+    }
+
+    // feature blocks still waiting for the chunk are dropped, they must have been applied already
+    pub fn save_chunk(&mut self, chunk: &Chunk, feature_owners: &FeatureOwners) -> Result<(), RegionError> {
+        return self.write(chunk.get_position(), Some(chunk), feature_owners, &[]);
+    }
+
+    // Adds feature blocks to the ones waiting for a chunk that is not loaded, blocks that
+    // are already saved are skipped. Everything else saved for the chunk is kept.
+    pub fn save_pending_features(&mut self, pos: ChunkPos, writes: &[FeatureWrite]) -> Result<(), RegionError> {
+        let mut saved = self.load_chunk(pos)?.unwrap_or_default();
+        for write in writes {
+            if !saved.pending_features.contains(write) {
+                saved.pending_features.push(*write);
+            }
+        }
+        return self.write(pos, saved.chunk.as_ref(), &saved.feature_owners, &saved.pending_features);
+    }
+
+    fn write(&mut self, pos: ChunkPos, chunk: Option<&Chunk>, feature_owners: &FeatureOwners, pending_features: &[FeatureWrite]) -> Result<(), RegionError> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.encode(pos, chunk, feature_owners, pending_features))?;
+        let compressed = encoder.finish()?;
+        self.region(Self::region_pos(pos))?.write(Self::index_in_region(pos), &compressed)?;
+        return Ok(());
+    }
+
+    fn region(&mut self, region_pos: IVec3) -> Result<&mut RegionFile, RegionError> {
+        if !self.regions.contains_key(&region_pos) {
+            let region = RegionFile::open(&self.region_path(region_pos))?;
+            self.regions.insert(region_pos, region);
+        }
+        return Ok(self.regions.get_mut(&region_pos).unwrap());
+    }
+
+    fn region_path(&self, region_pos: IVec3) -> PathBuf {
+        return self.directory.join(format!("r.{}.{}.{}.region", region_pos.x, region_pos.y, region_pos.z));
+    }
+
+    fn region_pos(pos: ChunkPos) -> IVec3 {
+        return pos.0 >> REGION_SIZE_EXP;
+    }
+
+    fn index_in_region(pos: ChunkPos) -> usize {
+        let local = pos.0 & (REGION_SIZE - 1);
+        return ((local.x * REGION_SIZE + local.y) * REGION_SIZE + local.z) as usize;
+    }
+
+    fn encode(&self, pos: ChunkPos, chunk: Option<&Chunk>, feature_owners: &FeatureOwners, pending_features: &[FeatureWrite]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(feature_owners.len() as u32).to_le_bytes());
+        for (block_pos, owner) in feature_owners {
+            data.extend_from_slice(&Self::index_in_chunk(pos, *block_pos).to_le_bytes());
+            data.extend_from_slice(&owner.priority.to_le_bytes());
+            write_named_block(owner.original, &self.registry, &mut data);
+        }
+        data.extend_from_slice(&(pending_features.len() as u32).to_le_bytes());
+        for write in pending_features {
+            data.extend_from_slice(&Self::index_in_chunk(pos, write.pos).to_le_bytes());
+            write_named_block(write.block, &self.registry, &mut data);
+            data.extend_from_slice(&write.priority.to_le_bytes());
+            data.extend_from_slice(&(write.feature as u32).to_le_bytes());
+        }
+        match chunk {
+            Some(chunk) => {
+                data.push(1);
+                let mut blocks = chunk.storage().clone();
+                blocks.compact();
+                blocks.write_named(&self.registry, &mut data);
+            }
+            None => data.push(0),
+        }
+        return data;
+    }
+
+    fn decode(&self, pos: ChunkPos, mut data: &[u8]) -> Option<SavedChunk> {
+        let mut saved = SavedChunk::default();
+        for _ in 0..read_u32(&mut data)? {
+            let block_pos = Self::block_in_chunk(pos, read_u16(&mut data)?)?;
+            let priority = read_u64(&mut data)?;
+            let original = read_named_block(&self.registry, &mut data)?;
+            saved.feature_owners.insert(block_pos, FeatureOwner { priority, original });
+        }
+        for _ in 0..read_u32(&mut data)? {
+            let block_pos = Self::block_in_chunk(pos, read_u16(&mut data)?)?;
+            let block = read_named_block(&self.registry, &mut data)?;
+            let priority = read_u64(&mut data)?;
+            let feature = read_u32(&mut data)? as usize;
+            saved.pending_features.push(FeatureWrite { pos: block_pos, block, priority, feature });
+        }
+        let (generated, rest) = data.split_first()?;
+        if *generated == 1 {
+            let blocks = PalettedStorage::read_named(CHUNK_VOLUME, &self.registry, rest)?;
+            saved.chunk = Some(Chunk::from_storage(pos, blocks)?);
+        } else if *generated != 0 || !rest.is_empty() {
+            return None;
+        }
+        return Some(saved);
+    }
+
+    fn index_in_chunk(pos: ChunkPos, block_pos: BlockPos) -> u16 {
+        let local = block_pos.0 - pos.block_min().0;
+        return ((local.x * CHUNK_SIZE_I + local.y) * CHUNK_SIZE_I + local.z) as u16;
+    }
+
+    fn block_in_chunk(pos: ChunkPos, index: u16) -> Option<BlockPos> {
+        let index = index as usize;
+        if index >= CHUNK_VOLUME {
+            return None;
+        }
+        let local = IVec3::new((index / (CHUNK_SIZE * CHUNK_SIZE)) as i32, (index / CHUNK_SIZE % CHUNK_SIZE) as i32, (index % CHUNK_SIZE) as i32);
+        return Some(BlockPos(pos.block_min().0 + local));
+    }
+}
+
+fn read_u32(data: &mut &[u8]) -> Option<u32> {
+    if data.len() < 4 {
+        return None;
+    }
+    let value = u32::from_le_bytes(data[..4].try_into().unwrap());
+    *data = &data[4..];
+    return Some(value);
+}
+
+fn read_u64(data: &mut &[u8]) -> Option<u64> {
+    if data.len() < 8 {
+        return None;
+    }
+    let value = u64::from_le_bytes(data[..8].try_into().unwrap());
+    *data = &data[8..];
+    return Some(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+    use crate::engine::terrarin::block::Block;
+    use super::*;
+
+    // directory in the system temp directory, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("region-test-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            return TempDir(path);
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn registry() -> Arc<BlockRegistry> {
+        return Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+    }
+
+    // chunk of pseudo random blocks that compresses badly
+    fn noisy_chunk(pos: ChunkPos, registry: &BlockRegistry) -> Chunk {
+        let blocks = ["stone", "dirt", "grass", "sand", "water", "leaves"].map(|name| registry.block(name));
+        let mut chunk = Chunk::empty(pos);
+        let mut state = 0x2545f4914f6cdd1du64;
+        for i in 0..CHUNK_VOLUME {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            chunk.set(i / 256, i / 16 % 16, i % 16, blocks[(state % blocks.len() as u64) as usize]);
+        }
+        return chunk;
+    }
+
+    fn same_blocks(a: &Chunk, b: &Chunk) -> bool {
+        return a.get_position() == b.get_position() && (0..CHUNK_VOLUME).all(|i| a.get(i / 256, i / 16 % 16, i % 16) == b.get(i / 256, i / 16 % 16, i % 16));
+    }
+
+    #[test]
+    fn saved_chunks_are_loaded_after_reopening() {
+        let directory = TempDir::new("reopen");
+        let registry = registry();
+        let log_z = registry.with_state_name(registry.block("log"), "axis", "z").unwrap();
+        let positions = [ChunkPos::new(0, 0, 0), ChunkPos::new(-1, -1, -1), ChunkPos::new(-8, 3, -9), ChunkPos::new(-17, -30, 5), ChunkPos::new(7, 7, 7)];
+        let mut chunks = Vec::new();
+        for (i, pos) in positions.iter().enumerate() {
+            let mut chunk = if i % 2 == 0 { noisy_chunk(*pos, &registry) } else { Chunk::filled(*pos, registry.block("stone")) };
+            chunk.set(1, 2, 3, log_z);
+            chunks.push(chunk);
+        }
+        {
+            let mut storage = RegionStorage::open(&directory.0, registry.clone()).unwrap();
+            for chunk in &chunks {
+                storage.save_chunk(chunk, &FeatureOwners::new()).unwrap();
+            }
+        }
+
+        let mut storage = RegionStorage::open(&directory.0, registry.clone()).unwrap();
+        for chunk in &chunks {
+            let loaded = storage.load_chunk(chunk.get_position()).unwrap().unwrap().chunk.unwrap();
+            assert!(same_blocks(&loaded, chunk), "chunk {:?}", chunk.get_position().0);
+            assert_eq!(loaded.get(1, 2, 3), log_z);
+        }
+        // one region file for every region that has a saved chunk
+        assert_eq!(fs::read_dir(&directory.0).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn never_saved_chunks_are_none() {
+        let directory = TempDir::new("missing");
+        let registry = registry();
+        let mut storage = RegionStorage::open(&directory.0, registry.clone()).unwrap();
+        assert!(storage.load_chunk(ChunkPos::new(-3, 0, 2)).unwrap().is_none());
+        // looking for a chunk doesn't create its region
+        assert_eq!(fs::read_dir(&directory.0).unwrap().count(), 0);
+
+        storage.save_chunk(&Chunk::filled(ChunkPos::new(-3, 0, 2), registry.block("dirt")), &FeatureOwners::new()).unwrap();
+        assert!(storage.load_chunk(ChunkPos::new(-3, 0, 3)).unwrap().is_none());
+        assert!(storage.load_chunk(ChunkPos::new(-3, 0, 2)).unwrap().is_some());
+    }
+
+    #[test]
+    fn larger_chunks_are_moved() {
+        let directory = TempDir::new("move");
+        let registry = registry();
+        let pos = ChunkPos::new(-2, 1, -5);
+        let next = ChunkPos::new(-2, 1, -4);
+        let mut storage = RegionStorage::open(&directory.0, registry.clone()).unwrap();
+        storage.save_chunk(&Chunk::filled(pos, registry.block("sand")), &FeatureOwners::new()).unwrap();
+        storage.save_chunk(&Chunk::filled(next, registry.block("water")), &FeatureOwners::new()).unwrap();
+        let region_pos = RegionStorage::region_pos(pos);
+        let index = RegionStorage::index_in_region(pos);
+        let old = storage.regions[&region_pos].entries[index];
+        assert_eq!(old.sectors, 1);
+
+        let noisy = noisy_chunk(pos, &registry);
+        storage.save_chunk(&noisy, &FeatureOwners::new()).unwrap();
+        let moved = storage.regions[&region_pos].entries[index];
+        assert!(moved.sectors > 1);
+        assert!(moved.sector > old.sector + 1);
+
+        // smaller chunk is written over the sectors it has
+        let mut smaller = noisy_chunk(pos, &registry);
+        smaller.set(0, 0, 0, Block::AIR);
+        storage.save_chunk(&smaller, &FeatureOwners::new()).unwrap();
+        assert_eq!(storage.regions[&region_pos].entries[index].sector, moved.sector);
+        drop(storage);
+
+        let mut storage = RegionStorage::open(&directory.0, registry.clone()).unwrap();
+        assert!(same_blocks(&storage.load_chunk(pos).unwrap().unwrap().chunk.unwrap(), &smaller));
+        assert!(same_blocks(&storage.load_chunk(next).unwrap().unwrap().chunk.unwrap(), &Chunk::filled(next, registry.block("water"))));
+    }
+
+    #[test]
+    fn foreign_files_are_rejected() {
+        let directory = TempDir::new("foreign");
+        let registry = registry();
+        let mut storage = RegionStorage::open(&directory.0, registry.clone()).unwrap();
+        let pos = ChunkPos::new(0, 0, -1);
+        let path = storage.region_path(RegionStorage::region_pos(pos));
+
+        fs::write(&path, vec![7; (HEADER_SECTORS * SECTOR_SIZE) as usize]).unwrap();
+        assert!(matches!(storage.load_chunk(pos), Err(RegionError::NotARegion(_))));
+        fs::write(&path, b"AMRG").unwrap();
+        assert!(matches!(storage.load_chunk(pos), Err(RegionError::NotARegion(_))));
+
+        let mut header = vec![0; (HEADER_SECTORS * SECTOR_SIZE) as usize];
+        header[0..4].copy_from_slice(&REGION_MAGIC);
+        header[4..8].copy_from_slice(&(REGION_VERSION + 1).to_le_bytes());
+        fs::write(&path, header).unwrap();
+        assert!(matches!(storage.load_chunk(pos), Err(RegionError::UnsupportedVersion(version)) if version == REGION_VERSION + 1));
+        assert!(matches!(storage.save_chunk(&Chunk::empty(pos), &FeatureOwners::new()), Err(RegionError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn features_are_saved_with_chunks() {
+        let directory = TempDir::new("features");
+        let registry = registry();
+        let log = registry.block("log");
+        let leaves = registry.block("leaves");
+        let pos = ChunkPos::new(-1, 2, -3);
+        let corner = BlockPos(pos.block_max().0);
+        let mut owners = FeatureOwners::new();
+        owners.insert(pos.block_min(), FeatureOwner { priority: u64::MAX, original: Block::AIR });
+        owners.insert(corner, FeatureOwner { priority: 12, original: registry.block("dirt") });
+        let writes = [
+            FeatureWrite { pos: pos.block_min(), block: log, priority: 3, feature: 0 },
+            FeatureWrite { pos: corner, block: leaves, priority: 4, feature: 1 },
+        ];
+        let waiting = ChunkPos::new(-1, 2, -4);
+        let waiting_writes = [FeatureWrite { pos: BlockPos(waiting.block_min().0 + 5), ..writes[1] }];
+        {
+            let mut storage = RegionStorage::open(&directory.0, registry.clone()).unwrap();
+            storage.save_pending_features(pos, &writes[..1]).unwrap();
+            // writes that are already saved are not saved twice
+            storage.save_pending_features(pos, &writes).unwrap();
+            storage.save_pending_features(waiting, &waiting_writes).unwrap();
+            let saved = storage.load_chunk(pos).unwrap().unwrap();
+            assert!(saved.chunk.is_none());
+            assert_eq!(saved.pending_features, writes);
+
+            // saving the chunk replaces writes waiting for it
+            storage.save_chunk(&Chunk::filled(pos, log), &owners).unwrap();
+        }
+
+        let mut storage = RegionStorage::open(&directory.0, registry.clone()).unwrap();
+        let saved = storage.load_chunk(pos).unwrap().unwrap();
+        assert!(same_blocks(&saved.chunk.unwrap(), &Chunk::filled(pos, log)));
+        assert!(saved.pending_features.is_empty());
+        assert_eq!(saved.feature_owners.len(), 2);
+        assert_eq!(saved.feature_owners[&corner].priority, 12);
+        assert_eq!(saved.feature_owners[&corner].original, registry.block("dirt"));
+        assert_eq!(saved.feature_owners[&pos.block_min()].priority, u64::MAX);
+        assert_eq!(storage.load_chunk(waiting).unwrap().unwrap().pending_features, waiting_writes);
+
+        // writes waiting for a generated chunk keep the chunk
+        storage.save_pending_features(pos, &writes).unwrap();
+        let saved = storage.load_chunk(pos).unwrap().unwrap();
+        assert!(saved.chunk.is_some());
+        assert_eq!(saved.feature_owners.len(), 2);
+        assert_eq!(saved.pending_features, writes);
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use glam::IVec3;
+use serde::Deserialize;
+use crate::engine::terrarin::block::Block;
+use crate::engine::terrarin::block_state::{StateLayout, StateProperty};
+use crate::engine::terrarin::ticks::BlockTickHandler;
+
+pub const AIR_NAME: &str = "air";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaceAppearance {
+    pub color: [f32; 3],
+    // colour is replaced by the grass colour of the biome the block is in
+    #[serde(default)]
+    pub grass_tint: bool,
+    // not used by the renderer yet
+    #[serde(default)]
+    pub texture: Option<String>,
+}
+
+// side appearance is used for top and bottom too unless they are given
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockFaces {
+    pub side: FaceAppearance,
+    #[serde(default)]
+    pub top: Option<FaceAppearance>,
+    #[serde(default)]
+    pub bottom: Option<FaceAppearance>,
+}
+
+impl BlockFaces {
+    pub fn face(&self, normal: IVec3) -> &FaceAppearance {
+        let face = if normal.y > 0 {
+            self.top.as_ref()
+        } else if normal.y < 0 {
+            self.bottom.as_ref()
+        } else {
+            None
+        };
+        return face.unwrap_or(&self.side);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockDefinition {
+    // stable name used in data files, numeric ids can change between versions
+    pub name: String,
+    #[serde(default = "default_solid")]
+    pub solid: bool,
+    // faces next to transparent blocks are rendered
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default)]
+    pub liquid: bool,
+    // game updates between flow steps of a liquid, how far it flows is the highest value of its "level" state
+    #[serde(default)]
+    pub flow_delay: u32,
+    pub faces: BlockFaces,
+    // light level in 0..=15
+    #[serde(default)]
+    pub light_emission: u8,
+    #[serde(default)]
+    pub hardness: f32,
+    #[serde(default)]
+    pub states: Vec<StateProperty>,
+    #[serde(skip)]
+    state_layout: StateLayout,
+}
+
+fn default_solid() -> bool {
+    return true;
+}
+
+impl BlockDefinition {
+    fn air() -> BlockDefinition {
+        return BlockDefinition {
+            name: AIR_NAME.to_string(),
+            solid: false,
+            transparent: true,
+            liquid: false,
+            flow_delay: 0,
+            faces: BlockFaces {
+                side: FaceAppearance { color: [0.0, 0.0, 0.0], grass_tint: false, texture: None },
+                top: None,
+                bottom: None,
+            },
+            light_emission: 0,
+            hardness: 0.0,
+            states: Vec::new(),
+            state_layout: StateLayout::default(),
+        };
+    }
+
+    fn property_index(&self, property: &str) -> Option<usize> {
+        return self.states.iter().position(|state| state.name() == property);
+    }
+
+    pub fn get_state(&self, state: u16, property: &str) -> Option<u16> {
+        let index = self.property_index(property)?;
+        return Some(self.state_layout.get(state, index));
+    }
+
+    // highest value of a property, None if block has no such property
+    pub fn max_state(&self, property: &str) -> Option<u16> {
+        let index = self.property_index(property)?;
+        return Some((self.states[index].value_count() - 1) as u16);
+    }
+
+    // None if block has no such property or value is out of its range
+    pub fn set_state(&self, state: u16, property: &str, value: u16) -> Option<u16> {
+        let index = self.property_index(property)?;
+        if value as u32 >= self.states[index].value_count() {
+            return None;
+        }
+        return Some(self.state_layout.set(state, index, value));
+    }
+
+    pub fn get_state_name(&self, state: u16, property: &str) -> Option<&str> {
+        let index = self.property_index(property)?;
+        return self.states[index].value_name(self.state_layout.get(state, index));
+    }
+
+    pub fn set_state_name(&self, state: u16, property: &str, value_name: &str) -> Option<u16> {
+        let index = self.property_index(property)?;
+        let value = self.states[index].value_index(value_name)?;
+        return Some(self.state_layout.set(state, index, value));
+    }
+
+    // Direction the top of the block points to. Blocks can be rotated with an "axis" (x, y, z)
+    // or "facing" (north, east, south, west, up, down) property.
+    pub fn up(&self, state: u16) -> IVec3 {
+        if let Some(axis) = self.get_state_name(state, "axis") {
+            return match axis {
+                "x" => IVec3::X,
+                "z" => IVec3::Z,
+                _ => IVec3::Y,
+            };
+        }
+        if let Some(facing) = self.get_state_name(state, "facing") {
+            return match facing {
+                "north" => -IVec3::Z,
+                "south" => IVec3::Z,
+                "east" => IVec3::X,
+                "west" => -IVec3::X,
+                "down" => -IVec3::Y,
+                _ => IVec3::Y,
+            };
+        }
+        return IVec3::Y;
+    }
+
+    // appearance of the face pointing to given direction in the world, with block rotation applied
+    pub fn face(&self, state: u16, normal: IVec3) -> &FaceAppearance {
+        let up = self.up(state);
+        let local = if normal == up {
+            IVec3::Y
+        } else if normal == -up {
+            -IVec3::Y
+        } else {
+            IVec3::X
+        };
+        return self.faces.face(local);
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    DuplicateName(String),
+    TooManyBlocks,
+    InvalidStates(String),
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            RegistryError::Io(error) => write!(f, "failed to read block definitions: {}", error),
+            RegistryError::Parse(error) => write!(f, "invalid block definitions: {}", error),
+            RegistryError::DuplicateName(name) => write!(f, "block '{}' is defined more than once", name),
+            RegistryError::TooManyBlocks => write!(f, "too many block definitions"),
+            RegistryError::InvalidStates(name) => write!(f, "states of block '{}' are empty or don't fit in 16 bits", name),
+        };
+    }
+}
+
+#[derive(Deserialize)]
+struct BlockFile {
+    blocks: Vec<BlockDefinition>,
+}
+
+// Maps block ids to their definitions. Air is always id 0, other blocks get ids in the order
+// they are listed in the data file. Behaviour that can't be described by data, like tick
+// handlers, is registered in code before the registry is shared.
+pub struct BlockRegistry {
+    definitions: Vec<BlockDefinition>,
+    ids: HashMap<String, u16>,
+    // by block id, blocks without a handler are never ticked
+    tick_handlers: Vec<Option<Arc<dyn BlockTickHandler>>>,
+}
+
+impl BlockRegistry {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BlockRegistry, RegistryError> {
+        let json = fs::read_to_string(path).map_err(RegistryError::Io)?;
+        return Self::from_json(&json);
+    }
+
+    pub fn from_json(json: &str) -> Result<BlockRegistry, RegistryError> {
+        let file: BlockFile = serde_json::from_str(json).map_err(RegistryError::Parse)?;
+        return Self::from_definitions(file.blocks);
+    }
+
+    pub fn from_definitions(definitions: Vec<BlockDefinition>) -> Result<BlockRegistry, RegistryError> {
+        let mut registry = BlockRegistry {
+            definitions: Vec::with_capacity(definitions.len() + 1),
+            ids: HashMap::new(),
+            tick_handlers: Vec::new(),
+        };
+        registry.register(BlockDefinition::air())?;
+        for definition in definitions {
+            registry.register(definition)?;
+        }
+        return Ok(registry);
+    }
+
+    fn register(&mut self, mut definition: BlockDefinition) -> Result<(), RegistryError> {
+        if self.ids.contains_key(&definition.name) {
+            return Err(RegistryError::DuplicateName(definition.name));
+        }
+        if self.definitions.len() > u16::MAX as usize {
+            return Err(RegistryError::TooManyBlocks);
+        }
+        definition.state_layout = match StateLayout::new(&definition.states) {
+            Some(layout) => layout,
+            None => return Err(RegistryError::InvalidStates(definition.name)),
+        };
+        self.ids.insert(definition.name.clone(), self.definitions.len() as u16);
+        self.definitions.push(definition);
+        self.tick_handlers.push(None);
+        return Ok(());
+    }
+
+    // replaces handler of the block type, all states of the block share it
+    pub fn set_tick_handler(&mut self, block: Block, handler: Arc<dyn BlockTickHandler>) {
+        if let Some(slot) = self.tick_handlers.get_mut(block.id as usize) {
+            *slot = Some(handler);
+        }
+    }
+
+    pub fn tick_handler(&self, block: Block) -> Option<&Arc<dyn BlockTickHandler>> {
+        return self.tick_handlers.get(block.id as usize).and_then(|handler| handler.as_ref());
+    }
+
+    // unknown ids are treated as air
+    pub fn get(&self, block: Block) -> &BlockDefinition {
+        return self.definitions.get(block.id as usize).unwrap_or(&self.definitions[0]);
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Block> {
+        return self.ids.get(name).map(|id| Block::new(*id));
+    }
+
+    // for setting up generators and other code that can't work without given block
+    pub fn block(&self, name: &str) -> Block {
+        return self.by_name(name).unwrap_or_else(|| panic!("unknown block '{}'", name));
+    }
+
+    pub fn name(&self, block: Block) -> &str {
+        return &self.get(block).name;
+    }
+
+    pub fn get_state(&self, block: Block, property: &str) -> Option<u16> {
+        return self.get(block).get_state(block.state, property);
+    }
+
+    pub fn with_state(&self, block: Block, property: &str, value: u16) -> Option<Block> {
+        return self.get(block).set_state(block.state, property, value).map(|state| block.with_state(state));
+    }
+
+    pub fn with_state_name(&self, block: Block, property: &str, value_name: &str) -> Option<Block> {
+        return self.get(block).set_state_name(block.state, property, value_name).map(|state| block.with_state(state));
+    }
+
+    pub fn face(&self, block: Block, normal: IVec3) -> &FaceAppearance {
+        return self.get(block).face(block.state, normal);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.definitions.len();
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (Block, &BlockDefinition)> {
+        return self.definitions.iter().enumerate().map(|(id, definition)| (Block::new(id as u16), definition));
+    }
+
+    pub fn blocks_where<F: Fn(&BlockDefinition) -> bool>(&self, filter: F) -> Vec<Block> {
+        return self.blocks().filter(|(_, definition)| filter(definition)).map(|(block, _)| block).collect();
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use glam::IVec3;
+use crate::engine::terrarin::block::Block;
+use crate::engine::terrarin::block_region::BlockAabb;
+use crate::engine::terrarin::edit::Clipboard;
+use crate::engine::terrarin::palette::PalettedStorage;
+use crate::engine::terrarin::registry::BlockRegistry;
+use crate::engine::terrarin::world::GameWorld;
+
+// Schematic file layout, numbers are little endian:
+//   magic "AMSC" and format version u32
+//   zlib compressed rest of the file: size and origin as 3 i32 each, then the blocks
+//   written by PalettedStorage::write_named in the order of BlockAabb::iter
+pub const SCHEMATIC_VERSION: u32 = 1;
+const SCHEMATIC_MAGIC: [u8; 4] = *b"AMSC";
+
+// larger schematics are most likely corrupted and would only waste memory
+const MAX_VOLUME: i64 = 1 << 24;
+
+#[derive(Debug)]
+pub enum SchematicError {
+    Io(std::io::Error),
+    NotASchematic,
+    UnsupportedVersion(u32),
+    Corrupted,
+}
+
+impl Display for SchematicError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            SchematicError::Io(error) => write!(f, "failed to access schematic file: {}", error),
+            SchematicError::NotASchematic => write!(f, "data is not a schematic"),
+            SchematicError::UnsupportedVersion(version) => write!(f, "schematic format version {} is not supported, expected {}", version, SCHEMATIC_VERSION),
+            SchematicError::Corrupted => write!(f, "schematic data is corrupted"),
+        };
+    }
+}
+
+impl From<std::io::Error> for SchematicError {
+    fn from(error: std::io::Error) -> SchematicError {
+        return SchematicError::Io(error);
+    }
+}
+
+// Block structure that can be saved, pasted into a world or placed by world generation.
+// Origin is the block of the structure that goes to the position it is placed at,
+// relative to its lowest corner.
+#[derive(Clone)]
+pub struct Schematic {
+    pub blocks: Clipboard,
+    pub origin: IVec3,
+}
+
+impl Schematic {
+    pub fn new(blocks: Clipboard, origin: IVec3) -> Schematic {
+        return Schematic { blocks, origin };
+    }
+
+    // blocks of chunks that are not loaded are saved as air
+    pub fn copy(world: &GameWorld, aabb: BlockAabb, origin: IVec3) -> Schematic {
+        return Schematic::new(Clipboard::copy(world, aabb), origin);
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, registry: &BlockRegistry) -> Result<(), SchematicError> {
+        fs::write(path, self.encode(registry)?)?;
+        return Ok(());
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, registry: &BlockRegistry) -> Result<Schematic, SchematicError> {
+        return Self::decode(&fs::read(path)?, registry);
+    }
+
+    pub fn encode(&self, registry: &BlockRegistry) -> Result<Vec<u8>, SchematicError> {
+        let blocks = self.blocks.blocks();
+        let mut storage = PalettedStorage::filled(blocks.len(), blocks[0]);
+        for (i, block) in blocks.iter().enumerate() {
+            storage.set(i, *block);
+        }
+        let mut data = Vec::new();
+        for value in self.blocks.size().to_array().into_iter().chain(self.origin.to_array()) {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        storage.write_named(registry, &mut data);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+        let mut file = Vec::new();
+        file.extend_from_slice(&SCHEMATIC_MAGIC);
+        file.extend_from_slice(&SCHEMATIC_VERSION.to_le_bytes());
+        file.extend_from_slice(&encoder.finish()?);
+        return Ok(file);
+    }
+
+    // blocks missing from the registry are loaded as air
+    pub fn decode(file: &[u8], registry: &BlockRegistry) -> Result<Schematic, SchematicError> {
+        if file.len() < 8 || file[0..4] != SCHEMATIC_MAGIC {
+            return Err(SchematicError::NotASchematic);
+        }
+        let version = u32::from_le_bytes(file[4..8].try_into().unwrap());
+        if version != SCHEMATIC_VERSION {
+            return Err(SchematicError::UnsupportedVersion(version));
+        }
+        let mut data = Vec::new();
+        if ZlibDecoder::new(&file[8..]).read_to_end(&mut data).is_err() || data.len() < 24 {
+            return Err(SchematicError::Corrupted);
+        }
+        let values: Vec<i32> = data[..24].chunks_exact(4).map(|value| i32::from_le_bytes(value.try_into().unwrap())).collect();
+        let size = IVec3::new(values[0], values[1], values[2]);
+        let origin = IVec3::new(values[3], values[4], values[5]);
+        if size.cmplt(IVec3::ONE).any() || size.x as i64 * size.y as i64 * size.z as i64 > MAX_VOLUME {
+            return Err(SchematicError::Corrupted);
+        }
+        let volume = (size.x * size.y * size.z) as usize;
+        let storage = PalettedStorage::read_named(volume, registry, &data[24..]).ok_or(SchematicError::Corrupted)?;
+        let blocks: Vec<Block> = (0..volume).map(|i| storage.get(i)).collect();
+        let blocks = Clipboard::from_blocks(size, blocks).ok_or(SchematicError::Corrupted)?;
+        return Ok(Schematic { blocks, origin });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schematic(registry: &BlockRegistry) -> Schematic {
+        let log = registry.block("log");
+        let blocks = (0..60).map(|i| match i % 5 {
+            0 => Block::AIR,
+            1 => registry.block("stone"),
+            2 => registry.with_state_name(log, "axis", "x").unwrap(),
+            3 => registry.with_state_name(log, "axis", "z").unwrap(),
+            _ => registry.block("sand"),
+        }).collect();
+        return Schematic::new(Clipboard::from_blocks(IVec3::new(5, 3, 4), blocks).unwrap(), IVec3::new(2, -1, 0));
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        let schematic = schematic(&registry);
+        let encoded = schematic.encode(&registry).unwrap();
+        let decoded = Schematic::decode(&encoded, &registry).unwrap();
+        assert_eq!(decoded.origin, schematic.origin);
+        assert_eq!(decoded.blocks.size(), schematic.blocks.size());
+        assert_eq!(decoded.blocks.blocks(), schematic.blocks.blocks());
+        assert_eq!(decoded.encode(&registry).unwrap(), encoded);
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        let encoded = schematic(&registry).encode(&registry).unwrap();
+        for len in 0..encoded.len() {
+            match Schematic::decode(&encoded[..len], &registry) {
+                Err(SchematicError::NotASchematic) => assert!(len < 8),
+                Err(SchematicError::Corrupted) => assert!(len >= 8),
+                _ => panic!("schematic cut to {} bytes was not rejected", len),
+            }
+        }
+    }
+
+    #[test]
+    fn other_files_and_versions_are_rejected() {
+        let registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        let encoded = schematic(&registry).encode(&registry).unwrap();
+        let mut magic = encoded.clone();
+        magic[..4].copy_from_slice(b"PNG\0");
+        assert!(matches!(Schematic::decode(&magic, &registry), Err(SchematicError::NotASchematic)));
+
+        let mut newer = encoded.clone();
+        newer[4..8].copy_from_slice(&(SCHEMATIC_VERSION + 1).to_le_bytes());
+        match Schematic::decode(&newer, &registry) {
+            Err(SchematicError::UnsupportedVersion(version)) => assert_eq!(version, SCHEMATIC_VERSION + 1),
+            _ => panic!("newer schematic version was not rejected"),
+        }
+    }
+
+    #[test]
+    fn unknown_blocks_are_loaded_as_air() {
+        let registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        // saved by a game version that had marble and listed stone at another id
+        let older = BlockRegistry::from_json(r#"{ "blocks": [
+            { "name": "marble", "faces": { "side": { "color": [0.9, 0.9, 0.9] } } },
+            { "name": "stone", "faces": { "side": { "color": [0.5, 0.5, 0.5] } } }
+        ] }"#).unwrap();
+        let blocks = (0..8).map(|i| if i % 2 == 0 { older.block("marble") } else { older.block("stone") }).collect();
+        let schematic = Schematic::new(Clipboard::from_blocks(IVec3::new(2, 2, 2), blocks).unwrap(), IVec3::ZERO);
+
+        let decoded = Schematic::decode(&schematic.encode(&older).unwrap(), &registry).unwrap();
+        for (i, block) in decoded.blocks.blocks().iter().enumerate() {
+            assert_eq!(*block, if i % 2 == 0 { Block::AIR } else { registry.block("stone") });
+        }
+    }
+}
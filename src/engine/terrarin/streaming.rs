@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use glam::IVec3;
+use crate::engine::terrarin::chunk::ChunkPos;
+use crate::engine::terrarin::lod::MAX_LOD;
+use crate::engine::terrarin::world::GameWorld;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StreamingSettings {
+    // horizontal radius in chunks around the centre chunk
+    pub view_distance: i32,
+    // chunks above and below the centre chunk
+    pub vertical_distance: i32,
+    // chunks are unloaded only this many chunks past view distance, so going back and forth
+    // over a chunk border doesn't load and unload the same chunks again
+    pub unload_margin: i32,
+    // limits of work done in one update, loads are chunks inserted into the world
+    pub loads_per_update: usize,
+    pub unloads_per_update: usize,
+    pub meshes_per_update: usize,
+    // chunks waiting for workers at once, fewer means less wasted work when view moves
+    pub max_requests: usize,
+    // chunks further than lod_distances[i] horizontally are meshed at level of detail i + 1
+    pub lod_distances: [i32; MAX_LOD as usize],
+    // chunks keep their level until they are this many chunks past the distance of another one,
+    // so moving along a band border doesn't mesh the same chunks again and again
+    pub lod_margin: f32,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        return StreamingSettings {
+            view_distance: 10,
+            vertical_distance: 3,
+            unload_margin: 2,
+            loads_per_update: 4,
+            unloads_per_update: 8,
+            meshes_per_update: 4,
+            max_requests: 32,
+            lod_distances: [4, 6, 8],
+            lod_margin: 1.0,
+        };
+    }
+}
+
+// Chunks changed by a single streaming update.
+pub struct StreamingUpdate {
+    // meshes of these chunks should be removed
+    pub unloaded: Vec<ChunkPos>,
+    // these chunks should be meshed again at ChunkStreamer::lod, closest first
+    pub meshes: Vec<ChunkPos>,
+}
+
+// Keeps chunks around a moving centre loaded, closest chunks are requested first.
+// Only a limited amount of chunks is loaded, unloaded and meshed in every update.
+// Requests for chunks that leave view before they are generated are cancelled.
+// Distant chunks are meshed at lower levels of detail, chunks are meshed again when the
+// centre moves far enough for their level to change. Meshes depend on the levels of their
+// neighbours, see ChunkMesher::build_lod, so neighbours are meshed again when a level changes.
+pub struct ChunkStreamer {
+    pub settings: StreamingSettings,
+    center: Option<ChunkPos>,
+    // chunks in range that are not requested yet, closest first
+    to_load: VecDeque<ChunkPos>,
+    requested: HashSet<ChunkPos>,
+    // chunks in range that are not loaded yet
+    waiting: HashSet<ChunkPos>,
+    to_unload: Vec<ChunkPos>,
+    to_mesh: HashSet<ChunkPos>,
+    // level of detail of every chunk that was handed out for meshing
+    lods: HashMap<ChunkPos, u32>,
+}
+
+impl ChunkStreamer {
+    pub fn new(settings: StreamingSettings) -> ChunkStreamer {
+        return ChunkStreamer {
+            settings,
+            center: None,
+            to_load: VecDeque::new(),
+            requested: HashSet::new(),
+            waiting: HashSet::new(),
+            to_unload: Vec::new(),
+            to_mesh: HashSet::new(),
+            lods: HashMap::new(),
+        };
+    }
+
+    pub fn in_view(&self, center: ChunkPos, pos: ChunkPos) -> bool {
+        return Self::in_range(center, pos, self.settings.view_distance, self.settings.vertical_distance);
+    }
+
+    // level of detail the chunk should be meshed at, 0 for chunks that were never meshed
+    pub fn lod(&self, pos: ChunkPos) -> u32 {
+        return self.lods.get(&pos).copied().unwrap_or(0);
+    }
+
+    // levels of the chunks next to this one, in the order of ChunkPos::neighbours
+    pub fn neighbour_lods(&self, pos: ChunkPos) -> [u32; 6] {
+        return pos.neighbours().map(|neighbour| self.lod(neighbour));
+    }
+
+    // true when every chunk in view is loaded
+    pub fn is_done(&self) -> bool {
+        return self.to_load.is_empty() && self.waiting.is_empty();
+    }
+
+    pub fn update(&mut self, world: &mut GameWorld, center: ChunkPos) -> StreamingUpdate {
+        if self.center != Some(center) {
+            self.center = Some(center);
+            self.plan(world, center);
+        }
+
+        let mut unloaded = Vec::new();
+        while unloaded.len() < self.settings.unloads_per_update {
+            let pos = match self.to_unload.pop() {
+                Some(pos) => pos,
+                None => break,
+            };
+            match world.unload_chunk(pos) {
+                Ok(true) => unloaded.push(pos),
+                Ok(false) => {}
+                Err(error) => println!("Failed to save chunk: {}", error),
+            }
+        }
+
+        world.receive_chunks(self.settings.loads_per_update);
+        // saved chunks are loaded right away when requested
+        let mut loaded = 0;
+        while loaded < self.settings.loads_per_update && world.requested_count() < self.settings.max_requests {
+            let pos = match self.to_load.pop_front() {
+                Some(pos) => pos,
+                None => break,
+            };
+            world.request_chunk(pos);
+            if world.is_requested(pos) {
+                self.requested.insert(pos);
+            } else {
+                loaded += 1;
+            }
+        }
+        self.requested.retain(|pos| world.is_requested(*pos));
+        self.waiting.retain(|pos| !world.is_loaded(*pos));
+
+        self.to_mesh.extend(world.take_dirty_meshes());
+        for pos in &unloaded {
+            self.to_mesh.remove(pos);
+            self.lods.remove(pos);
+        }
+        return StreamingUpdate {
+            unloaded,
+            meshes: self.take_meshes(center),
+        };
+    }
+
+    fn plan(&mut self, world: &mut GameWorld, center: ChunkPos) {
+        let distance = self.settings.view_distance;
+        let vertical = self.settings.vertical_distance;
+        let mut in_view = Vec::new();
+        for x in -distance..=distance {
+            for y in -vertical..=vertical {
+                for z in -distance..=distance {
+                    let pos = ChunkPos(center.0 + IVec3::new(x, y, z));
+                    if self.in_view(center, pos) && !world.is_loaded(pos) {
+                        in_view.push(pos);
+                    }
+                }
+            }
+        }
+        in_view.sort_by_key(|pos| Self::distance_squared(center, *pos));
+        self.waiting = in_view.iter().copied().collect();
+        self.to_load = in_view.into_iter().filter(|pos| !self.requested.contains(pos)).collect();
+        for pos in &self.requested {
+            if !self.waiting.contains(pos) {
+                world.cancel_chunk(*pos);
+            }
+        }
+        self.requested.retain(|pos| self.waiting.contains(pos));
+
+        let margin = self.settings.unload_margin;
+        self.to_unload = world.loaded_positions()
+            .filter(|pos| !Self::in_range(center, *pos, distance + margin, vertical + margin))
+            .collect();
+        // popped from the end, so furthest chunks are unloaded first
+        self.to_unload.sort_by_key(|pos| Self::distance_squared(center, *pos));
+
+        for (pos, lod) in &self.lods {
+            if self.choose_lod(center, *pos, Some(*lod)) != *lod {
+                self.to_mesh.insert(*pos);
+            }
+        }
+    }
+
+    // Level of detail for the horizontal distance from the centre. Current level is kept
+    // while it is the level of some distance within lod_margin.
+    fn choose_lod(&self, center: ChunkPos, pos: ChunkPos, current: Option<u32>) -> u32 {
+        let offset = pos.0 - center.0;
+        let distance = ((offset.x * offset.x + offset.z * offset.z) as f32).sqrt();
+        let lod_at = |distance: f32| self.settings.lod_distances.iter().filter(|band| distance > **band as f32).count() as u32;
+        let lod = lod_at(distance);
+        return match current {
+            Some(current) if (lod_at(distance - self.settings.lod_margin)..=lod_at(distance + self.settings.lod_margin)).contains(&current) => current,
+            _ => lod,
+        };
+    }
+
+    // Chunks are meshed only once their neighbours in view are loaded, otherwise every
+    // loaded neighbour would make them mesh again.
+    fn take_meshes(&mut self, center: ChunkPos) -> Vec<ChunkPos> {
+        let mut ready: Vec<ChunkPos> = self.to_mesh.iter()
+            .copied()
+            .filter(|pos| pos.surrounding().iter().all(|neighbour| !self.waiting.contains(neighbour)))
+            .collect();
+        ready.sort_by_key(|pos| Self::distance_squared(center, *pos));
+        ready.truncate(self.settings.meshes_per_update);
+        for pos in &ready {
+            self.to_mesh.remove(pos);
+            let lod = self.choose_lod(center, *pos, self.lods.get(pos).copied());
+            // neighbours were meshed with the level returned by lod, which is 0 before the first mesh
+            let old = self.lod(*pos);
+            self.lods.insert(*pos, lod);
+            if lod != old {
+                for neighbour in pos.neighbours() {
+                    if self.lods.contains_key(&neighbour) && !ready.contains(&neighbour) {
+                        self.to_mesh.insert(neighbour);
+                    }
+                }
+            }
+        }
+        return ready;
+    }
+
+    fn in_range(center: ChunkPos, pos: ChunkPos, distance: i32, vertical: i32) -> bool {
+        let offset = pos.0 - center.0;
+        return offset.x * offset.x + offset.z * offset.z <= distance * distance && offset.y.abs() <= vertical;
+    }
+
+    fn distance_squared(center: ChunkPos, pos: ChunkPos) -> i32 {
+        let offset = pos.0 - center.0;
+        return offset.x * offset.x + offset.y * offset.y + offset.z * offset.z;
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use crate::engine::terrarin::biome::{Biome, BiomeSource, BIOMES};
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::chunk::{Chunk, CHUNK_SIZE, ChunkPos};
+use crate::engine::terrarin::chunk_generator::ChunkGenerator;
+use crate::engine::terrarin::noise::{FractalNoise, NoiseSettings};
+use crate::engine::terrarin::registry::BlockRegistry;
+
+#[derive(Debug, Copy, Clone)]
+pub struct TerrainSettings {
+    pub seed: u64,
+    pub noise: NoiseSettings,
+    // surface height where noise is 0, replaced by biome heights when biomes are used
+    pub base_height: i32,
+    // how far surface can go up or down from base height
+    pub amplitude: f64,
+    // air below this level is filled with water
+    pub sea_level: i32,
+    // how many filler blocks are between surface and stone
+    pub filler_depth: i32,
+    // surface and filler are only used without biomes
+    pub surface_block: Block,
+    pub filler_block: Block,
+    pub stone_block: Block,
+    pub water_block: Block,
+}
+
+impl TerrainSettings {
+    pub fn new(seed: u64, registry: &BlockRegistry) -> Self {
+        TerrainSettings {
+            seed,
+            noise: NoiseSettings::default(),
+            base_height: 4,
+            amplitude: 12.0,
+            sea_level: 0,
+            filler_depth: 3,
+            surface_block: registry.block("grass"),
+            filler_block: registry.block("dirt"),
+            stone_block: registry.block("stone"),
+            water_block: registry.block("water"),
+        }
+    }
+}
+
+// Heightmap terrain from fractal noise, same seed and position always give the same chunk.
+pub struct NoiseTerrainGenerator {
+    settings: TerrainSettings,
+    height_noise: FractalNoise,
+    biomes: Option<BiomeSource>,
+    // surface and filler block of each biome
+    biome_blocks: HashMap<Biome, (Block, Block)>,
+}
+
+// what a single column of blocks is made of
+struct Column {
+    height: i32,
+    surface_block: Block,
+    filler_block: Block,
+}
+
+impl NoiseTerrainGenerator {
+    pub fn new(settings: TerrainSettings) -> NoiseTerrainGenerator {
+        return NoiseTerrainGenerator {
+            height_noise: FractalNoise::new(settings.seed, settings.noise),
+            settings,
+            biomes: None,
+            biome_blocks: HashMap::new(),
+        };
+    }
+
+    // biomes decide surface blocks and height of every column instead of settings
+    pub fn with_biomes(settings: TerrainSettings, registry: &BlockRegistry) -> NoiseTerrainGenerator {
+        let biome_blocks = BIOMES.iter()
+            .map(|biome| {
+                let properties = biome.properties();
+                (*biome, (registry.block(properties.surface_block), registry.block(properties.filler_block)))
+            })
+            .collect();
+        return NoiseTerrainGenerator {
+            height_noise: FractalNoise::new(settings.seed, settings.noise),
+            biomes: Some(BiomeSource::new(settings.seed)),
+            biome_blocks,
+            settings,
+        };
+    }
+
+    pub fn biomes(&self) -> Option<&BiomeSource> {
+        return self.biomes.as_ref();
+    }
+
+    pub fn settings(&self) -> &TerrainSettings {
+        return &self.settings;
+    }
+
+    // y of the highest terrain block in given column, water not included
+    pub fn height_at(&self, x: i32, z: i32) -> i32 {
+        return self.column_at(x, z).height;
+    }
+
+    fn column_at(&self, x: i32, z: i32) -> Column {
+        let noise = self.height_noise.get2(x as f64, z as f64);
+        let settings = &self.settings;
+        return match &self.biomes {
+            None => Column {
+                height: settings.base_height + (noise * settings.amplitude).round() as i32,
+                surface_block: settings.surface_block,
+                filler_block: settings.filler_block,
+            },
+            Some(biomes) => {
+                let sample = biomes.sample(x, z);
+                let (surface_block, filler_block) = self.biome_blocks[&sample.biome];
+                Column {
+                    height: (sample.base_height + noise * sample.amplitude).round() as i32,
+                    surface_block,
+                    filler_block,
+                }
+            }
+        };
+    }
+
+    fn block_at(&self, column: &Column, y: i32) -> Block {
+        let settings = &self.settings;
+        let height = column.height;
+        if y > height {
+            return if y <= settings.sea_level { settings.water_block } else { Block::AIR };
+        }
+        if y <= height - settings.filler_depth {
+            return settings.stone_block;
+        }
+        // no grass under water
+        if y == height && height >= settings.sea_level {
+            return column.surface_block;
+        }
+        return column.filler_block;
+    }
+}
+
+impl ChunkGenerator for NoiseTerrainGenerator {
+    fn generate_chunk(&self, pos: ChunkPos) -> Chunk {
+        let mut chunk = Chunk::empty(pos);
+        let min = pos.block_min();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column = self.column_at(min.x + x as i32, min.z + z as i32);
+                for y in 0..CHUNK_SIZE {
+                    chunk.set(x, y, z, self.block_at(&column, min.y + y as i32));
+                }
+            }
+        }
+        return chunk;
+    }
+
+    fn biome_at(&self, pos: BlockPos) -> Biome {
+        return match &self.biomes {
+            Some(biomes) => biomes.biome_at(pos.x, pos.z),
+            None => Biome::Plains,
+        };
+    }
+
+    fn grass_color(&self, pos: BlockPos) -> [f32; 3] {
+        return match &self.biomes {
+            Some(biomes) => biomes.sample(pos.x, pos.z).grass_color,
+            None => Biome::Plains.properties().grass_color,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{Hash, Hasher};
+    use crate::engine::terrarin::carver::{CarvingGenerator, NoiseCaveCarver, NoiseCaveSettings, TunnelCarver, TunnelSettings};
+    use super::*;
+
+    // FNV-1a, unlike DefaultHasher it is the same on every Rust version, so known hashes stay valid
+    struct Fnv(u64);
+
+    impl Hasher for Fnv {
+        fn finish(&self) -> u64 {
+            return self.0;
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    fn chunk_hash(chunk: &Chunk) -> u64 {
+        let mut hasher = Fnv(0xcbf29ce484222325);
+        chunk.hash(&mut hasher);
+        return hasher.finish();
+    }
+
+    // same generator as the game uses
+    fn generator(seed: u64, registry: &BlockRegistry) -> CarvingGenerator {
+        return CarvingGenerator::new(
+            Box::new(NoiseTerrainGenerator::with_biomes(TerrainSettings::new(seed, registry), registry)),
+            vec![
+                Box::new(NoiseCaveCarver::new(NoiseCaveSettings::new(seed, registry))),
+                Box::new(TunnelCarver::new(TunnelSettings::worms(seed, registry))),
+                Box::new(TunnelCarver::new(TunnelSettings::ravines(seed, registry))),
+            ],
+        );
+    }
+
+    const POSITIONS: [(i32, i32, i32); 6] = [(0, 0, 0), (-1, 0, -1), (3, -2, 7), (-12, 1, 5), (40, 0, -33), (0, -4, 0)];
+
+    #[test]
+    fn generated_chunks_are_deterministic() {
+        let registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        for seed in [0, 1337] {
+            let hashes: Vec<u64> = POSITIONS.iter().map(|(x, y, z)| chunk_hash(&generator(seed, &registry).generate_chunk(ChunkPos::new(*x, *y, *z)))).collect();
+            // one generator, positions in reverse order
+            let generator = generator(seed, &registry);
+            let mut reversed: Vec<u64> = POSITIONS.iter().rev().map(|(x, y, z)| chunk_hash(&generator.generate_chunk(ChunkPos::new(*x, *y, *z)))).collect();
+            reversed.reverse();
+            assert_eq!(hashes, reversed);
+        }
+    }
+
+    // Known hashes of generated chunks, these only change when generation changes on purpose.
+    // Biomes and carvers are left out, they use sin, cos and exp which can round differently on
+    // other platforms.
+    #[test]
+    fn generated_chunks_match_known_hashes() {
+        let registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        let generator = NoiseTerrainGenerator::new(TerrainSettings::new(1337, &registry));
+        let hashes: Vec<u64> = POSITIONS.iter().map(|(x, y, z)| chunk_hash(&generator.generate_chunk(ChunkPos::new(*x, *y, *z)))).collect();
+        assert_eq!(hashes, vec![
+            0xdeb72060ca6bdb64,
+            0x723fee08dbcaac9c,
+            0x906c579d29449a47,
+            0x101971d63a2a906d,
+            0x1f2d3f249be2fdca,
+            0x6d349a4b831f0301,
+        ]);
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, HashMap};
+use glam::IVec3;
+use crate::engine::terrarin::block::{Block, BlockPos, FACE_NORMALS};
+use crate::engine::terrarin::chunk::{ChunkPos, CHUNK_SIZE};
+use crate::engine::terrarin::noise::Random;
+use crate::engine::terrarin::world::GameWorld;
+
+// Behaviour of a block type over time, registered in BlockRegistry::set_tick_handler.
+// All methods do nothing by default, handlers only implement what they need.
+pub trait BlockTickHandler: Send + Sync {
+    // Scheduled tick for a block of this type came due. The block at pos may have changed
+    // since the tick was scheduled, handlers check what is there themselves.
+    fn scheduled_tick(&self, _ticks: &mut TickContext, _pos: BlockPos) {}
+
+    // only block types that return true are picked by random ticks
+    fn random_ticks(&self) -> bool {
+        return false;
+    }
+
+    fn random_tick(&self, _ticks: &mut TickContext, _pos: BlockPos) {}
+
+    // Block at changed or next to it changed, pos is this block. Called for the changed block
+    // itself too, then pos is the same as changed.
+    fn neighbour_changed(&self, _ticks: &mut TickContext, _pos: BlockPos, _changed: BlockPos) {}
+}
+
+// What tick handlers can work with, the world and the scheduler of further ticks.
+pub struct TickContext<'a> {
+    pub world: &'a mut GameWorld,
+    pub random: &'a mut Random,
+    scheduled: &'a mut ScheduledTicks,
+    tick: u64,
+}
+
+impl TickContext<'_> {
+    pub fn current_tick(&self) -> u64 {
+        return self.tick;
+    }
+
+    // Ticks the handler of block at pos after given number of game updates, at least one.
+    // Block types are ticked at a position only once, an earlier tick that is already scheduled is kept.
+    pub fn schedule(&mut self, pos: BlockPos, block: Block, delay: u32) {
+        self.scheduled.schedule(pos, block.id, self.tick + delay.max(1) as u64);
+    }
+}
+
+pub struct TickSettings {
+    // random ticks in every loaded chunk per game update
+    pub random_tick_rate: u32,
+    // limits work done in one update, the rest of due ticks waits for the next one
+    pub max_scheduled_per_update: usize,
+}
+
+impl Default for TickSettings {
+    fn default() -> Self {
+        return TickSettings {
+            random_tick_rate: 3,
+            max_scheduled_per_update: 512,
+        };
+    }
+}
+
+// order of a scheduled tick, ticks due on the same update run in the order they were scheduled
+type TickKey = (u64, u64);
+
+#[derive(Default)]
+struct ChunkTicks {
+    queue: BTreeMap<TickKey, (BlockPos, u16)>,
+    // key of every scheduled block type at every position, so nothing is scheduled twice
+    keys: HashMap<(BlockPos, u16), TickKey>,
+}
+
+#[derive(Default)]
+struct ScheduledTicks {
+    chunks: HashMap<ChunkPos, ChunkTicks>,
+    // counts scheduled ticks
+    order: u64,
+}
+
+impl ScheduledTicks {
+    fn schedule(&mut self, pos: BlockPos, id: u16, tick: u64) {
+        let chunk = self.chunks.entry(ChunkPos::from_block(pos)).or_default();
+        if let Some(key) = chunk.keys.get(&(pos, id)) {
+            if key.0 <= tick {
+                return;
+            }
+            let key = *key;
+            chunk.queue.remove(&key);
+        }
+        let key = (tick, self.order);
+        self.order += 1;
+        chunk.keys.insert((pos, id), key);
+        chunk.queue.insert(key, (pos, id));
+    }
+
+    fn len(&self) -> usize {
+        return self.chunks.values().map(|chunk| chunk.keys.len()).sum();
+    }
+
+    // takes ticks due on given tick from all chunks, in order
+    fn take_due(&mut self, tick: u64, limit: usize) -> Vec<(TickKey, BlockPos, u16)> {
+        let mut due = Vec::new();
+        for chunk in self.chunks.values_mut() {
+            while let Some(entry) = chunk.queue.first_entry() {
+                if entry.key().0 > tick {
+                    break;
+                }
+                let key = *entry.key();
+                let (pos, id) = entry.remove();
+                chunk.keys.remove(&(pos, id));
+                due.push((key, pos, id));
+            }
+        }
+        due.sort_unstable_by_key(|(key, _, _)| *key);
+        // ticks over limit go back with their old keys, so they are the first ones next update
+        for (key, pos, id) in due.drain(limit.min(due.len())..) {
+            let chunk = self.chunks.entry(ChunkPos::from_block(pos)).or_default();
+            chunk.keys.insert((pos, id), key);
+            chunk.queue.insert(key, (pos, id));
+        }
+        self.chunks.retain(|_, chunk| !chunk.queue.is_empty());
+        return due;
+    }
+}
+
+// Runs block tick handlers once every game update. Scheduled ticks are kept per chunk and
+// forgotten when their chunk unloads, they are not saved with it. Random ticks pick blocks
+// in every loaded chunk, chunks without randomly ticking blocks are skipped.
+pub struct BlockTicks {
+    pub settings: TickSettings,
+    tick: u64,
+    scheduled: ScheduledTicks,
+    random: Random,
+}
+
+impl BlockTicks {
+    pub fn new(settings: TickSettings, seed: u64) -> BlockTicks {
+        return BlockTicks {
+            settings,
+            tick: 0,
+            scheduled: ScheduledTicks::default(),
+            random: Random::new(seed),
+        };
+    }
+
+    pub fn current_tick(&self) -> u64 {
+        return self.tick;
+    }
+
+    pub fn scheduled_count(&self) -> usize {
+        return self.scheduled.len();
+    }
+
+    // schedules a tick from outside of tick handlers, see TickContext::schedule
+    pub fn schedule(&mut self, pos: BlockPos, block: Block, delay: u32) {
+        self.scheduled.schedule(pos, block.id, self.tick + delay.max(1) as u64);
+    }
+
+    // lets the changed block and its neighbours react, call this for every BlockChanged
+    pub fn block_changed(&mut self, world: &mut GameWorld, pos: BlockPos) {
+        let registry = world.registry().clone();
+        for near in std::iter::once(pos.0).chain(FACE_NORMALS.iter().map(|normal| pos.0 + *normal)) {
+            let near = BlockPos(near);
+            let handler = match world.get_block(near).and_then(|block| registry.tick_handler(block)) {
+                Some(handler) => handler,
+                None => continue,
+            };
+            handler.neighbour_changed(&mut self.context(world), near, pos);
+        }
+    }
+
+    // Advances by one game update, runs scheduled ticks that are due and then random ticks.
+    // Returns how many scheduled ticks ran.
+    pub fn tick(&mut self, world: &mut GameWorld) -> usize {
+        self.tick += 1;
+        self.scheduled.chunks.retain(|pos, _| world.is_loaded(*pos));
+
+        let registry = world.registry().clone();
+        let due = self.scheduled.take_due(self.tick, self.settings.max_scheduled_per_update);
+        for (_, pos, id) in &due {
+            if let Some(handler) = registry.tick_handler(Block::new(*id)) {
+                handler.scheduled_tick(&mut self.context(world), *pos);
+            }
+        }
+
+        if self.settings.random_tick_rate > 0 {
+            let mut chunks: Vec<ChunkPos> = world.loaded_positions()
+                .filter(|pos| world.loaded_chunk(*pos).map_or(false, |chunk| {
+                    chunk.storage().palette().iter().any(|block| registry.tick_handler(*block).map_or(false, |handler| handler.random_ticks()))
+                }))
+                .collect();
+            // same seed picks the same blocks whatever order the chunks are stored in
+            chunks.sort_unstable_by_key(|pos| (pos.x, pos.y, pos.z));
+            for chunk in chunks {
+                for _ in 0..self.settings.random_tick_rate {
+                    let index = self.random.next_u64() as usize % (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE);
+                    let offset = IVec3::new((index / (CHUNK_SIZE * CHUNK_SIZE)) as i32, (index / CHUNK_SIZE % CHUNK_SIZE) as i32, (index % CHUNK_SIZE) as i32);
+                    let pos = BlockPos(chunk.block_min().0 + offset);
+                    let handler = match world.get_block(pos).and_then(|block| registry.tick_handler(block)) {
+                        Some(handler) if handler.random_ticks() => handler,
+                        _ => continue,
+                    };
+                    handler.random_tick(&mut self.context(world), pos);
+                }
+            }
+        }
+        return due.len();
+    }
+
+    fn context<'a>(&'a mut self, world: &'a mut GameWorld) -> TickContext<'a> {
+        return TickContext {
+            world,
+            random: &mut self.random,
+            scheduled: &mut self.scheduled,
+            tick: self.tick,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use crate::engine::terrarin::registry::BlockRegistry;
+    use super::*;
+
+    fn positions(due: &[(TickKey, BlockPos, u16)]) -> Vec<BlockPos> {
+        return due.iter().map(|(_, pos, _)| *pos).collect();
+    }
+
+    #[test]
+    fn due_ticks_run_by_tick_then_schedule_order() {
+        let mut scheduled = ScheduledTicks::default();
+        let (a, b, c) = (BlockPos::new(1, 2, 3), BlockPos::new(4, 5, 6), BlockPos::new(7, 8, 9));
+        // in another chunk
+        let d = BlockPos::new(-1, 2, 3);
+        scheduled.schedule(a, 1, 5);
+        scheduled.schedule(b, 1, 3);
+        scheduled.schedule(c, 1, 5);
+        scheduled.schedule(d, 1, 3);
+        assert!(scheduled.take_due(2, 100).is_empty());
+        assert_eq!(positions(&scheduled.take_due(5, 100)), vec![b, d, a, c]);
+        assert_eq!(scheduled.len(), 0);
+        assert!(scheduled.chunks.is_empty());
+    }
+
+    #[test]
+    fn block_types_are_scheduled_once_per_position() {
+        let mut scheduled = ScheduledTicks::default();
+        let pos = BlockPos::new(3, -4, 5);
+        scheduled.schedule(pos, 1, 5);
+        scheduled.schedule(pos, 1, 7);
+        assert_eq!(scheduled.len(), 1);
+        // an earlier tick replaces the later one
+        scheduled.schedule(pos, 1, 4);
+        assert_eq!(scheduled.len(), 1);
+        // other block types at the same position are separate
+        scheduled.schedule(pos, 2, 7);
+        assert_eq!(scheduled.len(), 2);
+
+        assert_eq!(scheduled.take_due(4, 100), vec![((4, 1), pos, 1)]);
+        assert!(scheduled.take_due(6, 100).is_empty());
+        assert_eq!(scheduled.take_due(7, 100), vec![((7, 2), pos, 2)]);
+        // can be scheduled again once it ran
+        scheduled.schedule(pos, 1, 9);
+        assert_eq!(scheduled.len(), 1);
+    }
+
+    #[test]
+    fn ticks_over_the_limit_run_first_next_update() {
+        let mut scheduled = ScheduledTicks::default();
+        let all: Vec<BlockPos> = (0..10).map(|i| BlockPos::new(i * 7 - 30, i, 0)).collect();
+        for pos in &all {
+            scheduled.schedule(*pos, 1, 1);
+        }
+        assert_eq!(positions(&scheduled.take_due(1, 4)), all[..4]);
+        assert_eq!(scheduled.len(), 6);
+        // requeued ticks keep their place before ticks scheduled for the next update
+        let later = BlockPos::new(100, 0, 0);
+        scheduled.schedule(later, 1, 2);
+        assert_eq!(positions(&scheduled.take_due(2, 4)), all[4..8]);
+        let mut rest = all[8..].to_vec();
+        rest.push(later);
+        assert_eq!(positions(&scheduled.take_due(2, 4)), rest);
+        assert_eq!(scheduled.len(), 0);
+    }
+
+    struct RecordRandomTicks(Mutex<Vec<BlockPos>>);
+
+    impl BlockTickHandler for RecordRandomTicks {
+        fn random_ticks(&self) -> bool {
+            return true;
+        }
+
+        fn random_tick(&self, _ticks: &mut TickContext, pos: BlockPos) {
+            self.0.lock().unwrap().push(pos);
+        }
+    }
+
+    // random ticks of grass over given number of updates, two chunks are all grass and one all air
+    fn random_ticks(seed: u64, rate: u32, updates: u32) -> Vec<BlockPos> {
+        let mut registry = BlockRegistry::load("resources/blocks.json").unwrap();
+        let grass = registry.block("grass");
+        let handler = Arc::new(RecordRandomTicks(Mutex::new(Vec::new())));
+        registry.set_tick_handler(grass, handler.clone());
+        let registry = Arc::new(registry);
+        let mut world = GameWorld::new(Box::new(FlatEarthGenerator::new(15, -100, &registry)), registry.clone());
+        for pos in [ChunkPos::new(0, 0, 0), ChunkPos::new(-1, 0, 0), ChunkPos::new(0, 1, 0)] {
+            world.chunk_at(pos);
+        }
+        let mut ticks = BlockTicks::new(TickSettings { random_tick_rate: rate, ..TickSettings::default() }, seed);
+        for _ in 0..updates {
+            ticks.tick(&mut world);
+        }
+        let picked = handler.0.lock().unwrap().clone();
+        return picked;
+    }
+
+    #[test]
+    fn random_ticks_follow_the_rate() {
+        let picked = random_ticks(9, 3, 50);
+        assert_eq!(picked.len(), 2 * 3 * 50);
+        assert!(picked.iter().all(|pos| pos.chunk() == ChunkPos::new(0, 0, 0) || pos.chunk() == ChunkPos::new(-1, 0, 0)));
+        assert_eq!(random_ticks(9, 3, 50), picked);
+        assert_ne!(random_ticks(10, 3, 50), picked);
+        assert!(random_ticks(9, 0, 50).is_empty());
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use glam::{IVec3, Vec3};
+use crate::engine::object::gameobject::Mesh;
+use crate::engine::renderer::renderer::{Vertex, VertexIndex};
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::edit::Clipboard;
+use crate::engine::terrarin::mesher::{merge_rectangles, push_box_face};
+use crate::engine::terrarin::world::GameWorld;
+
+// MagicaVoxel files, see https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+// Files are a tree of chunks with 4 letter ids, only model sizes, voxels and the palette are read.
+const VOX_MAGIC: [u8; 4] = *b"VOX ";
+const VOX_VERSIONS: [u32; 2] = [150, 200];
+// models can't be bigger in MagicaVoxel
+const MAX_MODEL_SIZE: i32 = 256;
+
+#[derive(Debug)]
+pub enum VoxError {
+    Io(std::io::Error),
+    NotAVox,
+    UnsupportedVersion(u32),
+    Corrupted,
+}
+
+impl Display for VoxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            VoxError::Io(error) => write!(f, "failed to access vox file: {}", error),
+            VoxError::NotAVox => write!(f, "data is not a vox file"),
+            VoxError::UnsupportedVersion(version) => write!(f, "vox version {} is not supported", version),
+            VoxError::Corrupted => write!(f, "vox data is corrupted"),
+        };
+    }
+}
+
+impl From<std::io::Error> for VoxError {
+    fn from(error: std::io::Error) -> VoxError {
+        return VoxError::Io(error);
+    }
+}
+
+// Colours of a vox file as RGBA, indexed by colour index of voxels. Index 0 is empty space.
+pub type VoxPalette = [[u8; 4]; 256];
+
+// Palette MagicaVoxel uses for files without their own: a 6³ cube of colours without black,
+// then ramps of red, green, blue and grey.
+pub fn default_vox_palette() -> VoxPalette {
+    let mut palette = [[0; 4]; 256];
+    let mut i = 1;
+    let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                if r == 0 && g == 0 && b == 0 {
+                    continue;
+                }
+                palette[i] = [r, g, b, 0xff];
+                i += 1;
+            }
+        }
+    }
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    for channel in [0, 1, 2, 3] {
+        for value in ramp {
+            palette[i] = if channel == 3 { [value, value, value, 0xff] } else { [0, 0, 0, 0xff] };
+            if channel < 3 {
+                palette[i][channel] = value;
+            }
+            i += 1;
+        }
+    }
+    return palette;
+}
+
+// Which block every colour index becomes when a model is placed in a world.
+pub struct VoxBlockMapping {
+    pub blocks: HashMap<u8, Block>,
+    // block of colours missing from the table, None leaves them out
+    pub fallback: Option<Block>,
+}
+
+impl VoxBlockMapping {
+    pub fn new(fallback: Option<Block>) -> VoxBlockMapping {
+        return VoxBlockMapping { blocks: HashMap::new(), fallback };
+    }
+
+    pub fn block(&self, color: u8) -> Option<Block> {
+        return self.blocks.get(&color).copied().or(self.fallback);
+    }
+}
+
+// Single model of a vox file. MagicaVoxel has z pointing up, models are turned so it is y here,
+// keeping the model the same and not mirrored: vox (x, y, z) is (x, z, size y - 1 - y).
+pub struct VoxModel {
+    size: IVec3,
+    // colour indices, x changes slowest and z fastest, 0 is empty
+    voxels: Vec<u8>,
+}
+
+impl VoxModel {
+    pub fn size(&self) -> IVec3 {
+        return self.size;
+    }
+
+    // colour index of the voxel, 0 for empty voxels and outside of the model
+    pub fn get(&self, pos: IVec3) -> u8 {
+        if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(self.size).any() {
+            return 0;
+        }
+        return self.voxels[self.index(pos)];
+    }
+
+    // voxels without a block are air
+    pub fn to_clipboard(&self, mapping: &VoxBlockMapping) -> Clipboard {
+        let blocks = self.voxels.iter().map(|color| match *color {
+            0 => Block::AIR,
+            color => mapping.block(color).unwrap_or(Block::AIR),
+        }).collect();
+        return Clipboard::from_blocks(self.size, blocks).unwrap();
+    }
+
+    // Puts the lowest corner of the model at given position. Only voxels with a block are placed
+    // and only in loaded chunks, use WorldEditor::paste with to_clipboard to be able to undo it.
+    // Returns how many blocks changed.
+    pub fn place(&self, world: &mut GameWorld, at: BlockPos, mapping: &VoxBlockMapping) -> usize {
+        let mut blocks = Vec::new();
+        for x in 0..self.size.x {
+            for y in 0..self.size.y {
+                for z in 0..self.size.z {
+                    let pos = IVec3::new(x, y, z);
+                    let block = match self.get(pos) {
+                        0 => None,
+                        color => mapping.block(color),
+                    };
+                    if let Some(block) = block {
+                        blocks.push((BlockPos(at.0 + pos), block));
+                    }
+                }
+            }
+        }
+        return world.set_blocks(blocks);
+    }
+
+    // Greedy meshed model coloured by the palette, with ambient occlusion between its voxels and
+    // full sky light. A voxel is one unit, the lowest corner of the model is at the origin.
+    // Models with more vertices than a VertexIndex can address are split into several meshes with
+    // ids counting up from first_mesh_id. An empty model is a single mesh without vertices.
+    pub fn to_meshes(&self, palette: &VoxPalette, first_mesh_id: u32) -> Vec<Mesh> {
+        let mut meshes: Vec<Mesh> = Vec::new();
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices: Vec<VertexIndex> = Vec::new();
+        for axis in 0..3 {
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+            let (width, height) = (self.size[u] as usize, self.size[v] as usize);
+            let mut mask: Vec<Option<(u8, [u8; 4])>> = vec![None; width * height];
+            for dir in [-1, 1] {
+                for slice in 0..self.size[axis] {
+                    for i in 0..width {
+                        for j in 0..height {
+                            let mut pos = IVec3::ZERO;
+                            pos[axis] = slice;
+                            pos[u] = i as i32;
+                            pos[v] = j as i32;
+                            let mut facing = pos;
+                            facing[axis] += dir;
+                            let color = self.get(pos);
+                            mask[i * height + j] = if color != 0 && self.get(facing) == 0 {
+                                Some((color, self.face_ao(facing, u, v)))
+                            } else {
+                                None
+                            };
+                        }
+                    }
+                    for ((color, ao), i, j, rect_width, rect_height) in merge_rectangles(&mut mask, width, height) {
+                        if vertices.len() + 4 > VertexIndex::MAX as usize + 1 {
+                            meshes.push(Mesh {
+                                id: first_mesh_id + meshes.len() as u32,
+                                vertices: std::mem::take(&mut vertices),
+                                indices: std::mem::take(&mut indices),
+                            });
+                        }
+                        let mut min = Vec3::ZERO;
+                        min[axis] = slice as f32;
+                        min[u] = i as f32;
+                        min[v] = j as f32;
+                        let mut max = min;
+                        max[axis] += 1.0;
+                        max[u] += rect_width as f32;
+                        max[v] += rect_height as f32;
+                        let [r, g, b, _] = palette[color as usize];
+                        let color = [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0];
+                        push_box_face(axis, dir, min, max, color, [1.0, 0.0], ao, &mut vertices, &mut indices);
+                    }
+                }
+            }
+        }
+        if !vertices.is_empty() || meshes.is_empty() {
+            meshes.push(Mesh {
+                id: first_mesh_id + meshes.len() as u32,
+                vertices,
+                indices,
+            });
+        }
+        return meshes;
+    }
+
+    // same as ambient occlusion of chunk meshes, with every voxel opaque
+    fn face_ao(&self, facing: IVec3, u: usize, v: usize) -> [u8; 4] {
+        let occludes = |du: i32, dv: i32| {
+            let mut offset = IVec3::ZERO;
+            offset[u] = du;
+            offset[v] = dv;
+            return self.get(facing + offset) != 0;
+        };
+        return [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(du, dv)| {
+            let side_u = occludes(du, 0);
+            let side_v = occludes(0, dv);
+            if side_u && side_v {
+                return 0;
+            }
+            return 3 - side_u as u8 - side_v as u8 - occludes(du, dv) as u8;
+        });
+    }
+
+    fn index(&self, pos: IVec3) -> usize {
+        return ((pos.x * self.size.y + pos.y) * self.size.z + pos.z) as usize;
+    }
+}
+
+// Models of a vox file in the order they are stored. Placement of models in the scene graph
+// of newer files is not read, each model stands on its own.
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    pub palette: VoxPalette,
+}
+
+impl VoxFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<VoxFile, VoxError> {
+        return Self::decode(&fs::read(path)?);
+    }
+
+    pub fn decode(data: &[u8]) -> Result<VoxFile, VoxError> {
+        if data.len() < 8 || data[0..4] != VOX_MAGIC {
+            return Err(VoxError::NotAVox);
+        }
+        let version = read_u32(data, 4)?;
+        if !VOX_VERSIONS.contains(&version) {
+            return Err(VoxError::UnsupportedVersion(version));
+        }
+        let (id, _, children, _) = read_chunk(data, 8)?;
+        if id != b"MAIN" {
+            return Err(VoxError::Corrupted);
+        }
+        let mut file = VoxFile { models: Vec::new(), palette: default_vox_palette() };
+        let mut size = None;
+        let mut offset = 0;
+        while offset < children.len() {
+            let (id, content, _, end) = read_chunk(children, offset)?;
+            offset = end;
+            match id {
+                b"SIZE" => size = Some(Self::read_size(content)?),
+                b"XYZI" => {
+                    let vox_size = size.take().ok_or(VoxError::Corrupted)?;
+                    file.models.push(Self::read_voxels(vox_size, content)?);
+                }
+                b"RGBA" => {
+                    if content.len() < 256 * 4 {
+                        return Err(VoxError::Corrupted);
+                    }
+                    // colour i of the chunk is for index i + 1, the last one is never used
+                    for i in 0..255 {
+                        file.palette[i + 1].copy_from_slice(&content[i * 4..i * 4 + 4]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        return Ok(file);
+    }
+
+    // size in vox axes
+    fn read_size(content: &[u8]) -> Result<IVec3, VoxError> {
+        let size = IVec3::new(read_u32(content, 0)? as i32, read_u32(content, 4)? as i32, read_u32(content, 8)? as i32);
+        if size.cmplt(IVec3::ONE).any() || size.cmpgt(IVec3::splat(MAX_MODEL_SIZE)).any() {
+            return Err(VoxError::Corrupted);
+        }
+        return Ok(size);
+    }
+
+    fn read_voxels(vox_size: IVec3, content: &[u8]) -> Result<VoxModel, VoxError> {
+        let size = IVec3::new(vox_size.x, vox_size.z, vox_size.y);
+        let mut model = VoxModel { size, voxels: vec![0; (size.x * size.y * size.z) as usize] };
+        let count = read_u32(content, 0)? as usize;
+        let voxels = content.get(4..).and_then(|voxels| voxels.get(..count.checked_mul(4)?)).ok_or(VoxError::Corrupted)?;
+        for voxel in voxels.chunks_exact(4) {
+            let vox_pos = IVec3::new(voxel[0] as i32, voxel[1] as i32, voxel[2] as i32);
+            if vox_pos.cmpge(vox_size).any() {
+                return Err(VoxError::Corrupted);
+            }
+            let pos = IVec3::new(vox_pos.x, vox_pos.z, vox_size.y - 1 - vox_pos.y);
+            let i = model.index(pos);
+            model.voxels[i] = voxel[3];
+        }
+        return Ok(model);
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, VoxError> {
+    let bytes = data.get(offset..offset + 4).ok_or(VoxError::Corrupted)?;
+    return Ok(u32::from_le_bytes(bytes.try_into().unwrap()));
+}
+
+// id, content and children of the chunk starting at offset and where the next chunk starts
+fn read_chunk(data: &[u8], offset: usize) -> Result<(&[u8], &[u8], &[u8], usize), VoxError> {
+    let id = data.get(offset..offset + 4).ok_or(VoxError::Corrupted)?;
+    let content_len = read_u32(data, offset + 4)? as usize;
+    let children_len = read_u32(data, offset + 8)? as usize;
+    let content_start = offset + 12;
+    let children_start = content_start.checked_add(content_len).ok_or(VoxError::Corrupted)?;
+    let end = children_start.checked_add(children_len).ok_or(VoxError::Corrupted)?;
+    let content = data.get(content_start..children_start).ok_or(VoxError::Corrupted)?;
+    let children = data.get(children_start..end).ok_or(VoxError::Corrupted)?;
+    return Ok((id, content, children, end));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::engine::terrarin::block::FACE_NORMALS;
+    use crate::engine::terrarin::chunk::ChunkPos;
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use crate::engine::terrarin::registry::BlockRegistry;
+    use super::*;
+
+    #[test]
+    fn default_palette_matches_magica_voxel() {
+        let palette = default_vox_palette();
+        assert_eq!(palette[0], [0, 0, 0, 0]);
+        assert_eq!(palette[1], [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(palette[2], [0xff, 0xff, 0xcc, 0xff]);
+        assert_eq!(palette[215], [0x00, 0x00, 0x33, 0xff]);
+        // ramps of red, green, blue and grey
+        assert_eq!(palette[216], [0xee, 0x00, 0x00, 0xff]);
+        assert_eq!(palette[226], [0x00, 0xee, 0x00, 0xff]);
+        assert_eq!(palette[236], [0x00, 0x00, 0xee, 0xff]);
+        assert_eq!(palette[246], [0xee, 0xee, 0xee, 0xff]);
+        assert_eq!(palette[255], [0x11, 0x11, 0x11, 0xff]);
+    }
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend((content.len() as u32).to_le_bytes());
+        data.extend((children.len() as u32).to_le_bytes());
+        data.extend(content);
+        data.extend(children);
+        return data;
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        return values.iter().flat_map(|value| value.to_le_bytes()).collect();
+    }
+
+    // file with one model, size and voxels in vox axes, voxels are x, y, z and colour index
+    fn vox_file(size: [u32; 3], voxels: &[[u8; 4]], palette: Option<&VoxPalette>) -> Vec<u8> {
+        let mut xyzi = u32s(&[voxels.len() as u32]);
+        xyzi.extend(voxels.iter().flatten());
+        let mut children = chunk(b"SIZE", &u32s(&size), &[]);
+        children.extend(chunk(b"XYZI", &xyzi, &[]));
+        if let Some(palette) = palette {
+            children.extend(chunk(b"RGBA", &palette.concat(), &[]));
+        }
+        let mut data = VOX_MAGIC.to_vec();
+        data.extend(u32s(&[150]));
+        data.extend(chunk(b"MAIN", &[], &children));
+        return data;
+    }
+
+    #[test]
+    fn z_up_models_are_turned_to_y_up() {
+        let file = VoxFile::decode(&vox_file([2, 3, 4], &[[1, 0, 0, 10], [0, 2, 3, 20], [1, 1, 2, 30]], None)).unwrap();
+        assert_eq!(file.models.len(), 1);
+        let model = &file.models[0];
+        assert_eq!(model.size(), IVec3::new(2, 4, 3));
+        assert_eq!(model.get(IVec3::new(1, 0, 2)), 10);
+        assert_eq!(model.get(IVec3::new(0, 3, 0)), 20);
+        assert_eq!(model.get(IVec3::new(1, 2, 1)), 30);
+        assert_eq!(model.voxels.iter().filter(|color| **color != 0).count(), 3);
+        assert_eq!(file.palette, default_vox_palette());
+    }
+
+    #[test]
+    fn palette_colours_are_for_the_next_index() {
+        let mut colors = [[0; 4]; 256];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = [i as u8, 255 - i as u8, 7, 255];
+        }
+        let file = VoxFile::decode(&vox_file([1, 1, 1], &[[0, 0, 0, 1]], Some(&colors))).unwrap();
+        assert_eq!(file.palette[0], [0, 0, 0, 0]);
+        for i in 0..255 {
+            assert_eq!(file.palette[i + 1], colors[i]);
+        }
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        assert!(matches!(VoxFile::decode(&vox_file([2, 2, 2], &[[0, 2, 0, 1]], None)), Err(VoxError::Corrupted)));
+        assert!(matches!(VoxFile::decode(&vox_file([0, 2, 2], &[], None)), Err(VoxError::Corrupted)));
+        assert!(matches!(VoxFile::decode(&vox_file([257, 2, 2], &[], None)), Err(VoxError::Corrupted)));
+
+        let valid = vox_file([2, 2, 2], &[[0, 1, 0, 1], [1, 1, 1, 2]], Some(&default_vox_palette()));
+        assert!(VoxFile::decode(&valid).is_ok());
+        for len in 0..valid.len() {
+            match VoxFile::decode(&valid[..len]) {
+                Err(VoxError::NotAVox) => assert!(len < 8),
+                Err(VoxError::Corrupted) => assert!(len >= 8),
+                _ => panic!("file cut to {} bytes is not rejected", len),
+            }
+        }
+        // more voxels than the chunk holds
+        let mut count = valid.clone();
+        let xyzi = valid.windows(4).position(|id| id == b"XYZI").unwrap();
+        count[xyzi + 12..xyzi + 16].copy_from_slice(&3u32.to_le_bytes());
+        assert!(matches!(VoxFile::decode(&count), Err(VoxError::Corrupted)));
+
+        let mut version = valid.clone();
+        version[4..8].copy_from_slice(&151u32.to_le_bytes());
+        assert!(matches!(VoxFile::decode(&version), Err(VoxError::UnsupportedVersion(151))));
+        let mut magic = valid;
+        magic[0..4].copy_from_slice(b"VOY ");
+        assert!(matches!(VoxFile::decode(&magic), Err(VoxError::NotAVox)));
+    }
+
+    #[test]
+    fn colours_are_turned_into_mapped_blocks() {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        let stone = registry.block("stone");
+        let sand = registry.block("sand");
+        let file = VoxFile::decode(&vox_file([3, 1, 1], &[[0, 0, 0, 5], [1, 0, 0, 6]], None)).unwrap();
+        let model = &file.models[0];
+
+        let mut mapping = VoxBlockMapping::new(None);
+        mapping.blocks.insert(5, stone);
+        assert_eq!(model.to_clipboard(&mapping).blocks(), &[stone, Block::AIR, Block::AIR]);
+        mapping.fallback = Some(sand);
+        let clipboard = model.to_clipboard(&mapping);
+        assert_eq!(clipboard.size(), IVec3::new(3, 1, 1));
+        assert_eq!(clipboard.blocks(), &[stone, sand, Block::AIR]);
+
+        let mut world = GameWorld::new(Box::new(FlatEarthGenerator::new(-100, -100, &registry)), registry.clone());
+        world.chunk_at(ChunkPos::new(-1, 0, 0));
+        assert_eq!(model.place(&mut world, BlockPos::new(-2, 4, 0), &mapping), 2);
+        assert_eq!(world.get_block(BlockPos::new(-2, 4, 0)), Some(stone));
+        assert_eq!(world.get_block(BlockPos::new(-1, 4, 0)), Some(sand));
+        // colours without a block are left out
+        mapping.fallback = None;
+        assert_eq!(model.place(&mut world, BlockPos::new(-2, 5, 0), &mapping), 1);
+        assert_eq!(world.get_block(BlockPos::new(-2, 5, 0)), Some(stone));
+        assert_eq!(world.get_block(BlockPos::new(-1, 5, 0)), Some(Block::AIR));
+    }
+
+    #[test]
+    fn single_voxel_is_six_unoccluded_quads() {
+        let file = VoxFile::decode(&vox_file([1, 1, 1], &[[0, 0, 0, 1]], None)).unwrap();
+        let meshes = file.models[0].to_meshes(&file.palette, 3);
+        assert_eq!(meshes.len(), 1);
+        let mesh = &meshes[0];
+        assert_eq!(mesh.id, 3);
+        assert_eq!(mesh.vertices.len(), 24);
+        assert_eq!(mesh.indices.len(), 36);
+        let [r, g, b, _] = file.palette[1];
+        let color = [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0];
+        assert!(mesh.vertices.iter().all(|vertex| vertex.ao == 1.0 && vertex.color == color));
+        for normal in FACE_NORMALS {
+            let normal = normal.as_vec3().to_array();
+            assert_eq!(mesh.vertices.iter().filter(|vertex| vertex.normal == normal).count(), 4);
+        }
+    }
+
+    #[test]
+    fn large_models_are_split_into_several_meshes() {
+        // no two voxels share a side, so every voxel has six quads of its own
+        let mut voxels = Vec::new();
+        for x in 0..20u8 {
+            for y in 0..20u8 {
+                for z in 0..20u8 {
+                    if (x + y + z) % 2 == 0 {
+                        voxels.push([x, y, z, 1]);
+                    }
+                }
+            }
+        }
+        let file = VoxFile::decode(&vox_file([20, 20, 20], &voxels, None)).unwrap();
+        let meshes = file.models[0].to_meshes(&file.palette, 10);
+        assert_eq!(meshes.iter().map(|mesh| mesh.id).collect::<Vec<_>>(), vec![10, 11]);
+        assert_eq!(meshes[0].vertices.len(), VertexIndex::MAX as usize + 1);
+        assert_eq!(meshes.iter().map(|mesh| mesh.vertices.len()).sum::<usize>(), voxels.len() * 24);
+        for mesh in &meshes {
+            assert_eq!(mesh.indices.len() * 4, mesh.vertices.len() * 6);
+            assert!(mesh.indices.iter().all(|index| (*index as usize) < mesh.vertices.len()));
+        }
+
+        let empty = VoxFile::decode(&vox_file([2, 2, 2], &[], None)).unwrap();
+        let meshes = empty.models[0].to_meshes(&empty.palette, 1);
+        assert_eq!(meshes.len(), 1);
+        assert!(meshes[0].vertices.is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use crate::engine::terrarin::chunk::ChunkPos;
+
+// one thread per core, leaving one for the main thread
+pub fn default_worker_threads() -> usize {
+    let cores = thread::available_parallelism().map(|cores| cores.get()).unwrap_or(2);
+    return (cores - 1).max(1);
+}
+
+struct JobQueue<J> {
+    jobs: VecDeque<(ChunkPos, J)>,
+    closed: bool,
+}
+
+struct Shared<J> {
+    queue: Mutex<JobQueue<J>>,
+    available: Condvar,
+}
+
+// Runs jobs for chunks on background threads and sends results back through a channel.
+// Jobs start in the order they were submitted, but results can arrive in any order.
+pub struct WorkerPool<J: Send + 'static, R: Send + 'static> {
+    shared: Arc<Shared<J>>,
+    results: Receiver<(ChunkPos, R)>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<J: Send + 'static, R: Send + 'static> WorkerPool<J, R> {
+    pub fn new<F>(name: &str, threads: usize, work: F) -> WorkerPool<J, R>
+        where F: Fn(ChunkPos, J) -> R + Send + Sync + 'static
+    {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(JobQueue { jobs: VecDeque::new(), closed: false }),
+            available: Condvar::new(),
+        });
+        let work = Arc::new(work);
+        let (sender, results) = channel();
+        let threads = (0..threads.max(1))
+            .map(|i| {
+                let shared = shared.clone();
+                let work = work.clone();
+                let sender = sender.clone();
+                thread::Builder::new()
+                    .name(format!("{} {}", name, i))
+                    .spawn(move || Self::run(&shared, work.as_ref(), &sender))
+                    .unwrap()
+            })
+            .collect();
+        return WorkerPool { shared, results, threads };
+    }
+
+    // replaces job for the same chunk if it didn't start yet
+    pub fn submit(&self, pos: ChunkPos, job: J) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        match queue.jobs.iter_mut().find(|(queued, _)| *queued == pos) {
+            Some(queued) => queued.1 = job,
+            None => queue.jobs.push_back((pos, job)),
+        }
+        self.shared.available.notify_one();
+    }
+
+    // Removes job that didn't start yet and returns true if there was one.
+    // Result of a job that is already running still arrives and should be ignored by the caller.
+    pub fn cancel(&self, pos: ChunkPos) -> bool {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let before = queue.jobs.len();
+        queue.jobs.retain(|(queued, _)| *queued != pos);
+        return queue.jobs.len() != before;
+    }
+
+    pub fn queued(&self) -> usize {
+        return self.shared.queue.lock().unwrap().jobs.len();
+    }
+
+    pub fn try_receive(&self) -> Option<(ChunkPos, R)> {
+        return self.results.try_recv().ok();
+    }
+
+    // blocks until some job is done
+    pub fn receive(&self) -> (ChunkPos, R) {
+        // workers only stop when the pool is dropped, so the channel can't be closed here
+        return self.results.recv().unwrap();
+    }
+
+    fn run<F: Fn(ChunkPos, J) -> R>(shared: &Shared<J>, work: &F, results: &Sender<(ChunkPos, R)>) {
+        loop {
+            let (pos, job) = {
+                let mut queue = shared.queue.lock().unwrap();
+                loop {
+                    if queue.closed {
+                        return;
+                    }
+                    if let Some(job) = queue.jobs.pop_front() {
+                        break job;
+                    }
+                    queue = shared.available.wait(queue).unwrap();
+                }
+            };
+            if results.send((pos, work(pos, job))).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl<J: Send + 'static, R: Send + 'static> Drop for WorkerPool<J, R> {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.available.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,27 +1,713 @@
-use std::cell::{Ref, RefCell};
-use std::collections::HashMap;
+use std::cell::{OnceCell, Ref, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
+use std::sync::Arc;
+use glam::{IVec3, Vec3};
 use crate::ChunkGenerator;
-use crate::engine::terrarin::chunk::{Chunk, ChunkPos};
+use crate::engine::object::transform::Pos;
+use crate::engine::terrarin::biome::Biome;
+use crate::engine::terrarin::block::{Block, BlockPos};
+use crate::engine::terrarin::block_region::{BlockAabb, BlockRegion};
+use crate::engine::terrarin::chunk::{Chunk, ChunkPos, CHUNK_SIZE_I};
+use crate::engine::terrarin::features::{FeatureDecorator, FeatureOwners, FeatureWrite};
+use crate::engine::terrarin::light::{LightEngine, LightKind};
+use crate::engine::terrarin::neighbourhood::ChunkNeighbourhood;
+use crate::engine::terrarin::raycast::{raycast, RaycastHit};
+use crate::engine::terrarin::region::{RegionError, RegionStorage, SavedChunk};
+use crate::engine::terrarin::registry::BlockRegistry;
+use crate::engine::terrarin::workers::WorkerPool;
+
+// Sent for every block changed by set_block, set_blocks or set_block_state.
+// Blocks placed while chunks are generated don't send events.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BlockChanged {
+    pub pos: BlockPos,
+    pub old: Block,
+    pub new: Block,
+}
+
+// Chunk together with blocks its features place into it and its neighbours.
+struct GeneratedChunk {
+    chunk: Chunk,
+    features: Vec<FeatureWrite>,
+}
 
 pub struct GameWorld {
-    generator: Box<dyn ChunkGenerator>,
+    generator: Arc<dyn ChunkGenerator>,
+    registry: Arc<BlockRegistry>,
     chunks: HashMap<ChunkPos, RefCell<Chunk>>,
+    // chunks whose mesh is missing or outdated because they or their neighbours were loaded,
+    // or their light changed
+    dirty_meshes: HashSet<ChunkPos>,
+    decorator: Option<Arc<FeatureDecorator>>,
+    // Feature blocks waiting for their chunk to be generated or loaded. They are saved when the
+    // world is saved, and owners of feature blocks are saved with their chunks, so features keep
+    // the same priorities after chunks are unloaded.
+    pending_features: HashMap<ChunkPos, Vec<FeatureWrite>>,
+    feature_owners: HashMap<ChunkPos, FeatureOwners>,
+    storage: Option<RegionStorage>,
+    // changes waiting to be sent as events
+    block_changes: Vec<BlockChanged>,
+    workers: Option<WorkerPool<(), GeneratedChunk>>,
+    // chunks being generated by workers
+    requested: HashSet<ChunkPos>,
 }
 
 impl GameWorld {
-    pub fn new(generator: Box<dyn ChunkGenerator>) -> GameWorld {
+    pub fn new(generator: Box<dyn ChunkGenerator>, registry: Arc<BlockRegistry>) -> GameWorld {
         return GameWorld {
-            generator,
+            generator: Arc::from(generator),
+            registry,
             chunks: HashMap::new(),
+            dirty_meshes: HashSet::new(),
+            decorator: None,
+            pending_features: HashMap::new(),
+            feature_owners: HashMap::new(),
+            storage: None,
+            block_changes: Vec::new(),
+            workers: None,
+            requested: HashSet::new(),
         };
     }
 
+    pub fn with_features(generator: Box<dyn ChunkGenerator>, registry: Arc<BlockRegistry>, decorator: FeatureDecorator) -> GameWorld {
+        let mut world = Self::new(generator, registry);
+        world.decorator = Some(Arc::new(decorator));
+        return world;
+    }
+
+    // Chunks are loaded from storage when it has them and generated otherwise.
+    // Chunks are only saved when they are unloaded or the whole world is saved.
+    pub fn set_storage(&mut self, storage: RegionStorage) {
+        self.storage = Some(storage);
+    }
+
+    // Lets request_chunk generate chunks on background threads. Without workers
+    // requested chunks are generated right away.
+    pub fn start_workers(&mut self, threads: usize) {
+        let generator = self.generator.clone();
+        let decorator = self.decorator.clone();
+        self.workers = Some(WorkerPool::new("chunk generator", threads, move |pos, ()| {
+            return Self::generate(generator.as_ref(), decorator.as_deref(), pos);
+        }));
+    }
+
+    // loads or generates chunk on this thread if it is not loaded yet
     pub fn chunk_at(&mut self, pos: ChunkPos) -> Ref<Chunk> {
         if !self.chunks.contains_key(&pos) {
-            let generated = self.generator.generate_chunk(self, pos);
-            self.chunks.insert(pos, RefCell::new(generated));
+            self.cancel_chunk(pos);
+            match self.load_saved(pos) {
+                Some(saved) => self.insert_loaded(saved),
+                None => {
+                    let generated = Self::generate(self.generator.as_ref(), self.decorator.as_deref(), pos);
+                    self.insert_generated(generated);
+                }
+            }
         }
         return self.chunks[&pos].borrow();
     }
-}
\ No newline at end of file
+
+    // Saved chunks are loaded right away, other chunks are generated by workers and
+    // inserted by receive_chunks.
+    pub fn request_chunk(&mut self, pos: ChunkPos) {
+        if self.chunks.contains_key(&pos) || self.requested.contains(&pos) {
+            return;
+        }
+        if let Some(saved) = self.load_saved(pos) {
+            self.insert_loaded(saved);
+            return;
+        }
+        match &self.workers {
+            Some(workers) => {
+                workers.submit(pos, ());
+                self.requested.insert(pos);
+            }
+            None => {
+                self.chunk_at(pos);
+            }
+        }
+    }
+
+    // returns false if chunk was not requested
+    pub fn cancel_chunk(&mut self, pos: ChunkPos) -> bool {
+        if !self.requested.remove(&pos) {
+            return false;
+        }
+        if let Some(workers) = &self.workers {
+            workers.cancel(pos);
+        }
+        return true;
+    }
+
+    pub fn is_requested(&self, pos: ChunkPos) -> bool {
+        return self.requested.contains(&pos);
+    }
+
+    pub fn requested_count(&self) -> usize {
+        return self.requested.len();
+    }
+
+    // Inserts at most limit chunks finished by workers, returns how many were inserted.
+    pub fn receive_chunks(&mut self, limit: usize) -> usize {
+        let mut received = 0;
+        while received < limit {
+            let finished = match &self.workers {
+                Some(workers) => workers.try_receive(),
+                None => None,
+            };
+            let (pos, generated) = match finished {
+                Some(finished) => finished,
+                None => break,
+            };
+            if self.insert_requested(pos, generated) {
+                received += 1;
+            }
+        }
+        return received;
+    }
+
+    // requests chunks that are not loaded yet and blocks until all of them are loaded
+    pub fn wait_for_chunks(&mut self, positions: &[ChunkPos]) {
+        for pos in positions {
+            self.request_chunk(*pos);
+        }
+        while positions.iter().any(|pos| !self.chunks.contains_key(pos)) {
+            let (pos, generated) = match &self.workers {
+                Some(workers) => workers.receive(),
+                None => return,
+            };
+            self.insert_requested(pos, generated);
+        }
+    }
+
+    fn generate(generator: &dyn ChunkGenerator, decorator: Option<&FeatureDecorator>, pos: ChunkPos) -> GeneratedChunk {
+        let chunk = generator.generate_chunk(pos);
+        let features = match decorator {
+            Some(decorator) => {
+                // chunk above is only generated when ground in the top layer needs it
+                let above = OnceCell::new();
+                let block_above = |x: i32, z: i32| above
+                    .get_or_init(|| generator.generate_chunk(ChunkPos(pos.0 + IVec3::Y)))
+                    .get(x as usize, 0, z as usize);
+                decorator.decorate(&chunk, &|block| generator.biome_at(block), &block_above)
+            }
+            None => Vec::new(),
+        };
+        return GeneratedChunk { chunk, features };
+    }
+
+    // results of cancelled requests are thrown away
+    fn insert_requested(&mut self, pos: ChunkPos, generated: GeneratedChunk) -> bool {
+        if !self.requested.remove(&pos) || self.chunks.contains_key(&pos) {
+            return false;
+        }
+        self.insert_generated(generated);
+        return true;
+    }
+
+    // Returns the saved chunk, feature blocks saved for it are queued even if it was never generated.
+    // Chunks that fail to load are generated again, they overwrite broken data once saved.
+    fn load_saved(&mut self, pos: ChunkPos) -> Option<Chunk> {
+        let saved = self.read_saved(pos)?;
+        if !saved.pending_features.is_empty() {
+            let pending = self.pending_features.entry(pos).or_default();
+            for write in saved.pending_features {
+                if !pending.contains(&write) {
+                    pending.push(write);
+                }
+            }
+        }
+        let chunk = saved.chunk?;
+        if !saved.feature_owners.is_empty() {
+            self.feature_owners.insert(pos, saved.feature_owners);
+        }
+        return Some(chunk);
+    }
+
+    fn read_saved(&mut self, pos: ChunkPos) -> Option<SavedChunk> {
+        let storage = self.storage.as_mut()?;
+        return match storage.load_chunk(pos) {
+            Ok(saved) => saved,
+            Err(error) => {
+                println!("Failed to load chunk: {}", error);
+                None
+            }
+        };
+    }
+
+    // Calls f with the chunk as it is, or as it would be loaded, without keeping it in memory.
+    // Chunks that are not loaded or saved are generated every time, they only get features rooted
+    // in them and ones queued by loaded neighbours, not the ones of neighbours that aren't loaded.
+    pub fn peek_chunk<R, F: FnOnce(&Chunk) -> R>(&mut self, pos: ChunkPos, f: F) -> R {
+        if let Some(chunk) = self.chunks.get(&pos) {
+            return f(&chunk.borrow());
+        }
+        let saved = self.read_saved(pos).unwrap_or_default();
+        let (mut chunk, features) = match saved.chunk {
+            Some(chunk) => (chunk, Vec::new()),
+            None => {
+                let GeneratedChunk { chunk, features } = Self::generate(self.generator.as_ref(), self.decorator.as_deref(), pos);
+                (chunk, features)
+            }
+        };
+        if let Some(decorator) = &self.decorator {
+            let mut owners = saved.feature_owners;
+            let pending = self.pending_features.get(&pos).into_iter().flatten().chain(saved.pending_features.iter());
+            for write in pending.chain(features.iter()).filter(|write| write.pos.chunk() == pos) {
+                decorator.apply(&mut chunk, &mut owners, write);
+            }
+        }
+        return f(&chunk);
+    }
+
+    // Saved chunks already contain their own features, but features of neighbours generated
+    // while they were unloaded still have to be applied.
+    fn insert_loaded(&mut self, chunk: Chunk) {
+        let pos = chunk.get_position();
+        self.chunks.insert(pos, RefCell::new(chunk));
+        self.mark_mesh_dirty(pos);
+        if let Some(pending) = self.pending_features.remove(&pos) {
+            for write in pending {
+                self.apply_feature_write(&write);
+            }
+        }
+        self.light_chunk(pos, &[]);
+    }
+
+    // Blocks of features that belong to chunks which are not generated yet are queued
+    // and applied when these chunks are generated.
+    fn insert_generated(&mut self, generated: GeneratedChunk) {
+        let GeneratedChunk { chunk, features } = generated;
+        let pos = chunk.get_position();
+        self.chunks.insert(pos, RefCell::new(chunk));
+        self.mark_mesh_dirty(pos);
+        if let Some(pending) = self.pending_features.remove(&pos) {
+            for write in pending {
+                self.apply_feature_write(&write);
+            }
+        }
+        // neighbours were lit before features changed their blocks
+        let mut relight = Vec::new();
+        for write in features {
+            if self.apply_feature_write(&write) && write.pos.chunk() != pos {
+                relight.push(write.pos);
+            }
+        }
+        self.light_chunk(pos, &relight);
+    }
+
+    fn light_chunk(&mut self, pos: ChunkPos, relight: &[BlockPos]) {
+        let mut engine = LightEngine::new(&self.chunks, &self.registry);
+        engine.light_chunk(pos);
+        if !relight.is_empty() {
+            engine.update_blocks(relight);
+        }
+        let changed = engine.changed();
+        self.dirty_meshes.extend(changed);
+    }
+
+    fn update_light(&mut self, positions: &[BlockPos]) {
+        let mut engine = LightEngine::new(&self.chunks, &self.registry);
+        engine.update_blocks(positions);
+        let changed = engine.changed();
+        self.dirty_meshes.extend(changed);
+    }
+
+    // returns true if a block of a loaded chunk changed
+    fn apply_feature_write(&mut self, write: &FeatureWrite) -> bool {
+        let decorator = match &self.decorator {
+            Some(decorator) => decorator,
+            None => return false,
+        };
+        let target = write.pos.chunk();
+        let changed = match self.chunks.get(&target) {
+            Some(chunk) => {
+                let owners = self.feature_owners.entry(target).or_default();
+                decorator.apply(&mut chunk.borrow_mut(), owners, write)
+            }
+            None => {
+                self.pending_features.entry(target).or_default().push(*write);
+                false
+            }
+        };
+        if changed {
+            self.mark_mesh_dirty(target);
+        }
+        return changed;
+    }
+
+    // Saves chunk if it changed and forgets it, returns false if it was not loaded.
+    // Chunk stays loaded when saving fails. Without storage changes of the chunk are lost.
+    pub fn unload_chunk(&mut self, pos: ChunkPos) -> Result<bool, RegionError> {
+        if !self.chunks.contains_key(&pos) {
+            return Ok(false);
+        }
+        self.save_chunk(pos)?;
+        self.chunks.remove(&pos);
+        self.feature_owners.remove(&pos);
+        self.dirty_meshes.remove(&pos);
+        // border faces of neighbours are not hidden anymore
+        for neighbour in pos.surrounding() {
+            if self.chunks.contains_key(&neighbour) {
+                self.dirty_meshes.insert(neighbour);
+            }
+        }
+        return Ok(true);
+    }
+
+    // Saves every loaded chunk that changed since it was loaded or saved, and feature blocks
+    // waiting for chunks that are not loaded. Blocks waiting for chunks being generated by
+    // workers are kept until these chunks are inserted.
+    pub fn save(&mut self) -> Result<(), RegionError> {
+        let positions: Vec<ChunkPos> = self.chunks.keys().copied().collect();
+        for pos in positions {
+            self.save_chunk(pos)?;
+        }
+        let storage = match &mut self.storage {
+            Some(storage) => storage,
+            None => return Ok(()),
+        };
+        let pending: Vec<ChunkPos> = self.pending_features.keys()
+            .filter(|pos| !self.requested.contains(pos))
+            .copied()
+            .collect();
+        for pos in pending {
+            storage.save_pending_features(pos, &self.pending_features[&pos])?;
+            self.pending_features.remove(&pos);
+        }
+        return Ok(());
+    }
+
+    fn save_chunk(&mut self, pos: ChunkPos) -> Result<(), RegionError> {
+        let storage = match &mut self.storage {
+            Some(storage) => storage,
+            None => return Ok(()),
+        };
+        if let Some(chunk) = self.chunks.get(&pos) {
+            let mut chunk = chunk.borrow_mut();
+            if chunk.is_dirty() {
+                let no_owners = FeatureOwners::new();
+                storage.save_chunk(&chunk, self.feature_owners.get(&pos).unwrap_or(&no_owners))?;
+                chunk.clear_dirty();
+            }
+        }
+        return Ok(());
+    }
+
+    // does not generate anything, returns None if chunk is not loaded yet
+    pub fn loaded_chunk(&self, pos: ChunkPos) -> Option<Ref<Chunk>> {
+        return self.chunks.get(&pos).map(|chunk| chunk.borrow());
+    }
+
+    // blocks of loaded chunks inside of the box, nothing is generated
+    pub fn region(&self, aabb: BlockAabb) -> BlockRegion<'_> {
+        return BlockRegion::new(self, aabb);
+    }
+
+    pub fn registry(&self) -> &Arc<BlockRegistry> {
+        return &self.registry;
+    }
+
+    // None if chunk is not loaded or block has no such property
+    pub fn block_state(&self, pos: BlockPos, property: &str) -> Option<u16> {
+        let block = self.loaded_chunk(pos.chunk())?.get_block(pos);
+        return self.registry.get_state(block, property);
+    }
+
+    // returns false if chunk is not loaded, block has no such property or value is out of range
+    pub fn set_block_state(&mut self, pos: BlockPos, property: &str, value: u16) -> bool {
+        let block = match self.get_block(pos) {
+            Some(block) => block,
+            None => return false,
+        };
+        return match self.registry.with_state(block, property, value) {
+            Some(block) => {
+                self.set_block(pos, block);
+                true
+            }
+            None => false,
+        };
+    }
+
+    // None if chunk is not loaded
+    pub fn get_light(&self, pos: BlockPos, kind: LightKind) -> Option<u8> {
+        return self.loaded_chunk(pos.chunk()).map(|chunk| chunk.get_light(pos, kind));
+    }
+
+    // None if chunk is not loaded
+    pub fn get_block(&self, pos: BlockPos) -> Option<Block> {
+        return self.loaded_chunk(pos.chunk()).map(|chunk| chunk.get_block(pos));
+    }
+
+    // returns false if chunk is not loaded or block was already there
+    pub fn set_block(&mut self, pos: BlockPos, block: Block) -> bool {
+        let old = match self.chunks.get(&pos.chunk()) {
+            Some(chunk) => chunk.borrow_mut().set_block(pos, block),
+            None => return false,
+        };
+        if old == block {
+            return false;
+        }
+        self.update_light(&[pos]);
+        self.block_changed(BlockChanged { pos, old, new: block });
+        return true;
+    }
+
+    // Sets many blocks borrowing every chunk only once. Blocks in chunks that are not loaded
+    // are skipped, returns how many blocks changed.
+    pub fn set_blocks<I: IntoIterator<Item = (BlockPos, Block)>>(&mut self, blocks: I) -> usize {
+        let mut by_chunk: HashMap<ChunkPos, Vec<(BlockPos, Block)>> = HashMap::new();
+        for (pos, block) in blocks {
+            by_chunk.entry(pos.chunk()).or_default().push((pos, block));
+        }
+        let mut changes = Vec::new();
+        for (chunk_pos, blocks) in by_chunk {
+            let mut chunk = match self.chunks.get(&chunk_pos) {
+                Some(chunk) => chunk.borrow_mut(),
+                None => continue,
+            };
+            for (pos, block) in blocks {
+                let old = chunk.set_block(pos, block);
+                if old != block {
+                    changes.push(BlockChanged { pos, old, new: block });
+                }
+            }
+        }
+        let changed = changes.len();
+        let positions: Vec<BlockPos> = changes.iter().map(|change| change.pos).collect();
+        self.update_light(&positions);
+        for change in changes {
+            self.block_changed(change);
+        }
+        return changed;
+    }
+
+    // first block along the ray that passes filter, for example |block| !block.is_air()
+    pub fn raycast<F: Fn(Block) -> bool>(&self, origin: Pos, dir: Vec3, max_dist: f32, filter: F) -> Option<RaycastHit> {
+        return raycast(self, origin, dir, max_dist, filter);
+    }
+
+    // Changes since last call, main loop sends them as bevy events.
+    pub fn take_block_changes(&mut self) -> Vec<BlockChanged> {
+        return std::mem::take(&mut self.block_changes);
+    }
+
+    fn block_changed(&mut self, change: BlockChanged) {
+        let chunk_pos = change.pos.chunk();
+        self.dirty_meshes.insert(chunk_pos);
+        // neighbours only need new meshes if the block is in their padding, on the border they share
+        let local = change.pos.chunk_relative().0;
+        for neighbour in chunk_pos.surrounding() {
+            let offset = neighbour.0 - chunk_pos.0;
+            let touches = (0..3).all(|axis| match offset[axis] {
+                -1 => local[axis] == 0,
+                1 => local[axis] == CHUNK_SIZE_I - 1,
+                _ => true,
+            });
+            if touches && self.chunks.contains_key(&neighbour) {
+                self.dirty_meshes.insert(neighbour);
+            }
+        }
+        self.block_changes.push(change);
+    }
+
+    // biome is decided by the generator, so it is known even for chunks that are not loaded
+    pub fn biome_at(&self, pos: BlockPos) -> Biome {
+        return self.generator.biome_at(pos);
+    }
+
+    pub fn grass_color_at(&self, pos: BlockPos) -> [f32; 3] {
+        return self.generator.grass_color(pos);
+    }
+
+    pub fn is_loaded(&self, pos: ChunkPos) -> bool {
+        return self.chunks.contains_key(&pos);
+    }
+
+    pub fn loaded_positions(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        return self.chunks.keys().copied();
+    }
+
+    // loaded chunk together with border blocks of its loaded neighbours
+    pub fn neighbourhood(&self, pos: ChunkPos) -> Option<ChunkNeighbourhood> {
+        let chunk = self.loaded_chunk(pos)?;
+        let neighbours: Vec<Ref<Chunk>> = pos.surrounding().iter().filter_map(|neighbour| self.loaded_chunk(*neighbour)).collect();
+        let neighbour_refs: Vec<&Chunk> = neighbours.iter().map(|neighbour| neighbour.deref()).collect();
+        let mut neighbourhood = ChunkNeighbourhood::new(chunk.deref(), &neighbour_refs);
+        neighbourhood.set_grass_colors(|column| self.generator.grass_color(column));
+        return Some(neighbourhood);
+    }
+
+    // Returns loaded chunks that need to be meshed again and forgets about them.
+    // Loading a chunk marks its loaded neighbours too, as their border faces might be hidden now.
+    pub fn take_dirty_meshes(&mut self) -> Vec<ChunkPos> {
+        return self.dirty_meshes.drain()
+            .filter(|pos| self.chunks.contains_key(pos))
+            .collect();
+    }
+
+    // corners of neighbours are shaded by blocks of this chunk, so the diagonal ones are marked too
+    fn mark_mesh_dirty(&mut self, pos: ChunkPos) {
+        self.dirty_meshes.insert(pos);
+        for neighbour in pos.surrounding() {
+            if self.chunks.contains_key(&neighbour) {
+                self.dirty_meshes.insert(neighbour);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use crate::engine::terrarin::chunk_generator::FlatEarthGenerator;
+    use crate::engine::terrarin::features::Feature;
+    use crate::engine::terrarin::terrain_generator::{NoiseTerrainGenerator, TerrainSettings};
+    use super::*;
+
+    fn world(seed: u64) -> GameWorld {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        let generator = NoiseTerrainGenerator::with_biomes(TerrainSettings::new(seed, &registry), &registry);
+        let decorator = FeatureDecorator::new(seed, vec![Feature::tree(&registry), Feature::boulder(&registry)]);
+        return GameWorld::with_features(Box::new(generator), registry, decorator);
+    }
+
+    fn positions() -> Vec<ChunkPos> {
+        let mut positions = Vec::new();
+        for x in -2..=1 {
+            for y in -1..=1 {
+                for z in -2..=1 {
+                    positions.push(ChunkPos::new(x, y, z));
+                }
+            }
+        }
+        return positions;
+    }
+
+    fn hashes(world: &GameWorld) -> HashMap<ChunkPos, u64> {
+        return world.loaded_positions()
+            .map(|pos| {
+                let mut hasher = DefaultHasher::new();
+                world.loaded_chunk(pos).unwrap().hash(&mut hasher);
+                (pos, hasher.finish())
+            })
+            .collect();
+    }
+
+    // features reaching into neighbours must give the same chunks whatever order chunks are generated in
+    #[test]
+    fn generation_is_independent_of_order() {
+        let mut forward = world(7);
+        for pos in positions() {
+            forward.chunk_at(pos);
+        }
+        let mut backward = world(7);
+        for pos in positions().into_iter().rev() {
+            backward.chunk_at(pos);
+        }
+        let mut threaded = world(7);
+        threaded.start_workers(4);
+        threaded.wait_for_chunks(&positions());
+
+        let expected = hashes(&forward);
+        assert_eq!(expected.len(), positions().len());
+        assert_eq!(hashes(&backward), expected);
+        assert_eq!(hashes(&threaded), expected);
+    }
+
+    // features crossing into chunks that are not loaded must come out the same after saving,
+    // unloading and loading these chunks again
+    #[test]
+    fn features_survive_saving_and_unloading() {
+        let directory = std::env::temp_dir().join(format!("world-test-features-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let mut reference = world(7);
+        for pos in positions() {
+            reference.chunk_at(pos);
+        }
+        let expected = hashes(&reference);
+        let (first, second): (Vec<ChunkPos>, Vec<ChunkPos>) = positions().into_iter().partition(|pos| (pos.x + pos.y + pos.z).rem_euclid(2) == 0);
+
+        let mut saved = world(7);
+        saved.set_storage(RegionStorage::open(directory.join("saved"), saved.registry().clone()).unwrap());
+        for pos in &first {
+            saved.chunk_at(*pos);
+        }
+        saved.save().unwrap();
+        let mut reopened = world(7);
+        reopened.set_storage(RegionStorage::open(directory.join("saved"), reopened.registry().clone()).unwrap());
+        for pos in positions() {
+            reopened.chunk_at(pos);
+        }
+        assert_eq!(hashes(&reopened), expected);
+
+        let mut unloaded = world(7);
+        unloaded.set_storage(RegionStorage::open(directory.join("unloaded"), unloaded.registry().clone()).unwrap());
+        for pos in &first {
+            unloaded.chunk_at(*pos);
+        }
+        for pos in &first {
+            assert!(unloaded.unload_chunk(*pos).unwrap());
+        }
+        unloaded.save().unwrap();
+        for pos in second.iter().chain(first.iter()) {
+            unloaded.chunk_at(*pos);
+        }
+        assert_eq!(hashes(&unloaded), expected);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    // a chunk loaded again must still know which feature blocks have higher priority
+    #[test]
+    fn feature_priorities_survive_unloading() {
+        let directory = std::env::temp_dir().join(format!("world-test-priorities-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        let mut tree = Feature::tree(&registry);
+        tree.rules.attempts_per_chunk = 0;
+        let generator = crate::engine::terrarin::chunk_generator::FlatEarthGenerator::new(7, 5, &registry);
+        let mut world = GameWorld::with_features(Box::new(generator), registry.clone(), FeatureDecorator::new(7, vec![tree]));
+        world.set_storage(RegionStorage::open(&directory, registry.clone()).unwrap());
+        let pos = BlockPos::new(-3, 20, 4);
+        let leaves = registry.block("leaves");
+        let log = registry.block("log");
+        let write = |block: Block, priority: u64| FeatureWrite { pos, block, priority, feature: 0 };
+
+        world.chunk_at(pos.chunk());
+        assert!(world.apply_feature_write(&write(leaves, 10)));
+        assert!(world.unload_chunk(pos.chunk()).unwrap());
+        // queued while the chunk is not loaded
+        assert!(!world.apply_feature_write(&write(log, 5)));
+        world.chunk_at(pos.chunk());
+        assert_eq!(world.get_block(pos), Some(leaves));
+        assert!(world.apply_feature_write(&write(log, 20)));
+        assert_eq!(world.get_block(pos), Some(log));
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    // ground in the top layer of a chunk is covered by the chunk above
+    #[test]
+    fn features_grow_on_the_top_layer_of_a_chunk() {
+        let registry = Arc::new(BlockRegistry::load("resources/blocks.json").unwrap());
+        let log = registry.block("log");
+        let stone = registry.block("stone");
+        let mut tree = Feature::tree(&registry);
+        tree.rules.use_biome_density = false;
+        tree.rules.chance = 1.0;
+        let decorator = FeatureDecorator::new(3, vec![tree.clone()]);
+        let mut world = GameWorld::with_features(Box::new(FlatEarthGenerator::new(15, 10, &registry)), registry.clone(), decorator);
+        for pos in [ChunkPos::new(0, 0, 0), ChunkPos::new(0, 1, 0)] {
+            world.chunk_at(pos);
+        }
+        let trunks = BlockAabb::new(BlockPos::new(0, 16, 0), BlockPos::new(15, 16, 15)).iter().filter(|pos| world.get_block(*pos) == Some(log)).count();
+        assert!(trunks > 0);
+
+        // nothing grows under a ceiling of the chunk above
+        let decorator = FeatureDecorator::new(3, vec![tree]);
+        let chunk = FlatEarthGenerator::new(15, 10, &registry).generate_chunk(ChunkPos::new(0, 0, 0));
+        assert!(!decorator.decorate(&chunk, &|_| Biome::Plains, &|_, _| Block::AIR).is_empty());
+        assert!(decorator.decorate(&chunk, &|_| Biome::Plains, &|_, _| stone).is_empty());
+    }
+}
+
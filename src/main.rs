@@ -1,11 +1,13 @@
 #![windows_subsystem = "windows"]
 
 use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
 use std::f32::consts::PI;
 use std::ops::{Add, Deref, Mul};
 use std::sync::Arc;
 use std::thread::spawn;
 use bevy_ecs::prelude::*;
+use bevy_ecs::event::Events;
 use bevy_ecs::system::SystemState;
 use bevy_ecs::world::World;
 use easy_gltf::Model;
@@ -26,14 +28,124 @@ use crate::engine::object::transform::{Pos, Transform};
 use crate::engine::renderer::graphic_object::GraphicObjectDesc;
 use crate::engine::renderer::options::GraphicOptions;
 use crate::engine::renderer::renderer::{GraphicEngine, Renderer, Vertex};
+use crate::engine::terrarin::carver::{CarvingGenerator, NoiseCaveCarver, NoiseCaveSettings, TunnelCarver, TunnelSettings};
 use crate::engine::terrarin::chunk::{CHUNK_SIZE, ChunkPos};
 use crate::engine::terrarin::chunk_generator::{ChunkGenerator, FlatEarthGenerator};
-use crate::engine::terrarin::world::GameWorld;
+use crate::engine::terrarin::features::{Feature, FeatureDecorator};
+use crate::engine::terrarin::fluids::register_fluids;
+use crate::engine::terrarin::mesher::ChunkMesher;
+use crate::engine::terrarin::region::RegionStorage;
+use crate::engine::terrarin::neighbourhood::ChunkNeighbourhood;
+use crate::engine::terrarin::streaming::{ChunkStreamer, StreamingSettings};
+use crate::engine::terrarin::workers::{default_worker_threads, WorkerPool};
+use crate::engine::terrarin::registry::BlockRegistry;
+use crate::engine::terrarin::terrain_generator::{NoiseTerrainGenerator, TerrainSettings};
+use crate::engine::terrarin::ticks::{BlockTicks, TickSettings};
+use crate::engine::terrarin::world::{BlockChanged, GameWorld};
 use crate::input::{ASCEND, ROTATE};
 
 mod engine;
 mod game_loop;
 
+// Chunk meshes that are currently rendered, so they can be replaced or removed.
+// Meshes are built by workers, old mesh of a chunk stays until the new one is done.
+struct ChunkMeshes {
+    // neighbourhood, level of detail, levels of its neighbours and mesh id
+    workers: WorkerPool<(ChunkNeighbourhood, u32, [u32; 6], u32), Mesh>,
+    entities: HashMap<ChunkPos, Entity>,
+    // id of the newest mesh requested for each chunk, older meshes that finish later are ignored
+    pending: HashMap<ChunkPos, u32>,
+    next_mesh_id: u32,
+}
+
+impl ChunkMeshes {
+    fn remove(&mut self, pos: ChunkPos, commands: &mut Commands, render_ids: &Query<&RenderId>, renderer: &mut GraphicEngine) {
+        if let Some(entity) = self.entities.remove(&pos) {
+            if let Ok(render_id) = render_ids.get(entity) {
+                renderer.remove_graphic_object(render_id.id);
+            }
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Terrain systems run in this order every frame. Block changes made while streaming are sent
+// before tick_blocks reads them, and events are only updated once every reader has seen them.
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+enum TerrainSystem {
+    StreamChunks,
+    SendBlockChanges,
+    TickBlocks,
+    UpdateBlockEvents,
+}
+
+#[profiling::function]
+fn stream_chunks(
+    mut commands: Commands,
+    cameras: Query<&Transform, With<Camera>>,
+    render_ids: Query<&RenderId>,
+    mut streamer: NonSendMut<ChunkStreamer>,
+    mut game_world: NonSendMut<GameWorld>,
+    mut chunk_meshes: NonSendMut<ChunkMeshes>,
+    mut renderer: NonSendMut<GraphicEngine>,
+) {
+    let center = match cameras.iter().next() {
+        Some(transform) => transform.position().chunk(),
+        None => return,
+    };
+    let update = streamer.update(&mut game_world, center);
+    for pos in &update.unloaded {
+        chunk_meshes.workers.cancel(*pos);
+        chunk_meshes.pending.remove(pos);
+        chunk_meshes.remove(*pos, &mut commands, &render_ids, &mut renderer);
+    }
+    for pos in update.meshes {
+        if let Some(neighbourhood) = game_world.neighbourhood(pos) {
+            chunk_meshes.next_mesh_id += 1;
+            let mesh_id = chunk_meshes.next_mesh_id;
+            chunk_meshes.pending.insert(pos, mesh_id);
+            chunk_meshes.workers.submit(pos, (neighbourhood, streamer.lod(pos), streamer.neighbour_lods(pos), mesh_id));
+        }
+    }
+
+    let material = renderer.materials.borrow().get_default();
+    while let Some((pos, mesh)) = chunk_meshes.workers.try_receive() {
+        if chunk_meshes.pending.get(&pos) != Some(&mesh.id) {
+            continue;
+        }
+        chunk_meshes.pending.remove(&pos);
+        chunk_meshes.remove(pos, &mut commands, &render_ids, &mut renderer);
+        if mesh.indices.is_empty() {
+            continue;
+        }
+        let transform = Transform::at(pos.world_min());
+        let id = renderer.create_graphic_object(GraphicObjectDesc {
+            transform,
+            mesh: mesh.clone(),
+            material,
+        });
+        let entity = commands.spawn()
+            .insert(transform)
+            .insert(mesh)
+            .insert(RenderId { id })
+            .id();
+        chunk_meshes.entities.insert(pos, entity);
+    }
+}
+
+fn send_block_changes(mut game_world: NonSendMut<GameWorld>, mut events: EventWriter<BlockChanged>) {
+    events.send_batch(game_world.take_block_changes().into_iter());
+}
+
+// runs once every game update, so blocks tick at the same speed no matter the frame rate
+#[profiling::function]
+fn tick_blocks(mut ticks: ResMut<BlockTicks>, mut game_world: NonSendMut<GameWorld>, mut changes: EventReader<BlockChanged>) {
+    for change in changes.iter() {
+        ticks.block_changed(&mut game_world, change.pos);
+    }
+    ticks.tick(&mut game_world);
+}
+
 #[profiling::function]
 fn update_camera(mut query: Query<(&mut Camera, &Transform)>, mut renderer: NonSendMut<GraphicEngine>) {
     for (mut camera, transform) in query.iter_mut() {
@@ -159,37 +271,34 @@ fn main() {
     //     }
     // }
 
-    let scenes = easy_gltf::load("resources/cube.glb").unwrap();
-    let cube_model = &scenes[0].models[0];
-    let cube_mesh_grass = model_to_mesh(cube_model, 2);
-    let cube_mesh_stone = model_to_mesh(cube_model, 1);
-    let generator = FlatEarthGenerator {
-        grass_level: 7,
-        stone_level: 5
-    };
-    let mut game_world = GameWorld::new(Box::new(generator));
-    let chunk = game_world.chunk_at(ChunkPos::new(0,0,0));
-
-    let pos = chunk.get_position().world_min();
-    for x in 0..CHUNK_SIZE {
-        for y in 0..CHUNK_SIZE {
-            for z in 0..CHUNK_SIZE {
-                let block = chunk[x][y][z];
-                if block.id == 0 {
-                    continue
-                }
-                let mesh = if block.id == 1 { cube_mesh_stone.clone() } else { cube_mesh_grass.clone() };
-                world.spawn()
-                    .insert(Transform::new(
-                        pos.add(Vec3::new(x as f32, y as f32, z as f32)).into(),
-                        Quat::IDENTITY,
-                        Vec3::ONE,
-                    ))
-                    .insert(mesh)
-                    .insert(RenderId { id: 0 });
-            }
-        }
-    }
+    let mut registry = BlockRegistry::load("resources/blocks.json").unwrap();
+    register_fluids(&mut registry);
+    let registry = Arc::new(registry);
+    let seed = 1337;
+    let generator = CarvingGenerator::new(
+        Box::new(NoiseTerrainGenerator::with_biomes(TerrainSettings::new(seed, &registry), &registry)),
+        vec![
+            Box::new(NoiseCaveCarver::new(NoiseCaveSettings::new(seed, &registry))),
+            Box::new(TunnelCarver::new(TunnelSettings::worms(seed, &registry))),
+            Box::new(TunnelCarver::new(TunnelSettings::ravines(seed, &registry))),
+        ],
+    );
+    let decorator = FeatureDecorator::new(seed, vec![Feature::tree(&registry), Feature::boulder(&registry)]);
+    let mut game_world = GameWorld::with_features(Box::new(generator), registry.clone(), decorator);
+    game_world.set_storage(RegionStorage::open("saves/world", registry.clone()).unwrap());
+    game_world.start_workers(default_worker_threads());
+    world.insert_non_send_resource(ChunkStreamer::new(StreamingSettings::default()));
+    world.insert_resource(Events::<BlockChanged>::default());
+    world.insert_resource(BlockTicks::new(TickSettings::default(), seed));
+    let mesher = ChunkMesher::new(registry.clone());
+    world.insert_non_send_resource(ChunkMeshes {
+        workers: WorkerPool::new("chunk mesher", default_worker_threads(), move |_, (neighbourhood, lod, neighbour_lods, mesh_id)| {
+            return mesher.build_lod(&neighbourhood, lod, neighbour_lods, mesh_id);
+        }),
+        entities: HashMap::new(),
+        pending: HashMap::new(),
+        next_mesh_id: 0,
+    });
     // // let chunk = chunk_ref.deref();
     // for (pos, block) in chunk_ref.into_iter() {
     //     world.spawn()
@@ -220,7 +329,11 @@ fn main() {
 
     scheduler.add_stage("basic_stage", SystemStage::single_threaded()
         .with_system(update_camera)
-        .with_system(update_input),
+        .with_system(update_input)
+        .with_system(stream_chunks.label(TerrainSystem::StreamChunks))
+        .with_system(send_block_changes.label(TerrainSystem::SendBlockChanges).after(TerrainSystem::StreamChunks))
+        .with_system(tick_blocks.label(TerrainSystem::TickBlocks).after(TerrainSystem::SendBlockChanges))
+        .with_system(Events::<BlockChanged>::update_system.label(TerrainSystem::UpdateBlockEvents).after(TerrainSystem::TickBlocks)),
     );
 
 
@@ -238,6 +351,7 @@ fn main() {
     }
 
     world.insert_non_send_resource(renderer);
+    world.insert_non_send_resource(game_world);
 
     profiling::scope!("loaded");
     game_loop(event_loop, window, world, 144, 0.5, move |g| {
@@ -260,6 +374,10 @@ fn main() {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
+                let mut game_world: Mut<GameWorld> = g.game.get_non_send_resource_mut().unwrap();
+                if let Err(error) = game_world.save() {
+                    println!("Failed to save world: {}", error);
+                }
                 g.exit_next_iteration = true;
             }
             Event::WindowEvent {
@@ -277,7 +395,7 @@ fn main() {
 fn model_to_mesh(model: &Model, id: u32) -> Mesh {
     return Mesh {
         id: id,
-        vertices: model.vertices().iter().map(|x| Vertex { position: x.position.into(), normal: x.normal.into() }).collect::<Vec<_>>(),
+        vertices: model.vertices().iter().map(|x| Vertex { position: x.position.into(), normal: x.normal.into(), color: [0.8, 0.8, 0.8], light: [1.0, 0.0], ao: 1.0 }).collect::<Vec<_>>(),
         indices: model.indices().unwrap().iter().map(|x| *x as u16).collect::<Vec<_>>(),
     }
 }